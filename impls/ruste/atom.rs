@@ -0,0 +1,153 @@
+use crate::{environment::Environment, types::MalType};
+use anyhow::Result;
+use std::{cell::RefCell, fmt::Debug, rc::Rc};
+
+struct State {
+    value: MalType,
+    /// Bumped on every change, so `swap!` can tell whether the value it
+    /// started from is still current without comparing values.
+    version: u64,
+    watches: Vec<(MalType, MalType)>,
+    validator: Option<MalType>,
+}
+
+/// A mutable reference to a value. Every change goes through the validator,
+/// if there is one, and is then reported to each watch.
+#[derive(Clone)]
+pub struct Atom(Rc<RefCell<State>>);
+
+impl Atom {
+    pub fn new(value: MalType) -> Self {
+        Self(Rc::new(RefCell::new(State {
+            value,
+            version: 0,
+            watches: vec![],
+            validator: None,
+        })))
+    }
+
+    pub fn ptr_eq(&self, other: &Atom) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub fn deref(&self) -> MalType {
+        self.0.borrow().value.clone()
+    }
+
+    pub fn reset(&self, value: MalType, env: &Environment) -> Result<MalType> {
+        self.validate(&value, env)?;
+        let old = {
+            let mut state = self.0.borrow_mut();
+            state.version += 1;
+            std::mem::replace(&mut state.value, value.clone())
+        };
+        self.notify(old, value.clone(), env)?;
+        Ok(value)
+    }
+
+    /// Applies `f` to the current value and `args`. Should `f` itself change
+    /// the atom, the result is stale and `f` runs again on the new value.
+    pub fn swap(&self, f: &MalType, args: &[MalType], env: &Environment) -> Result<MalType> {
+        loop {
+            let (old, version) = {
+                let state = self.0.borrow();
+                (state.value.clone(), state.version)
+            };
+            let mut f_args = vec![old];
+            f_args.extend(args.iter().cloned());
+            let new = f.clone().eval(&f_args, env)?;
+            if self.0.borrow().version == version {
+                return self.reset(new, env);
+            }
+        }
+    }
+
+    /// Sets the value to `new` if it's still `old`, which compares lazy seqs
+    /// by identity rather than walking them.
+    pub fn compare_and_set(&self, old: &MalType, new: MalType, env: &Environment) -> Result<bool> {
+        if !identical(&self.deref(), old) {
+            return Ok(false);
+        }
+        self.reset(new, env)?;
+        Ok(true)
+    }
+
+    /// Adds a watch under `key`, replacing any watch already using it.
+    pub fn add_watch(&self, key: MalType, f: MalType) {
+        let mut state = self.0.borrow_mut();
+        state.watches.retain(|(k, _)| k != &key);
+        state.watches.push((key, f));
+    }
+
+    pub fn remove_watch(&self, key: &MalType) {
+        self.0.borrow_mut().watches.retain(|(k, _)| k != key);
+    }
+
+    /// Sets the validator, which the current value has to pass as well.
+    /// A nil validator removes it.
+    pub fn set_validator(&self, validator: MalType, env: &Environment) -> Result<()> {
+        let validator = match validator {
+            MalType::Nil => None,
+            validator => Some(validator),
+        };
+        let previous = std::mem::replace(&mut self.0.borrow_mut().validator, validator);
+        if let Err(err) = self.validate(&self.deref(), env) {
+            self.0.borrow_mut().validator = previous;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    pub fn validator(&self) -> MalType {
+        self.0.borrow().validator.clone().unwrap_or(MalType::Nil)
+    }
+
+    fn validate(&self, value: &MalType, env: &Environment) -> Result<()> {
+        let validator = self.0.borrow().validator.clone();
+        match validator {
+            Some(validator)
+                if !validator
+                    .clone()
+                    .eval(std::slice::from_ref(value), env)?
+                    .truthy() =>
+            {
+                anyhow::bail!("Invalid reference state {:b}", value)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn notify(&self, old: MalType, new: MalType, env: &Environment) -> Result<()> {
+        // Watches may add or remove watches, so they run from a copy
+        let watches = self.0.borrow().watches.clone();
+        for (key, f) in watches {
+            f.eval(
+                &[key, MalType::Atom(self.clone()), old.clone(), new.clone()],
+                env,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Equality that takes a lazy seq to be only itself, so a possibly infinite
+/// one is never realized. Collections compare item by item.
+fn identical(a: &MalType, b: &MalType) -> bool {
+    match (a, b) {
+        (MalType::Lazy(a), MalType::Lazy(b)) => a.ptr_eq(b),
+        (MalType::Lazy(_), _) | (_, MalType::Lazy(_)) => false,
+        (MalType::List(a, _), MalType::List(b, _))
+        | (MalType::Vector(a, _), MalType::Vector(b, _))
+        | (MalType::HashMap(a, _), MalType::HashMap(b, _))
+        | (MalType::Set(a, _), MalType::Set(b, _)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| identical(a, b))
+        }
+        (a, b) => a == b,
+    }
+}
+
+impl Debug for Atom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Atom").field(&self.0.borrow().value).finish()
+    }
+}
@@ -0,0 +1,85 @@
+//! How long resolving a name takes, run with `cargo bench`: a scope keyed by
+//! strings against one keyed by interned symbols, then through the evaluator
+//! a local bound a few scopes out, and a core function, which misses every
+//! scope first. Last a whole program, where every call resolves its head.
+
+use ruste::{
+    eval::eval,
+    intern::{Sym, SymMap},
+    reader::{Lexer, Parser},
+    Interpreter, MalType,
+};
+use std::{collections::HashMap, hint::black_box, time::Instant};
+
+const LOOKUPS: u32 = 1_000_000;
+
+fn read(input: &str) -> MalType {
+    Parser::new(Lexer::tokenize(input))
+        .parse()
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap()
+}
+
+/// Nanoseconds per call of `f`.
+fn time(mut f: impl FnMut()) -> f64 {
+    let start = Instant::now();
+    for _ in 0..LOOKUPS {
+        f();
+    }
+    start.elapsed().as_nanos() as f64 / LOOKUPS as f64
+}
+
+fn main() {
+    let names: Vec<String> = (0..32).map(|i| format!("name-{}", i)).collect();
+    let by_string: HashMap<String, MalType> = names
+        .iter()
+        .map(|name| (name.clone(), MalType::Nil))
+        .collect();
+    let by_sym: SymMap<MalType> = names
+        .iter()
+        .map(|name| (Sym::new(name), MalType::Nil))
+        .collect();
+    let (name, sym) = (names[17].as_str(), Sym::new(&names[17]));
+    println!(
+        "map String {:>8.1} ns",
+        time(|| {
+            black_box(by_string.get(black_box(name)));
+        })
+    );
+    println!(
+        "map Sym    {:>8.1} ns",
+        time(|| {
+            black_box(by_sym.get(black_box(&sym)));
+        })
+    );
+
+    let interpreter = Interpreter::new();
+    let mut env = interpreter.environment().current_ns();
+    for scope in 0..4 {
+        env.enter();
+        for i in 0..8 {
+            env.bind(&read(&format!("x{}{}", scope, i)), MalType::Number(i))
+                .unwrap();
+        }
+    }
+
+    for name in ["x37", "x05", "+"] {
+        let symbol = read(name);
+        let ns = time(|| {
+            black_box(eval(black_box(&symbol), &mut env).unwrap());
+        });
+        println!("lookup {:<4} {:>8.1} ns", name, ns);
+    }
+
+    interpreter
+        .eval_str("(defn fib [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))")
+        .unwrap();
+    let start = Instant::now();
+    interpreter.eval_str("(fib 22)").unwrap();
+    println!(
+        "(fib 22)    {:>8.1} ms",
+        start.elapsed().as_secs_f64() * 1000.0
+    );
+}
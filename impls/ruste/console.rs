@@ -31,7 +31,7 @@ impl Console {
             return None;
         }
 
-        if buffer.len() > 0 {
+        if !buffer.is_empty() {
             Some(buffer)
         } else {
             None
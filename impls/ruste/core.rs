@@ -1,73 +1,1555 @@
 use anyhow::Result;
 use log::{debug, trace};
-use std::{collections::HashMap, rc::Rc};
+use regex::Captures;
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::HashSet,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use crate::{
+    atom::Atom,
+    edn::TagReaders,
     environment::Environment,
-    types::{MalExpr, MalFn, MalLibFn, MalType},
+    intern::{Sym, SymMap, AMP},
+    lazy::{self, LazySeq, SeqIter},
+    multimethod::MultiFn,
+    native::{arg_error, check_arity, mismatch, FromArg, Kind},
+    native_fn,
+    protocol::Protocol,
+    reader::{Lexer, Parser},
+    types::{
+        hash_map_get, hash_map_insert, lookup, MalArity, MalExpr, MalFn, MalLibFn, MalRegex,
+        MalType, Meta, NativeFn, Recur, Tail,
+    },
 };
+use Core::{BinOp, Bind, LibFn};
 
-pub fn add_functions(hm: &mut HashMap<String, MalType>) {
+/// Every core binding as `(name, arglists, doc, kind)`.
+#[rustfmt::skip]
+const CORE: &[(&str, &str, &str, Core)] = &[
     // Keywords
-    make_bind(hm, "def!", def);
-    make_bind(hm, "let*", _let);
-    make_bind(hm, "do", _do);
-    make_bind(hm, "if", _if);
-    make_bind(hm, "fn*", _fn);
-
+    ("def!", "[name value] [name doc value]", "Binds name to value in the current namespace. A docstring, and any metadata on name, are added to the value's metadata.", Bind(def)),
+    ("let*", "[[bindings*] body]", "Evaluates body with each binding pattern bound to the value of its form, in order.", Bind(_let)),
+    ("do", "[forms*]", "Evaluates the forms in order, returning the value of the last one.", Bind(_do)),
+    ("if", "[test then] [test then else]", "Evaluates then if test is truthy and else otherwise, which defaults to nil.", Bind(_if)),
+    ("fn*", "[name? [params*] body*] [name? ([params*] body*) ...]", "Makes a fn with one or more arities. A named fn can call itself by its name.", Bind(_fn)),
+    ("defn", "[name doc? attr-map? [params*] body*] [name doc? attr-map? ([params*] body*) ...]", "Defines name as a fn, with the docstring and arglists in its metadata.", Bind(defn)),
+    ("lazy-seq", "[body]", "A sequence that evaluates body the first time it is walked.", Bind(lazy_seq)),
+    ("loop*", "[[bindings*] body*]", "Like let*, but recur in tail position jumps back to the top with new bindings.", Bind(_loop)),
+    ("recur", "[exprs*]", "Rebinds the enclosing loop* or fn arity to exprs and evaluates it again.", Bind(recur)),
+    ("ns", "[name (:require specs*) ...]", "Switches to namespace name, creating it if need be, and requires the specs from it.", Bind(ns)),
+    ("require", "[specs*]", "Loads each namespace unless it already is, given as a name or as [name :as alias :refer [names*]].", Bind(require)),
+    ("refer", "[ns] [ns names]", "Makes names from ns, or all of them, resolvable without qualification.", Bind(refer)),
+    ("defprotocol", "[name (method [this params*] doc?) ...]", "Defines a protocol and a fn per method, dispatching on the type of the first argument.", Bind(defprotocol)),
+    ("extend-type", "[type protocol (method [this params*] body*) ...]", "Implements the methods of each protocol for type.", Bind(extend_type)),
+    ("extend-protocol", "[protocol type (method [this params*] body*) ...]", "Implements the methods of protocol for each type.", Bind(extend_protocol)),
+    ("defrecord", "[name [fields*]]", "Defines a record type with the constructors ->name and map->name and the predicate name?.", Bind(defrecord)),
+    ("defmulti", "[name doc? dispatch-fn]", "Defines a multimethod, calling the method registered for what dispatch-fn returns.", Bind(defmulti)),
+    ("defmethod", "[multi dispatch-value [params*] body*]", "Adds a method to multi for dispatch-value.", Bind(defmethod)),
+    ("doc", "[name]", "Prints the arglists and docstring of whatever name is bound to.", Bind(doc)),
+    ("source", "[name]", "Prints the form a user fn was defined with.", Bind(source)),
+    ("trace", "[name]", "Prints each call of the fn bound to name, and what it returns.", Bind(trace)),
+    ("untrace", "[name]", "Stops tracing the fn bound to name.", Bind(untrace)),
+    ("break", "[name]", "Stops in the debugger whenever the fn bound to name is called.", Bind(set_breakpoint)),
+    ("unbreak", "[name]", "Removes the breakpoint on the fn bound to name.", Bind(clear_breakpoint)),
+    ("profile", "[expr] [file expr]", "Evaluates expr and prints the calls it made, with their time and allocations. Given a file, also writes the folded stacks there for flamegraph tools.", Bind(profile)),
     // core functions
-    make_fn(hm, "pr-str", pr_str);
-    make_fn(hm, "str", str);
-    make_fn(hm, "prn", prn);
-    make_fn(hm, "println", println);
+    ("pr-str", "[xs*]", "The readable forms of xs as one string, separated by spaces.", LibFn(pr_str)),
+    ("str", "[xs*]", "The printed forms of xs concatenated into one string.", LibFn(str)),
+    ("subs", "[s start] [s start end]", "The characters of s from index start up to but not including end, or to the end of s.", LibFn(subs)),
+    ("prn", "[x]", "Prints the readable form of x followed by a newline.", LibFn(prn)),
+    ("println", "[xs*]", "Prints xs separated by spaces, followed by a newline.", LibFn(println)),
+    ("list", "[items*]", "A list of items.", LibFn(list)),
+    ("list?", "[x]", "Whether x is a list.", LibFn(is_list)),
+    ("empty?", "[coll]", "Whether coll has no items.", LibFn(any)),
+    ("count", "[coll]", "The number of items in coll.", LibFn(count)),
+    ("cons", "[x seq]", "A sequence of x followed by the items of seq.", LibFn(cons)),
+    ("first", "[coll]", "The first item of coll, or nil.", LibFn(first)),
+    ("rest", "[coll]", "The items of coll after the first, as a possibly empty sequence.", LibFn(rest)),
+    ("not", "[x]", "Whether x is nil or false.", LibFn(not)),
+    ("even?", "[n]", "Whether n is even.", LibFn(is_even)),
+    ("odd?", "[n]", "Whether n is odd.", LibFn(is_odd)),
+    // Lazy sequences
+    ("range", "[] [end] [start end] [start end step]", "A lazy sequence of numbers from start, by step, up to but not including end. Without end it never stops.", LibFn(range)),
+    ("iterate", "[f x]", "The lazy sequence x, (f x), (f (f x)) and so on.", LibFn(iterate)),
+    ("repeat", "[x] [n x]", "A lazy sequence of x, n times or forever.", LibFn(repeat)),
+    ("cycle", "[coll]", "The items of coll repeated forever, lazily.", LibFn(cycle)),
+    ("map", "[f colls+]", "Lazily applies f to the first items of each coll, then the second ones, until one runs out.", LibFn(map)),
+    ("filter", "[pred coll]", "The items of coll for which pred is truthy, lazily.", LibFn(filter)),
+    ("take", "[n coll]", "The first n items of coll, lazily.", LibFn(take)),
+    ("drop", "[n coll]", "The items of coll after the first n.", LibFn(drop)),
+    ("take-while", "[pred coll]", "The items of coll up to the first for which pred is falsy, lazily.", LibFn(take_while)),
+    // Sequences
+    ("remove", "[pred coll]", "The items of coll for which pred is falsy, lazily.", LibFn(remove)),
+    ("reduce", "[f coll] [f init coll]", "Folds coll with f, starting from init or else the first item.", LibFn(reduce)),
+    ("sort", "[coll] [comparator coll]", "The items of coll in ascending order, or as comparator orders them.", LibFn(sort)),
+    ("sort-by", "[keyfn coll] [keyfn comparator coll]", "The items of coll ordered by (keyfn item).", LibFn(sort_by)),
+    ("group-by", "[f coll]", "A map from each (f item) to the vector of items giving it.", LibFn(group_by)),
+    ("frequencies", "[coll]", "A map from each distinct item of coll to the number of times it occurs.", LibFn(frequencies)),
+    ("partition", "[n coll] [n step coll] [n step pad coll]", "Lazy lists of n items each, starting step items apart. The last one is filled up from pad if given, and dropped if short.", LibFn(partition)),
+    ("interleave", "[colls*]", "The first item of each coll, then the second of each and so on, lazily.", LibFn(interleave)),
+    ("distinct", "[coll]", "The items of coll without repeats, lazily.", LibFn(distinct)),
+    ("reverse", "[coll]", "The items of coll in reverse order.", LibFn(reverse)),
+    ("some", "[pred coll]", "The first truthy (pred item) for the items of coll, or nil.", LibFn(some)),
+    ("every?", "[pred coll]", "Whether pred is truthy for every item of coll.", LibFn(every)),
+    ("zipmap", "[keys vals]", "A map pairing up keys with vals.", LibFn(zipmap)),
+    // Maps
+    ("get", "[coll key] [coll key default]", "The value for key in a map, record or vector, or default, which is nil unless given.", LibFn(get)),
+    ("assoc", "[coll key value & kvs]", "coll with key set to value, for each pair given.", LibFn(assoc)),
+    ("dissoc", "[coll keys*]", "coll without keys.", LibFn(dissoc)),
+    // Sets
+    ("set", "[coll]", "A set of the distinct items of coll.", LibFn(set)),
+    ("set?", "[x]", "Whether x is a set.", LibFn(is_set)),
+    ("conj", "[coll xs*]", "coll with xs added where the type of coll adds them.", LibFn(conj)),
+    ("disj", "[set xs*]", "set without xs.", LibFn(disj)),
+    ("contains?", "[coll key]", "Whether coll has key, or index key for vectors.", LibFn(contains)),
+    ("union", "[sets*]", "The items in any of the sets.", LibFn(union)),
+    ("intersection", "[set sets*]", "The items in every one of the sets.", LibFn(intersection)),
+    ("difference", "[set sets*]", "The items of set not in any of the others.", LibFn(difference)),
+    // Regex
+    ("re-pattern", "[s]", "Compiles s into a regex.", LibFn(re_pattern)),
+    ("re-find", "[re s]", "The first match of re in s, with its groups as a vector if it has any.", LibFn(re_find)),
+    ("re-matches", "[re s]", "The match of re against the whole of s, or nil.", LibFn(re_matches)),
+    ("re-seq", "[re s]", "All matches of re in s.", LibFn(re_seq)),
+    ("re-replace", "[re s replacement]", "s with every match of re replaced by the string replacement, or by what the fn replacement returns for the match.", LibFn(re_replace)),
+    // JSON
+    ("json-parse", "[s] [s opts]", "Reads the JSON in s. With {:keywords true} object keys become keywords, which are never freed.", LibFn(json_parse)),
+    ("json-stringify", "[x] [x opts]", "The JSON for x, indented with {:pretty true}.", LibFn(json_stringify)),
+    // EDN
+    ("read-edn", "[s] [s opts]", "Reads the first EDN value in s, using :readers from opts for tagged literals. Its keywords and symbols are never freed.", LibFn(read_edn)),
+    ("pr-edn", "[x]", "The EDN for x.", LibFn(pr_edn)),
+    // Protocols
+    ("satisfies?", "[protocol x]", "Whether protocol is implemented for the type of x.", LibFn(satisfies)),
+    // Metadata
+    ("meta", "[x]", "The metadata of x, or nil.", LibFn(meta)),
+    ("with-meta", "[x meta]", "x with its metadata replaced by the map meta.", LibFn(with_meta)),
+    ("vary-meta", "[x f args*]", "x with its metadata replaced by (f meta args*).", LibFn(vary_meta)),
+    // Atoms
+    ("atom", "[x & {:keys [validator]}]", "A new atom holding x.", LibFn(atom)),
+    ("atom?", "[x]", "Whether x is an atom.", LibFn(is_atom)),
+    ("deref", "[atom]", "The value of atom, also read as @atom.", LibFn(deref)),
+    ("reset!", "[atom x]", "Sets the value of atom to x, returning x.", LibFn(reset)),
+    ("swap!", "[atom f args*]", "Sets the value of atom to (f value args*), returning the new value.", LibFn(swap)),
+    ("compare-and-set!", "[atom old new]", "Sets the value of atom to new if it equals old, returning whether it did.", LibFn(compare_and_set)),
+    ("add-watch", "[atom key f]", "Calls (f key atom old new) on every change of atom, replacing the watch under key.", LibFn(add_watch)),
+    ("remove-watch", "[atom key]", "Removes the watch under key.", LibFn(remove_watch)),
+    ("set-validator!", "[atom f]", "Makes every new value of atom pass f, or removes the validator if f is nil.", LibFn(set_validator)),
+    ("get-validator", "[atom]", "The validator of atom, or nil.", LibFn(get_validator)),
+    // Multimethods
+    ("remove-method", "[multi dispatch-value]", "Removes the method of multi for dispatch-value.", LibFn(remove_method)),
+    ("methods", "[multi]", "A map of the methods of multi by dispatch value.", LibFn(methods)),
+    ("derive", "[child parent]", "Makes child a kind of parent for isa? and multimethod dispatch.", LibFn(derive)),
+    ("underive", "[child parent]", "Undoes (derive child parent).", LibFn(underive)),
+    ("isa?", "[child parent]", "Whether child equals or derives from parent. Vectors compare item by item.", LibFn(isa)),
+    ("ancestors", "[tag]", "The set of everything tag derives from, directly or not.", LibFn(ancestors)),
+    // Docs
+    ("apropos", "[str-or-regex]", "Sorted names, resolvable from here, that contain str or match regex.", LibFn(apropos)),
+    // Namespaces
+    ("load-file", "[path]", "Evaluates the forms in the file at path, returning the value of the last one.", LibFn(load_file)),
+    ("load-path", "[]", "The directories require looks for namespaces in.", LibFn(load_path)),
+    ("set-load-path!", "[dirs]", "Sets the directories require looks for namespaces in.", LibFn(set_load_path)),
+    // Arithmic
+    ("+", "[x y]", "The sum of x and y.", BinOp(|val1, val2| val1 + val2)),
+    ("-", "[x y]", "x minus y.", BinOp(|val1, val2| val1 - val2)),
+    ("*", "[x y]", "The product of x and y.", BinOp(|val1, val2| val1 * val2)),
+    ("/", "[x y]", "x divided by y.", BinOp(|val1, val2| val1 / val2)),
+    // Cmp
+    ("=", "[x y]", "Whether x equals y.", LibFn(equals)),
+    ("<", "[x y]", "Whether x is less than y.", BinOp(|val1, val2| Ok(MalType::Bool(val1.num_cmp(val2, "<")?.is_some_and(Ordering::is_lt))))),
+    ("<=", "[x y]", "Whether x is less than or equal to y.", BinOp(|val1, val2| Ok(MalType::Bool(val1.num_cmp(val2, "<=")?.is_some_and(Ordering::is_le))))),
+    (">", "[x y]", "Whether x is greater than y.", BinOp(|val1, val2| Ok(MalType::Bool(val1.num_cmp(val2, ">")?.is_some_and(Ordering::is_gt))))),
+    (">=", "[x y]", "Whether x is greater than or equal to y.", BinOp(|val1, val2| Ok(MalType::Bool(val1.num_cmp(val2, ">=")?.is_some_and(Ordering::is_ge))))),
+];
 
-    make_fn(hm, "list", list);
-    make_fn(hm, "list?", is_list);
-    make_fn(hm, "empty?", any);
-    make_fn(hm, "count", count);
+thread_local! {
+    /// The core bindings, made once per thread. Every environment starts out
+    /// with clones of them, rather than parsing their arglists again.
+    static CORE_BINDINGS: SymMap<MalType> = CORE
+        .iter()
+        .map(|(name, arglists, doc, kind)| {
+            (Sym::new(name), kind.make(name, native_meta(arglists, doc)))
+        })
+        .collect();
+}
 
-    // Arithmic
-    make_bin_op(hm, "+", |val1, val2| val1 + val2);
-    make_bin_op(hm, "-", |val1, val2| val1 - val2);
-    make_bin_op(hm, "*", |val1, val2| val1 * val2);
-    make_bin_op(hm, "/", |val1, val2| val1 / val2);
+pub fn add_functions(hm: &mut SymMap<MalType>) {
+    CORE_BINDINGS.with(|core| {
+        hm.extend(core.iter().map(|(name, value)| (*name, value.clone())));
+    });
+}
 
-    // Cmp
-    make_bin_op(hm, "=", |val1, val2| MalType::Bool(val1 == val2));
-    make_bin_op(hm, "<", |val1, val2| MalType::Bool(val1 < val2));
-    make_bin_op(hm, "<=", |val1, val2| MalType::Bool(val1 <= val2));
-    make_bin_op(hm, ">", |val1, val2| MalType::Bool(val1 > val2));
-    make_bin_op(hm, ">=", |val1, val2| MalType::Bool(val1 >= val2));
+native_fn! {
+    #[name = "="]
+    fn equals(x: &MalType, y: &MalType) -> Result<bool> {
+        x.equals(y)
+    }
+}
+
+native_fn! {
+    #[name = "count"]
+    fn count(coll: &MalType) -> Result<i64> {
+        match coll {
+            MalType::List(inner, _) | MalType::Vector(inner, _) | MalType::Set(inner, _) => {
+                Ok(inner.len() as i64)
+            }
+            MalType::HashMap(inner, _) => Ok(inner.len() as i64 / 2),
+            MalType::Record(record) => Ok(record.entries().len() as i64 / 2),
+            MalType::String(s) => Ok(s.chars().count() as i64),
+            MalType::Nil => Ok(0),
+            MalType::Lazy(_) => {
+                let mut count = 0;
+                for item in SeqIter::new(coll)? {
+                    item?;
+                    count += 1;
+                }
+                Ok(count)
+            }
+            other => Err(arg_error("count", 1, mismatch("a collection", other))),
+        }
+    }
+}
+
+native_fn! {
+    #[name = "empty?"]
+    fn any(coll: &MalType) -> Result<bool> {
+        match coll {
+            MalType::List(inner, _) | MalType::Vector(inner, _) | MalType::Set(inner, _) => {
+                Ok(inner.is_empty())
+            }
+            MalType::HashMap(inner, _) => Ok(inner.is_empty()),
+            MalType::String(s) => Ok(s.is_empty()),
+            MalType::Nil => Ok(true),
+            MalType::Lazy(seq) => Ok(seq.step()?.is_none()),
+            other => Err(arg_error("empty?", 1, mismatch("a collection", other))),
+        }
+    }
+}
+
+native_fn! {
+    #[name = "cons"]
+    fn cons(x: MalType, coll: &MalType) -> Result<MalType> {
+        match coll {
+            MalType::List(inner, _) | MalType::Vector(inner, _) => {
+                let mut list = vec![x];
+                list.extend(inner.iter().cloned());
+                Ok(MalType::List(list, None))
+            }
+            MalType::Nil => Ok(MalType::List(vec![x], None)),
+            // Consing onto a lazy sequence must not realize it
+            rest => Ok(MalType::Lazy(LazySeq::realized(Some((x, rest.clone()))))),
+        }
+    }
+}
+
+native_fn! {
+    #[name = "first"]
+    fn first(coll: &MalType) -> Result<MalType> {
+        match coll {
+            // No need to step, which copies the rest of the collection
+            MalType::List(inner, _) | MalType::Vector(inner, _) | MalType::Set(inner, _) => {
+                Ok(inner.first().cloned().unwrap_or(MalType::Nil))
+            }
+            other => Ok(lazy::step(other)?
+                .map(|(first, _)| first)
+                .unwrap_or(MalType::Nil)),
+        }
+    }
+}
+
+native_fn! {
+    #[name = "rest"]
+    fn rest(coll: &MalType) -> Result<MalType> {
+        match coll {
+            MalType::List(inner, _) | MalType::Vector(inner, _) => {
+                Ok(MalType::List(inner.iter().skip(1).cloned().collect(), None))
+            }
+            other => Ok(lazy::step(other)?
+                .map(|(_, rest)| rest)
+                .unwrap_or(MalType::List(vec![], None))),
+        }
+    }
+}
+
+native_fn! {
+    #[name = "not"]
+    fn not(value: &MalType) -> Result<bool> {
+        Ok(!value.truthy())
+    }
+}
+
+native_fn! {
+    #[name = "even?"]
+    fn is_even(n: i64) -> Result<bool> {
+        Ok(n % 2 == 0)
+    }
+}
+
+native_fn! {
+    #[name = "odd?"]
+    fn is_odd(n: i64) -> Result<bool> {
+        Ok(n % 2 != 0)
+    }
+}
+
+fn range(args: &[MalType], _: Environment) -> Result<MalType> {
+    check_arity("range", &[Kind::Optional; 3], args.len())?;
+    for (i, arg) in args.iter().enumerate() {
+        if !matches!(arg, MalType::Number(_) | MalType::Float(_)) {
+            return Err(arg_error("range", i + 1, mismatch("a number", arg)));
+        }
+    }
+    let (start, end, step) = match args {
+        [] => (MalType::Number(0), None, MalType::Number(1)),
+        [end] => (MalType::Number(0), Some(end.clone()), MalType::Number(1)),
+        [start, end] => (start.clone(), Some(end.clone()), MalType::Number(1)),
+        [start, end, step] => (start.clone(), Some(end.clone()), step.clone()),
+        _ => unreachable!("arity was checked"),
+    };
+    Ok(lazy_range(start, end, step))
+}
+
+fn lazy_range(start: MalType, end: Option<MalType>, step: MalType) -> MalType {
+    MalType::Lazy(LazySeq::new(move || {
+        let done = match &end {
+            Some(end)
+                if step
+                    .num_cmp(&MalType::Number(0), "range")?
+                    .is_some_and(Ordering::is_lt) =>
+            {
+                start.num_cmp(end, "range")?.is_some_and(Ordering::is_le)
+            }
+            Some(end) => start.num_cmp(end, "range")?.is_some_and(Ordering::is_ge),
+            None => false,
+        };
+        if done {
+            return Ok(None);
+        }
+        let next = (&start + &step)?;
+        Ok(Some((
+            start.clone(),
+            lazy_range(next, end.clone(), step.clone()),
+        )))
+    }))
+}
+
+native_fn! {
+    #[name = "iterate"]
+    fn iterate(env: &Environment, f: MalType, x: MalType) -> Result<MalType> {
+        Ok(lazy_iterate(f, x, env.clone()))
+    }
+}
+
+fn lazy_iterate(f: MalType, x: MalType, env: Environment) -> MalType {
+    let rest = {
+        let x = x.clone();
+        MalType::Lazy(LazySeq::new(move || {
+            let next = f.clone().eval(std::slice::from_ref(&x), &env)?;
+            lazy::step(&lazy_iterate(f.clone(), next, env.clone()))
+        }))
+    };
+    MalType::Lazy(LazySeq::realized(Some((x, rest))))
+}
+
+/// `(repeat x)` or `(repeat n x)`. Not a `native_fn!`, as its optional
+/// parameter comes first and x may well be nil.
+fn repeat(args: &[MalType], _: Environment) -> Result<MalType> {
+    check_arity("repeat", &[Kind::Optional, Kind::Required], args.len())?;
+    match args {
+        [x] => Ok(lazy_repeat(x.clone())),
+        [n, x] => {
+            let n = i64::from_value(n).map_err(|err| arg_error("repeat", 1, err))?;
+            Ok(lazy_take(n, lazy_repeat(x.clone())))
+        }
+        _ => unreachable!("arity was checked"),
+    }
+}
+
+fn lazy_repeat(x: MalType) -> MalType {
+    MalType::Lazy(LazySeq::new(move || {
+        Ok(Some((x.clone(), lazy_repeat(x.clone()))))
+    }))
+}
+
+native_fn! {
+    #[name = "cycle"]
+    fn cycle(coll: MalType) -> Result<MalType> {
+        Ok(lazy_cycle(coll.clone(), coll))
+    }
 }
 
-fn count(args: &[MalType], _: Environment) -> Result<MalType> {
-    match &args[0] {
-        MalType::List(inner) => Ok(MalType::Number(inner.len() as i64)),
-        MalType::Nil => Ok(MalType::Number(0)),
-        _ => anyhow::bail!("count? received unexpected value {:?}", &args[0]),
+fn lazy_cycle(coll: MalType, current: MalType) -> MalType {
+    MalType::Lazy(LazySeq::new(move || {
+        let step = match lazy::step(&current)? {
+            Some(step) => step,
+            // Start over, unless the collection itself is empty
+            None => match lazy::step(&coll)? {
+                Some(step) => step,
+                None => return Ok(None),
+            },
+        };
+        let (first, rest) = step;
+        Ok(Some((first, lazy_cycle(coll.clone(), rest))))
+    }))
+}
+
+native_fn! {
+    #[name = "map"]
+    fn map(env: &Environment, f: MalType, coll: MalType, colls: &[MalType]) -> Result<MalType> {
+        let colls = std::iter::once(coll).chain(colls.iter().cloned()).collect();
+        Ok(lazy_map(f, colls, env.clone()))
+    }
+}
+
+fn lazy_map(f: MalType, colls: Vec<MalType>, env: Environment) -> MalType {
+    MalType::Lazy(LazySeq::new(move || {
+        let mut firsts = vec![];
+        let mut rests = vec![];
+        // Stops as soon as the shortest collection runs out
+        for coll in &colls {
+            match lazy::step(coll)? {
+                Some((first, rest)) => {
+                    firsts.push(first);
+                    rests.push(rest);
+                }
+                None => return Ok(None),
+            }
+        }
+        let value = f.clone().eval(&firsts, &env)?;
+        Ok(Some((value, lazy_map(f.clone(), rests, env.clone()))))
+    }))
+}
+
+native_fn! {
+    #[name = "filter"]
+    fn filter(env: &Environment, pred: MalType, coll: MalType) -> Result<MalType> {
+        Ok(lazy_filter(pred, coll, env.clone(), true))
+    }
+}
+
+/// Keeps the items for which `pred` is `keep`, so it also serves as `remove`.
+fn lazy_filter(pred: MalType, coll: MalType, env: Environment, keep: bool) -> MalType {
+    MalType::Lazy(LazySeq::new(move || {
+        let mut current = coll.clone();
+        // Skip rejected items in a loop rather than through nested cells
+        while let Some((first, rest)) = lazy::step(&current)? {
+            if pred
+                .clone()
+                .eval(std::slice::from_ref(&first), &env)?
+                .truthy()
+                == keep
+            {
+                let rest = lazy_filter(pred.clone(), rest, env.clone(), keep);
+                return Ok(Some((first, rest)));
+            }
+            current = rest;
+        }
+        Ok(None)
+    }))
+}
+
+native_fn! {
+    #[name = "take"]
+    fn take(n: i64, coll: MalType) -> Result<MalType> {
+        Ok(lazy_take(n, coll))
+    }
+}
+
+fn lazy_take(n: i64, coll: MalType) -> MalType {
+    MalType::Lazy(LazySeq::new(move || {
+        if n <= 0 {
+            return Ok(None);
+        }
+        Ok(lazy::step(&coll)?.map(|(first, rest)| (first, lazy_take(n - 1, rest))))
+    }))
+}
+
+native_fn! {
+    #[name = "drop"]
+    fn drop(n: i64, coll: MalType) -> Result<MalType> {
+        Ok(MalType::Lazy(LazySeq::new(move || {
+            let mut current = coll.clone();
+            for _ in 0..n {
+                match lazy::step(&current)? {
+                    Some((_, rest)) => current = rest,
+                    None => return Ok(None),
+                }
+            }
+            lazy::step(&current)
+        })))
     }
 }
 
-fn any(args: &[MalType], _: Environment) -> Result<MalType> {
-    match &args[0] {
-        MalType::List(inner) => Ok(MalType::Bool(inner.len() == 0)),
-        _ => anyhow::bail!("empty? received unexpected value {:?}", &args[0]),
+native_fn! {
+    #[name = "take-while"]
+    fn take_while(env: &Environment, pred: MalType, coll: MalType) -> Result<MalType> {
+        Ok(lazy_take_while(pred, coll, env.clone()))
     }
 }
 
-fn is_list(args: &[MalType], _: Environment) -> Result<MalType> {
-    match &args[0] {
-        MalType::List(_) => Ok(MalType::Bool(true)),
-        _ => Ok(MalType::Bool(false)),
+fn lazy_take_while(pred: MalType, coll: MalType, env: Environment) -> MalType {
+    MalType::Lazy(LazySeq::new(move || match lazy::step(&coll)? {
+        Some((first, rest))
+            if pred
+                .clone()
+                .eval(std::slice::from_ref(&first), &env)?
+                .truthy() =>
+        {
+            let rest = lazy_take_while(pred.clone(), rest, env.clone());
+            Ok(Some((first, rest)))
+        }
+        _ => Ok(None),
+    }))
+}
+
+native_fn! {
+    #[name = "list?"]
+    fn is_list(x: &MalType) -> Result<bool> {
+        Ok(matches!(x, MalType::List(..)))
     }
 }
+
 fn list(args: &[MalType], _: Environment) -> Result<MalType> {
-    Ok(MalType::List(args.to_vec()))
+    Ok(MalType::List(args.to_vec(), None))
+}
+
+native_fn! {
+    #[name = "remove"]
+    fn remove(env: &Environment, pred: MalType, coll: MalType) -> Result<MalType> {
+        Ok(lazy_filter(pred, coll, env.clone(), false))
+    }
+}
+
+fn reduce(args: &[MalType], env: Environment) -> Result<MalType> {
+    check_arity(
+        "reduce",
+        &[Kind::Required, Kind::Optional, Kind::Required],
+        args.len(),
+    )?;
+    let (f, init, coll) = match args {
+        [f, coll] => {
+            let mut items = SeqIter::new(coll)?;
+            match items.next() {
+                Some(first) => (f, first?, items),
+                // Like Clojure, reducing nothing calls f without arguments
+                None => return f.clone().eval(&[], &env),
+            }
+        }
+        [f, init, coll] => (f, init.clone(), SeqIter::new(coll)?),
+        _ => unreachable!("arity was checked"),
+    };
+
+    let mut acc = init;
+    for item in coll {
+        acc = f.clone().eval(&[acc, item?], &env)?;
+    }
+    Ok(acc)
+}
+
+fn sort(args: &[MalType], env: Environment) -> Result<MalType> {
+    check_arity("sort", &[Kind::Optional, Kind::Required], args.len())?;
+    match args {
+        [coll] => sort_items(collect_seq(coll)?, None, None, &env),
+        [comparator, coll] => sort_items(collect_seq(coll)?, None, Some(comparator), &env),
+        _ => unreachable!("arity was checked"),
+    }
+}
+
+fn sort_by(args: &[MalType], env: Environment) -> Result<MalType> {
+    check_arity(
+        "sort-by",
+        &[Kind::Required, Kind::Optional, Kind::Required],
+        args.len(),
+    )?;
+    match args {
+        [keyfn, coll] => sort_items(collect_seq(coll)?, Some(keyfn), None, &env),
+        [keyfn, comparator, coll] => {
+            sort_items(collect_seq(coll)?, Some(keyfn), Some(comparator), &env)
+        }
+        _ => unreachable!("arity was checked"),
+    }
+}
+
+/// Stable sort on `keyfn` of each item, ordered by `comparator` or by
+/// `compare` when none is given.
+fn sort_items(
+    items: Vec<MalType>,
+    keyfn: Option<&MalType>,
+    comparator: Option<&MalType>,
+    env: &Environment,
+) -> Result<MalType> {
+    let mut keyed = vec![];
+    for item in items {
+        let key = match keyfn {
+            Some(keyfn) => keyfn.clone().eval(std::slice::from_ref(&item), env)?,
+            None => item.clone(),
+        };
+        keyed.push((key, item));
+    }
+
+    // sort_by can't fail, so hold on to the first error and report it after
+    let mut error = None;
+    keyed.sort_by(|(a, _), (b, _)| {
+        if error.is_some() {
+            return Ordering::Equal;
+        }
+        let ordering = match comparator {
+            Some(comparator) => compare_with(comparator, a, b, env),
+            None => compare(a, b),
+        };
+        ordering.unwrap_or_else(|err| {
+            error = Some(err);
+            Ordering::Equal
+        })
+    });
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(MalType::List(
+            keyed.into_iter().map(|(_, item)| item).collect(),
+            None,
+        )),
+    }
+}
+
+/// Default ordering: numbers by value, strings, keywords and symbols
+/// alphabetically, and vectors item by item.
+fn compare(a: &MalType, b: &MalType) -> Result<Ordering> {
+    let ordering = match (a, b) {
+        (MalType::Nil, MalType::Nil) => Ordering::Equal,
+        (MalType::Nil, _) => Ordering::Less,
+        (_, MalType::Nil) => Ordering::Greater,
+        (MalType::Number(_) | MalType::Float(_), MalType::Number(_) | MalType::Float(_)) => {
+            a.num_cmp(b, "compare")?.unwrap_or(Ordering::Equal)
+        }
+        (MalType::String(a), MalType::String(b)) => a.cmp(b),
+        (MalType::Keyword(a), MalType::Keyword(b))
+        | (MalType::Symbol(a, _), MalType::Symbol(b, _)) => a.cmp(b),
+        (MalType::Bool(a), MalType::Bool(b)) => a.cmp(b),
+        (MalType::Vector(a, _), MalType::Vector(b, _)) => {
+            if a.len() != b.len() {
+                return Ok(a.len().cmp(&b.len()));
+            }
+            for (a, b) in a.iter().zip(b) {
+                match compare(a, b)? {
+                    Ordering::Equal => (),
+                    ordering => return Ok(ordering),
+                }
+            }
+            Ordering::Equal
+        }
+        _ => anyhow::bail!("Cannot compare {:b} with {:b}", a, b),
+    };
+    Ok(ordering)
+}
+
+/// A comparator either returns a number like `compare`, or is a predicate
+/// like `<` that tells whether its first argument goes first.
+fn compare_with(
+    comparator: &MalType,
+    a: &MalType,
+    b: &MalType,
+    env: &Environment,
+) -> Result<Ordering> {
+    match comparator.clone().eval(&[a.clone(), b.clone()], env)? {
+        MalType::Number(nr) => Ok(nr.cmp(&0)),
+        MalType::Float(nr) => Ok(nr.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+        first if first.truthy() => Ok(Ordering::Less),
+        _ if comparator
+            .clone()
+            .eval(&[b.clone(), a.clone()], env)?
+            .truthy() =>
+        {
+            Ok(Ordering::Greater)
+        }
+        _ => Ok(Ordering::Equal),
+    }
+}
+
+native_fn! {
+    #[name = "group-by"]
+    fn group_by(env: &Environment, f: &MalType, coll: &MalType) -> Result<MalType> {
+        let mut groups: Vec<(MalType, Vec<MalType>)> = vec![];
+        for item in SeqIter::new(coll)? {
+            let item = item?;
+            let key = f.clone().eval(std::slice::from_ref(&item), env)?;
+            match groups.iter_mut().find(|(k, _)| k == &key) {
+                Some((_, group)) => group.push(item),
+                None => groups.push((key, vec![item])),
+            }
+        }
+
+        Ok(MalType::HashMap(
+            groups
+                .into_iter()
+                .flat_map(|(key, group)| [key, MalType::Vector(group, None)])
+                .collect(),
+            None,
+        ))
+    }
+}
+
+native_fn! {
+    #[name = "frequencies"]
+    fn frequencies(coll: &MalType) -> Result<MalType> {
+        let mut counts: Vec<(MalType, i64)> = vec![];
+        for item in SeqIter::new(coll)? {
+            let item = item?;
+            match counts.iter_mut().find(|(k, _)| k == &item) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((item, 1)),
+            }
+        }
+
+        Ok(MalType::HashMap(
+            counts
+                .into_iter()
+                .flat_map(|(item, count)| [item, MalType::Number(count)])
+                .collect(),
+            None,
+        ))
+    }
+}
+
+fn partition(args: &[MalType], _: Environment) -> Result<MalType> {
+    let kinds = [
+        Kind::Required,
+        Kind::Optional,
+        Kind::Optional,
+        Kind::Required,
+    ];
+    check_arity("partition", &kinds, args.len())?;
+    let (n, step, pad, coll) = match args {
+        [n, coll] => (n, n, None, coll),
+        [n, step, coll] => (n, step, None, coll),
+        [n, step, pad, coll] => (n, step, Some(pad.clone()), coll),
+        _ => unreachable!("arity was checked"),
+    };
+    let n = i64::from_value(n).map_err(|err| arg_error("partition", 1, err))?;
+    let step = i64::from_value(step).map_err(|err| arg_error("partition", 2, err))?;
+    if n <= 0 || step <= 0 {
+        anyhow::bail!("partition expects positive sizes, got {} and {}", n, step)
+    }
+    Ok(lazy_partition(n as usize, step as usize, pad, coll.clone()))
+}
+
+/// Groups of `n` items, `step` apart. A short final group is dropped, unless
+/// `pad` is given to fill it up.
+fn lazy_partition(n: usize, step: usize, pad: Option<MalType>, coll: MalType) -> MalType {
+    MalType::Lazy(LazySeq::new(move || {
+        let mut group = vec![];
+        let mut current = coll.clone();
+        let mut rest = None;
+        let mut consumed = 0;
+        // Walk far enough to fill the group and to find where the next one starts
+        while consumed < n.max(step) {
+            if consumed == step {
+                rest = Some(current.clone());
+            }
+            match lazy::step(&current)? {
+                Some((first, next)) => {
+                    if group.len() < n {
+                        group.push(first);
+                    }
+                    current = next;
+                    consumed += 1;
+                }
+                None => break,
+            }
+        }
+        if consumed == step {
+            rest = Some(current);
+        }
+
+        if group.is_empty() {
+            return Ok(None);
+        }
+        if group.len() < n {
+            match &pad {
+                Some(pad) => {
+                    let fill = SeqIter::new(pad)?.take(n - group.len());
+                    group.extend(fill.collect::<Result<Vec<_>>>()?);
+                }
+                None => return Ok(None),
+            }
+        }
+
+        let rest = rest.unwrap_or(MalType::Nil);
+        Ok(Some((
+            MalType::List(group, None),
+            lazy_partition(n, step, pad.clone(), rest),
+        )))
+    }))
+}
+
+fn interleave(args: &[MalType], _: Environment) -> Result<MalType> {
+    Ok(lazy_interleave(args.to_vec()))
+}
+
+fn lazy_interleave(colls: Vec<MalType>) -> MalType {
+    MalType::Lazy(LazySeq::new(move || {
+        let mut firsts = vec![];
+        let mut rests = vec![];
+        for coll in &colls {
+            match lazy::step(coll)? {
+                Some((first, rest)) => {
+                    firsts.push(first);
+                    rests.push(rest);
+                }
+                None => return Ok(None),
+            }
+        }
+        if firsts.is_empty() {
+            return Ok(None);
+        }
+
+        let mut round = lazy_interleave(rests);
+        for item in firsts.into_iter().rev() {
+            round = MalType::Lazy(LazySeq::realized(Some((item, round))));
+        }
+        lazy::step(&round)
+    }))
+}
+
+native_fn! {
+    #[name = "distinct"]
+    fn distinct(coll: MalType) -> Result<MalType> {
+        Ok(lazy_distinct(coll, Rc::default()))
+    }
+}
+
+/// Every cell shares the one set of items seen so far. Cells are realized in
+/// order and only once, so each adds to it what the ones before it left.
+fn lazy_distinct(coll: MalType, seen: Rc<RefCell<HashSet<Seen>>>) -> MalType {
+    MalType::Lazy(LazySeq::new(move || {
+        let mut current = coll.clone();
+        while let Some((first, rest)) = lazy::step(&current)? {
+            lazy::realize(&first)?;
+            if seen.borrow_mut().insert(Seen(first.clone())) {
+                return Ok(Some((first, lazy_distinct(rest, Rc::clone(&seen)))));
+            }
+            current = rest;
+        }
+        Ok(None)
+    }))
+}
+
+/// An item `distinct` has seen, hashed consistently with `==`: lists,
+/// vectors and lazy seqs hash alike, as they can equal each other, and sets
+/// hash regardless of order. Lazy seqs in it have to be realized.
+struct Seen(MalType);
+
+impl Hash for Seen {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(&self.0, state)
+    }
+}
+
+impl PartialEq for Seen {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Seen {}
+
+fn hash_value<H: Hasher>(value: &MalType, state: &mut H) {
+    let hash_all =
+        |items: &[MalType], state: &mut H| items.iter().for_each(|item| hash_value(item, state));
+    match value {
+        MalType::List(items, _) | MalType::Vector(items, _) => return hash_all(items, state),
+        MalType::Lazy(seq) => return hash_all(&seq.cached().0, state),
+        other => std::mem::discriminant(other).hash(state),
+    }
+    match value {
+        MalType::HashMap(items, _) => hash_all(items, state),
+        MalType::Set(items, _) => items
+            .iter()
+            .map(|item| {
+                let mut hasher = DefaultHasher::new();
+                hash_value(item, &mut hasher);
+                hasher.finish()
+            })
+            .fold(0u64, u64::wrapping_add)
+            .hash(state),
+        MalType::String(s) => s.hash(state),
+        MalType::Symbol(sym, _) | MalType::Keyword(sym) => sym.hash(state),
+        MalType::Number(n) => n.hash(state),
+        // So -0.0 hashes as 0.0, which it equals
+        MalType::Float(n) => (n + 0.0).to_bits().hash(state),
+        MalType::Bool(b) => b.hash(state),
+        MalType::Tagged(tag, value) => {
+            tag.hash(state);
+            hash_value(value, state);
+        }
+        // Equality tells the rest apart
+        _ => (),
+    }
+}
+
+native_fn! {
+    #[name = "reverse"]
+    fn reverse(coll: &MalType) -> Result<MalType> {
+        let mut items = collect_seq(coll)?;
+        items.reverse();
+        Ok(MalType::List(items, None))
+    }
+}
+
+native_fn! {
+    #[name = "some"]
+    fn some(env: &Environment, pred: &MalType, coll: &MalType) -> Result<MalType> {
+        for item in SeqIter::new(coll)? {
+            let ret = pred.clone().eval(&[item?], env)?;
+            if ret.truthy() {
+                return Ok(ret);
+            }
+        }
+        Ok(MalType::Nil)
+    }
+}
+
+native_fn! {
+    #[name = "every?"]
+    fn every(env: &Environment, pred: &MalType, coll: &MalType) -> Result<bool> {
+        for item in SeqIter::new(coll)? {
+            if !pred.clone().eval(&[item?], env)?.truthy() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+native_fn! {
+    #[name = "zipmap"]
+    fn zipmap(keys: &MalType, values: &MalType) -> Result<MalType> {
+        let mut map = vec![];
+        for (key, value) in SeqIter::new(keys)?.zip(SeqIter::new(values)?) {
+            hash_map_insert(&mut map, key?, value?);
+        }
+        Ok(MalType::HashMap(map, None))
+    }
+}
+
+fn collect_seq(coll: &MalType) -> Result<Vec<MalType>> {
+    SeqIter::new(coll)?.collect()
+}
+
+native_fn! {
+    #[name = "set"]
+    fn set(coll: &MalType) -> Result<MalType> {
+        let mut set = vec![];
+        for item in SeqIter::new(coll)? {
+            set_insert(&mut set, item?);
+        }
+        Ok(MalType::Set(set, None))
+    }
+}
+
+native_fn! {
+    #[name = "set?"]
+    fn is_set(value: &MalType) -> Result<bool> {
+        Ok(matches!(value, MalType::Set(..)))
+    }
+}
+
+native_fn! {
+    #[name = "conj"]
+    fn conj(coll: &MalType, items: &[MalType]) -> Result<MalType> {
+        let items = items.iter().cloned();
+        match coll {
+            MalType::List(inner, meta) => {
+                let mut list: Vec<MalType> = items.rev().collect();
+                list.extend(inner.iter().cloned());
+                Ok(MalType::List(list, meta.clone()))
+            }
+            MalType::Nil => Ok(MalType::List(items.rev().collect(), None)),
+            MalType::Vector(inner, meta) => Ok(MalType::Vector(
+                inner.iter().cloned().chain(items).collect(),
+                meta.clone(),
+            )),
+            MalType::Set(inner, meta) => {
+                let mut set = inner.clone();
+                for item in items {
+                    set_insert(&mut set, item);
+                }
+                Ok(MalType::Set(set, meta.clone()))
+            }
+            other => Err(arg_error("conj", 1, mismatch("a collection", other))),
+        }
+    }
+}
+
+native_fn! {
+    #[name = "disj"]
+    fn disj(set: &MalType, items: &[MalType]) -> Result<MalType> {
+        match set {
+            MalType::Set(inner, meta) => Ok(MalType::Set(
+                inner
+                    .iter()
+                    .filter(|item| !items.contains(item))
+                    .cloned()
+                    .collect(),
+                meta.clone(),
+            )),
+            MalType::Nil => Ok(MalType::Nil),
+            other => Err(arg_error("disj", 1, mismatch("a set", other))),
+        }
+    }
+}
+
+native_fn! {
+    #[name = "get"]
+    fn get(coll: &MalType, key: &MalType, default: Option<MalType>) -> Result<MalType> {
+        Ok(lookup(coll, key).or(default).unwrap_or(MalType::Nil))
+    }
+}
+
+native_fn! {
+    #[name = "assoc"]
+    fn assoc(coll: MalType, key: MalType, value: MalType, kvs: &[MalType]) -> Result<MalType> {
+        if !kvs.len().is_multiple_of(2) {
+            anyhow::bail!("assoc expects a value for every key")
+        }
+
+        let pairs = kvs.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone()));
+        let mut coll = coll;
+        for (key, value) in std::iter::once((key, value)).chain(pairs) {
+            coll = match coll {
+                MalType::HashMap(mut map, meta) => {
+                    hash_map_insert(&mut map, key, value);
+                    MalType::HashMap(map, meta)
+                }
+                MalType::Nil => MalType::HashMap(vec![key, value], None),
+                MalType::Record(record) => MalType::Record(record.assoc(key, value)),
+                MalType::Vector(mut items, meta) => match key {
+                    MalType::Number(i) if i >= 0 && (i as usize) < items.len() => {
+                        items[i as usize] = value;
+                        MalType::Vector(items, meta)
+                    }
+                    MalType::Number(i) if i >= 0 && i as usize == items.len() => {
+                        items.push(value);
+                        MalType::Vector(items, meta)
+                    }
+                    key => anyhow::bail!("assoc index {:b} out of bounds", key),
+                },
+                other => return Err(arg_error("assoc", 1, mismatch("a map or vector", &other))),
+            };
+        }
+        Ok(coll)
+    }
+}
+
+native_fn! {
+    #[name = "dissoc"]
+    fn dissoc(coll: MalType, keys: &[MalType]) -> Result<MalType> {
+        let mut coll = coll;
+        for key in keys {
+            coll = match coll {
+                MalType::HashMap(map, meta) => MalType::HashMap(
+                    map.chunks(2)
+                        .filter(|pair| &pair[0] != key)
+                        .flatten()
+                        .cloned()
+                        .collect(),
+                    meta,
+                ),
+                MalType::Record(record) => record.dissoc(key),
+                MalType::Nil => MalType::Nil,
+                other => return Err(arg_error("dissoc", 1, mismatch("a map", &other))),
+            };
+        }
+        Ok(coll)
+    }
+}
+
+native_fn! {
+    #[name = "contains?"]
+    fn contains(coll: &MalType, key: &MalType) -> Result<bool> {
+        match (coll, key) {
+            (MalType::Set(inner, _), item) => Ok(inner.contains(item)),
+            (MalType::HashMap(inner, _), key) => Ok(hash_map_get(inner, key).is_some()),
+            (MalType::Record(record), key) => Ok(record.get(key).is_some()),
+            (MalType::Vector(inner, _), MalType::Number(i)) => {
+                Ok(*i >= 0 && (*i as usize) < inner.len())
+            }
+            (MalType::Nil, _) => Ok(false),
+            (other, _) => Err(arg_error("contains?", 1, mismatch("a collection", other))),
+        }
+    }
+}
+
+native_fn! {
+    #[name = "union"]
+    fn union(sets: &[MalType]) -> Result<MalType> {
+        let mut set = vec![];
+        for (i, arg) in sets.iter().enumerate() {
+            for item in set_items("union", i + 1, arg)? {
+                set_insert(&mut set, item.clone());
+            }
+        }
+        Ok(MalType::Set(set, None))
+    }
+}
+
+native_fn! {
+    #[name = "intersection"]
+    fn intersection(first: &MalType, others: &[MalType]) -> Result<MalType> {
+        let mut set = set_items("intersection", 1, first)?.to_vec();
+        for (i, arg) in others.iter().enumerate() {
+            let other = set_items("intersection", i + 2, arg)?;
+            set.retain(|item| other.contains(item));
+        }
+        Ok(MalType::Set(set, None))
+    }
+}
+
+native_fn! {
+    #[name = "difference"]
+    fn difference(first: &MalType, others: &[MalType]) -> Result<MalType> {
+        let mut set = set_items("difference", 1, first)?.to_vec();
+        for (i, arg) in others.iter().enumerate() {
+            let other = set_items("difference", i + 2, arg)?;
+            set.retain(|item| !other.contains(item));
+        }
+        Ok(MalType::Set(set, None))
+    }
+}
+
+/// The items of the set passed as argument `position` of `name`.
+fn set_items<'a>(name: &str, position: usize, arg: &'a MalType) -> Result<&'a [MalType]> {
+    match arg {
+        MalType::Set(inner, _) => Ok(inner),
+        MalType::Nil => Ok(&[]),
+        other => Err(arg_error(name, position, mismatch("a set", other))),
+    }
+}
+
+fn set_insert(set: &mut Vec<MalType>, item: MalType) {
+    if !set.contains(&item) {
+        set.push(item);
+    }
+}
+
+native_fn! {
+    #[name = "re-pattern"]
+    fn re_pattern(pattern: &MalType) -> Result<MalType> {
+        match pattern {
+            MalType::String(pattern) => Ok(MalType::Regex(MalRegex::new(pattern)?)),
+            MalType::Regex(re) => Ok(MalType::Regex(re.clone())),
+            other => Err(arg_error("re-pattern", 1, mismatch("a string or regex", other))),
+        }
+    }
+}
+
+native_fn! {
+    #[name = "re-find"]
+    fn re_find(re: &MalRegex, s: &str) -> Result<MalType> {
+        Ok(re
+            .captures(s)
+            .map(|captures| captures_to_mal(&captures))
+            .unwrap_or(MalType::Nil))
+    }
+}
+
+native_fn! {
+    #[name = "re-matches"]
+    fn re_matches(re: &MalRegex, s: &str) -> Result<MalType> {
+        Ok(re
+            .anchored()?
+            .captures(s)
+            .map(|captures| captures_to_mal(&captures))
+            .unwrap_or(MalType::Nil))
+    }
+}
+
+native_fn! {
+    #[name = "re-seq"]
+    fn re_seq(re: &MalRegex, s: &str) -> Result<MalType> {
+        let matches: Vec<MalType> = re
+            .captures_iter(s)
+            .map(|captures| captures_to_mal(&captures))
+            .collect();
+        if matches.is_empty() {
+            Ok(MalType::Nil)
+        } else {
+            Ok(MalType::List(matches, None))
+        }
+    }
+}
+
+native_fn! {
+    #[name = "re-replace"]
+    fn re_replace(env: &Environment, re: &MalRegex, s: &str, replacement: &MalType) -> Result<String> {
+        let f = match replacement {
+            MalType::String(replacement) => {
+                return Ok(re.replace_all(s, replacement.as_str()).into_owned())
+            }
+            f => f,
+        };
+
+        let mut buffer = String::new();
+        let mut last = 0;
+        for captures in re.captures_iter(s) {
+            let whole = captures.get(0).unwrap();
+            buffer.push_str(&s[last..whole.start()]);
+            let replacement = f.clone().eval(&[captures_to_mal(&captures)], env)?;
+            buffer.push_str(&replacement.to_string());
+            last = whole.end();
+        }
+        buffer.push_str(&s[last..]);
+        Ok(buffer)
+    }
+}
+
+/// A match without groups is its matched string, otherwise a vector of the
+/// whole match followed by each group, with nil for groups that didn't take part.
+fn captures_to_mal(captures: &Captures) -> MalType {
+    if captures.len() == 1 {
+        return MalType::String(captures[0].to_string());
+    }
+
+    MalType::Vector(
+        captures
+            .iter()
+            .map(|group| match group {
+                Some(group) => MalType::String(group.as_str().to_string()),
+                None => MalType::Nil,
+            })
+            .collect(),
+        None,
+    )
+}
+
+native_fn! {
+    #[name = "json-parse"]
+    fn json_parse(s: &str, opts: Option<&MalType>) -> Result<MalType> {
+        let keywords = match opts {
+            Some(opts) => option(opts, "keywords")?,
+            None => false,
+        };
+        crate::json::parse(s, keywords)
+    }
+}
+
+native_fn! {
+    #[name = "json-stringify"]
+    fn json_stringify(value: &MalType, opts: Option<&MalType>) -> Result<String> {
+        let pretty = match opts {
+            Some(opts) => option(opts, "pretty")?,
+            None => false,
+        };
+        crate::json::stringify(value, pretty)
+    }
+}
+
+native_fn! {
+    #[name = "read-edn"]
+    fn read_edn(env: &Environment, s: &str, opts: Option<&MalType>) -> Result<MalType> {
+        let readers = match opts {
+            Some(opts) => TagReaders::from_opts(opts)?,
+            None => TagReaders::new(),
+        };
+        crate::edn::read(s, &readers, env)
+    }
+}
+
+native_fn! {
+    #[name = "pr-edn"]
+    fn pr_edn(value: &MalType) -> Result<String> {
+        crate::edn::print(value)
+    }
+}
+
+fn defprotocol(args: &[MalType], mut env: Environment) -> Result<MalType> {
+    crate::protocol::defprotocol(args, &mut env)
+}
+
+fn extend_type(args: &[MalType], env: Environment) -> Result<MalType> {
+    crate::protocol::extend_type(args, &env)
+}
+
+fn extend_protocol(args: &[MalType], env: Environment) -> Result<MalType> {
+    crate::protocol::extend_protocol(args, &env)
+}
+
+native_fn! {
+    #[name = "satisfies?"]
+    fn satisfies(protocol: &Protocol, value: &MalType) -> Result<bool> {
+        Ok(protocol.satisfied_by(value))
+    }
+}
+
+fn defrecord(args: &[MalType], mut env: Environment) -> Result<MalType> {
+    crate::record::defrecord(args, &mut env)
+}
+
+native_fn! {
+    #[name = "meta"]
+    fn meta(value: &MalType) -> Result<MalType> {
+        Ok(value.meta().cloned().unwrap_or(MalType::Nil))
+    }
+}
+
+native_fn! {
+    #[name = "with-meta"]
+    fn with_meta(value: MalType, meta: MalType) -> Result<MalType> {
+        value.with_meta(new_meta("with-meta", meta)?)
+    }
+}
+
+native_fn! {
+    #[name = "vary-meta"]
+    fn vary_meta(env: &Environment, value: MalType, f: MalType, args: &[MalType]) -> Result<MalType> {
+        let mut f_args = vec![value.meta().cloned().unwrap_or(MalType::Nil)];
+        f_args.extend(args.iter().cloned());
+        let meta = f.eval(&f_args, env)?;
+        value.with_meta(new_meta("vary-meta", meta)?)
+    }
+}
+
+/// The metadata to give a value, which must be a map or nil.
+fn new_meta(name: &str, meta: MalType) -> Result<Meta> {
+    match meta {
+        MalType::Nil => Ok(None),
+        meta @ MalType::HashMap(..) => Ok(Some(Rc::new(meta))),
+        other => anyhow::bail!(
+            "{} expects metadata to be a map or nil, got {:b}",
+            name,
+            other
+        ),
+    }
+}
+
+native_fn! {
+    #[name = "atom"]
+    fn atom(env: &Environment, value: MalType, opts: &[MalType]) -> Result<MalType> {
+        if !opts.len().is_multiple_of(2) {
+            anyhow::bail!("atom expects a value for every option")
+        }
+
+        let atom = Atom::new(value);
+        for opt in opts.chunks(2) {
+            match &opt[0] {
+                MalType::Keyword(key) if key == "validator" => {
+                    atom.set_validator(opt[1].clone(), env)?
+                }
+                other => anyhow::bail!("atom received unexpected option {:b}", other),
+            }
+        }
+        Ok(MalType::Atom(atom))
+    }
+}
+
+native_fn! {
+    #[name = "atom?"]
+    fn is_atom(value: &MalType) -> Result<bool> {
+        Ok(matches!(value, MalType::Atom(_)))
+    }
+}
+
+native_fn! {
+    #[name = "deref"]
+    fn deref(atom: &Atom) -> Result<MalType> {
+        Ok(atom.deref())
+    }
+}
+
+native_fn! {
+    #[name = "reset!"]
+    fn reset(env: &Environment, atom: &Atom, value: MalType) -> Result<MalType> {
+        atom.reset(value, env)
+    }
+}
+
+native_fn! {
+    #[name = "swap!"]
+    fn swap(env: &Environment, atom: &Atom, f: &MalType, args: &[MalType]) -> Result<MalType> {
+        atom.swap(f, args, env)
+    }
+}
+
+native_fn! {
+    #[name = "compare-and-set!"]
+    fn compare_and_set(env: &Environment, atom: &Atom, old: &MalType, new: MalType) -> Result<bool> {
+        atom.compare_and_set(old, new, env)
+    }
+}
+
+native_fn! {
+    #[name = "add-watch"]
+    fn add_watch(atom: &Atom, key: MalType, f: MalType) -> Result<MalType> {
+        atom.add_watch(key, f);
+        Ok(MalType::Atom(atom.clone()))
+    }
+}
+
+native_fn! {
+    #[name = "remove-watch"]
+    fn remove_watch(atom: &Atom, key: &MalType) -> Result<MalType> {
+        atom.remove_watch(key);
+        Ok(MalType::Atom(atom.clone()))
+    }
+}
+
+native_fn! {
+    #[name = "set-validator!"]
+    fn set_validator(env: &Environment, atom: &Atom, validator: MalType) -> Result<()> {
+        atom.set_validator(validator, env)
+    }
+}
+
+native_fn! {
+    #[name = "get-validator"]
+    fn get_validator(atom: &Atom) -> Result<MalType> {
+        Ok(atom.validator())
+    }
+}
+
+fn defmulti(args: &[MalType], mut env: Environment) -> Result<MalType> {
+    crate::multimethod::defmulti(args, &mut env)
+}
+
+fn defmethod(args: &[MalType], mut env: Environment) -> Result<MalType> {
+    crate::multimethod::defmethod(args, &mut env)
+}
+
+native_fn! {
+    #[name = "remove-method"]
+    fn remove_method(multi: &MultiFn, value: &MalType) -> Result<MalType> {
+        multi.remove_method(value);
+        Ok(MalType::Multi(multi.clone()))
+    }
+}
+
+native_fn! {
+    #[name = "methods"]
+    fn methods(multi: &MultiFn) -> Result<MalType> {
+        Ok(multi.methods())
+    }
+}
+
+native_fn! {
+    #[name = "derive"]
+    fn derive(env: &Environment, child: &MalType, parent: &MalType) -> Result<()> {
+        env.namespaces()
+            .borrow_mut()
+            .hierarchy_mut()
+            .derive(child, parent)
+    }
+}
+
+native_fn! {
+    #[name = "underive"]
+    fn underive(env: &Environment, child: &MalType, parent: &MalType) -> Result<()> {
+        env.namespaces()
+            .borrow_mut()
+            .hierarchy_mut()
+            .underive(child, parent);
+        Ok(())
+    }
+}
+
+native_fn! {
+    #[name = "isa?"]
+    fn isa(env: &Environment, child: &MalType, parent: &MalType) -> Result<bool> {
+        Ok(env.namespaces().borrow().hierarchy().isa(child, parent))
+    }
+}
+
+native_fn! {
+    #[name = "ancestors"]
+    fn ancestors(env: &Environment, child: &MalType) -> Result<MalType> {
+        let ancestors = env.namespaces().borrow().hierarchy().ancestors(child);
+        Ok(MalType::Set(ancestors, None))
+    }
+}
+
+fn ns(args: &[MalType], env: Environment) -> Result<MalType> {
+    crate::namespace::ns(args, &env)
+}
+
+fn require(args: &[MalType], env: Environment) -> Result<MalType> {
+    args.iter()
+        .try_for_each(|spec| crate::namespace::require(spec, &env))?;
+    Ok(MalType::Nil)
+}
+
+fn refer(args: &[MalType], env: Environment) -> Result<MalType> {
+    match args {
+        [MalType::Symbol(ns, _)] => {
+            crate::namespace::refer(ns, &MalType::Keyword("all".into()), &env)?
+        }
+        [MalType::Symbol(ns, _), MalType::Keyword(only), names] if only == "only" => {
+            crate::namespace::refer(ns, names, &env)?
+        }
+        _ => anyhow::bail!("refer expects a namespace and optionally :only [names]"),
+    }
+    Ok(MalType::Nil)
+}
+
+native_fn! {
+    #[name = "load-file"]
+    fn load_file(env: &Environment, path: &str) -> Result<MalType> {
+        crate::namespace::load_file(Path::new(path), env)
+    }
+}
+
+fn load_path(_: &[MalType], env: Environment) -> Result<MalType> {
+    let namespaces = env.namespaces().borrow();
+    Ok(MalType::Vector(
+        namespaces
+            .load_path()
+            .iter()
+            .map(|dir| MalType::String(dir.display().to_string()))
+            .collect(),
+        None,
+    ))
+}
+
+native_fn! {
+    #[name = "set-load-path!"]
+    fn set_load_path(env: &Environment, dirs: Vec<String>) -> Result<MalType> {
+        let dirs = dirs.into_iter().map(PathBuf::from).collect();
+        env.namespaces().borrow_mut().set_load_path(dirs);
+        Ok(MalType::Nil)
+    }
+}
+
+/// Reads a flag like `{:pretty true}` from an options map, which may be nil.
+fn option(opts: &MalType, name: &str) -> Result<bool> {
+    match opts {
+        MalType::HashMap(map, _) => Ok(hash_map_get(map, &MalType::Keyword(name.into()))
+            .map(MalType::truthy)
+            .unwrap_or(false)),
+        MalType::Nil => Ok(false),
+        other => anyhow::bail!("Expected an options map, got {:b}", other),
+    }
 }
 
 // Pretty
+native_fn! {
+    #[name = "subs"]
+    fn subs(s: &str, start: i64, end: Option<i64>) -> Result<String> {
+        let len = s.chars().count() as i64;
+        let end = end.unwrap_or(len);
+        if start < 0 || end < start || end > len {
+            anyhow::bail!("subs range {} to {} is out of bounds for {:?}", start, end, s)
+        }
+        Ok(s.chars()
+            .skip(start as usize)
+            .take((end - start) as usize)
+            .collect())
+    }
+}
+
 fn prn(args: &[MalType], _: Environment) -> Result<MalType> {
-    if let Some(arg) = args.get(0) {
+    args.iter().try_for_each(lazy::realize)?;
+    if let Some(arg) = args.first() {
         println!("{:b}", arg);
     } else {
         println!()
@@ -77,6 +1559,7 @@ fn prn(args: &[MalType], _: Environment) -> Result<MalType> {
 
 fn println(args: &[MalType], _: Environment) -> Result<MalType> {
     use std::fmt::Write;
+    args.iter().try_for_each(lazy::realize)?;
     let mut buffer = String::new();
     for (i, arg) in args.iter().enumerate() {
         if i > 0 {
@@ -92,6 +1575,7 @@ fn println(args: &[MalType], _: Environment) -> Result<MalType> {
 // Pretty
 fn pr_str(args: &[MalType], _: Environment) -> Result<MalType> {
     use std::fmt::Write;
+    args.iter().try_for_each(lazy::realize)?;
     let mut buffer = String::new();
     for (i, arg) in args.iter().enumerate() {
         if i > 0 {
@@ -105,8 +1589,9 @@ fn pr_str(args: &[MalType], _: Environment) -> Result<MalType> {
 
 fn str(args: &[MalType], _: Environment) -> Result<MalType> {
     use std::fmt::Write;
+    args.iter().try_for_each(lazy::realize)?;
     let mut buffer = String::new();
-    for (i, arg) in args.iter().enumerate() {
+    for arg in args {
         write!(&mut buffer, "{}", arg)?;
     }
     Ok(MalType::String(buffer))
@@ -120,63 +1605,270 @@ fn _do(args: &[MalType], mut env: Environment) -> Result<MalType> {
         .ok_or(anyhow::anyhow!("Received error while trying to eval?"))?
 }
 
-fn _if(args: &[MalType], mut env: Environment) -> Result<MalType> {
-    let booleanish = crate::eval::eval(&args[0], &mut env)?;
-
-    // if trueish, eval 2nd parameter, otherwise eval third
-    match booleanish {
-        MalType::Nil => {
-            if args.len() < 3 {
-                return Ok(MalType::Nil);
-            }
-            crate::eval::eval(&args[2], &mut env)
+native_fn! {
+    #[name = "if"]
+    fn _if(env: &Environment, test: &MalType, then: &MalType, else_: Option<&MalType>) -> Result<MalType> {
+        let mut env = env.clone();
+        // if trueish, eval 2nd parameter, otherwise eval third
+        match crate::eval::eval(test, &mut env)?.truthy() {
+            true => crate::eval::eval(then, &mut env),
+            false => match else_ {
+                Some(else_) => crate::eval::eval(else_, &mut env),
+                None => Ok(MalType::Nil),
+            },
         }
-        MalType::Bool(x) if !x => {
-            if args.len() < 3 {
-                return Ok(MalType::Nil);
-            }
-            crate::eval::eval(&args[2], &mut env)
-        }
-        _ => crate::eval::eval(&args[1], &mut env),
     }
 }
 
+fn lazy_seq(args: &[MalType], env: Environment) -> Result<MalType> {
+    let body = args.to_vec();
+    Ok(MalType::Lazy(LazySeq::new(move || {
+        if body.is_empty() {
+            return Ok(None);
+        }
+        let value = _do(&body, env.clone())?;
+        lazy::step(&value)
+    })))
+}
+
+/// `(fn* name? [params] body...)`, or with one `([params] body...)` list per
+/// arity. The mal style `(fn* (params) body)` is read as a single arity.
 fn _fn(args: &[MalType], env: Environment) -> Result<MalType> {
-    match &args[0] {
-        MalType::List(_) => (),
-        _ => anyhow::bail!("Received non list as parameter to fn*"),
+    let mut source = vec![MalType::Symbol("fn*".into(), None)];
+    source.extend(args.iter().cloned());
+
+    let (name, args) = match args.first() {
+        Some(MalType::Symbol(name, _)) => (Some(name.to_string()), &args[1..]),
+        _ => (None, args),
     };
 
+    let is_arity = |form: &MalType| matches!(form, MalType::List(inner, _) if matches!(inner.first(), Some(MalType::Vector(..))));
+    let arities = if !args.is_empty() && args.iter().all(is_arity) {
+        args.iter()
+            .map(|form| match form {
+                MalType::List(inner, _) => read_arity(&inner[0], &inner[1..]),
+                _ => unreachable!(),
+            })
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        match args.first() {
+            Some(params) => vec![read_arity(params, &args[1..])?],
+            None => anyhow::bail!("fn* expects a parameter list"),
+        }
+    };
+
+    let variadic: Vec<usize> = arities
+        .iter()
+        .filter(|arity| arity.arity().1)
+        .map(|arity| arity.arity().0)
+        .collect();
+    if variadic.len() > 1 {
+        anyhow::bail!("fn* can't have more than one variadic arity")
+    }
+    for (i, arity) in arities.iter().enumerate() {
+        let (required, is_variadic) = arity.arity();
+        if is_variadic {
+            continue;
+        }
+        if arities[..i]
+            .iter()
+            .any(|other| other.arity() == (required, false))
+        {
+            anyhow::bail!("fn* can't have two arities taking {} args", required)
+        }
+        if variadic.iter().any(|min| required > *min) {
+            anyhow::bail!("fn* can't have a fixed arity with more params than the variadic one")
+        }
+    }
+
     let mal_fn = MalFn {
-        expr: Box::new(args[1].clone()),
-        captured_args: Box::new(args[0].clone()),
+        name,
+        arities: Rc::new(arities),
         captured_env: env,
+        meta: None,
+        source: Some(Rc::new(MalType::List(source, None))),
     };
     Ok(MalType::Fn(mal_fn))
 }
 
+fn _loop(args: &[MalType], env: Environment) -> Result<MalType> {
+    let bindings = match args.first() {
+        Some(MalType::Vector(bindings, _)) | Some(MalType::List(bindings, _))
+            if bindings.len().is_multiple_of(2) =>
+        {
+            bindings
+        }
+        _ => anyhow::bail!("loop* expects a vector with an even number of binding forms"),
+    };
+    let body = &args[1..];
+    check_recur(body, true)?;
+
+    let mut scope = env.clone();
+    scope.enter();
+    for pair in bindings.chunks(2) {
+        let value = crate::eval::eval(&pair[1], &mut scope)?;
+        scope.bind(&pair[0], value)?;
+    }
+
+    loop {
+        let ret = body
+            .iter()
+            .try_fold(MalType::Nil, |_, form| crate::eval::eval(form, &mut scope));
+        match Tail::from(ret)? {
+            Tail::Return(ret) => return Ok(ret),
+            Tail::Recur(values) => {
+                if values.len() != bindings.len() / 2 {
+                    anyhow::bail!(
+                        "Mismatched argument count to recur, expected {} args, got {}",
+                        bindings.len() / 2,
+                        values.len()
+                    )
+                }
+                scope = env.clone();
+                scope.enter();
+                for (pair, value) in bindings.chunks(2).zip(values) {
+                    scope.bind(&pair[0], value)?;
+                }
+            }
+        }
+    }
+}
+
+fn recur(args: &[MalType], mut env: Environment) -> Result<MalType> {
+    let values = args
+        .iter()
+        .map(|arg| crate::eval::eval(arg, &mut env))
+        .collect::<Result<Vec<_>>>()?;
+    Err(Recur::raise(values))
+}
+
+/// Rejects `recur` anywhere but in tail position of `forms`, before they run.
+/// Only the last form is in tail position, and only if `tail` is set.
+fn check_recur(forms: &[MalType], tail: bool) -> Result<()> {
+    for (i, form) in forms.iter().enumerate() {
+        check_recur_form(form, tail && i + 1 == forms.len())?;
+    }
+    Ok(())
+}
+
+fn check_recur_form(form: &MalType, tail: bool) -> Result<()> {
+    let inner = match form {
+        MalType::List(inner, _) => inner,
+        MalType::Vector(inner, _) | MalType::Set(inner, _) | MalType::HashMap(inner, _) => {
+            return check_recur(inner, false)
+        }
+        _ => return Ok(()),
+    };
+    let head = match inner.first() {
+        Some(MalType::Symbol(head, _)) => head.as_str(),
+        _ => "",
+    };
+    let bindings = |form: Option<&MalType>| match form {
+        Some(MalType::Vector(bindings, _)) | Some(MalType::List(bindings, _)) => {
+            let values: Vec<MalType> = bindings.iter().skip(1).step_by(2).cloned().collect();
+            check_recur(&values, false)
+        }
+        _ => Ok(()),
+    };
+
+    match head {
+        "recur" if !tail => anyhow::bail!("Can only recur from tail position: {:b}", form),
+        "recur" => check_recur(&inner[1..], false),
+        "if" => {
+            check_recur(&inner[1..2.min(inner.len())], false)?;
+            inner[2.min(inner.len())..]
+                .iter()
+                .try_for_each(|branch| check_recur_form(branch, tail))
+        }
+        "do" => check_recur(&inner[1..], tail),
+        // let* only evaluates its last body form
+        "let*" => {
+            bindings(inner.get(1))?;
+            check_recur(&inner[inner.len().min(2)..], tail)
+        }
+        // A loop is a recur target of its own
+        "loop*" => {
+            bindings(inner.get(1))?;
+            check_recur(&inner[inner.len().min(2)..], true)
+        }
+        // Checked when the fn* itself is evaluated
+        "fn*" => Ok(()),
+        _ => check_recur(inner, false),
+    }
+}
+
+fn read_arity(params: &MalType, body: &[MalType]) -> Result<MalArity> {
+    let inner = match params {
+        MalType::List(inner, _) | MalType::Vector(inner, _) => inner,
+        other => anyhow::bail!("fn* expects a parameter list or vector, got {:b}", other),
+    };
+    if let Some(i) = inner.iter().position(|param| param.is_symbol(*AMP)) {
+        if inner.len() != i + 2 {
+            anyhow::bail!("fn* expects exactly one binding after & in {:b}", params)
+        }
+    }
+
+    check_recur(body, true)?;
+
+    Ok(MalArity {
+        params: params.clone(),
+        body: body.to_vec(),
+    })
+}
+
 fn def(args: &[MalType], mut env: Environment) -> Result<MalType> {
-    let eval = crate::eval::eval(&args[1], &mut env)?;
-    env.set(args[0].clone(), eval.clone());
-    Ok(eval)
+    crate::doc::def(args, &mut env)
+}
+
+fn defn(args: &[MalType], mut env: Environment) -> Result<MalType> {
+    crate::doc::defn(args, &mut env)
+}
+
+fn doc(args: &[MalType], env: Environment) -> Result<MalType> {
+    crate::doc::doc(args, &env)
+}
+
+fn source(args: &[MalType], env: Environment) -> Result<MalType> {
+    crate::doc::source(args, &env)
+}
+
+fn trace(args: &[MalType], env: Environment) -> Result<MalType> {
+    crate::debug::trace(args, &env)
+}
+
+fn untrace(args: &[MalType], env: Environment) -> Result<MalType> {
+    crate::debug::untrace(args, &env)
+}
+
+fn set_breakpoint(args: &[MalType], env: Environment) -> Result<MalType> {
+    crate::debug::set_breakpoint(args, &env)
+}
+
+fn clear_breakpoint(args: &[MalType], env: Environment) -> Result<MalType> {
+    crate::debug::clear_breakpoint(args, &env)
+}
+
+fn profile(args: &[MalType], mut env: Environment) -> Result<MalType> {
+    crate::profile::profile(args, &mut env)
+}
+
+fn apropos(args: &[MalType], env: Environment) -> Result<MalType> {
+    crate::doc::apropos(args, &env)
 }
 
 fn _let(args: &[MalType], mut env: Environment) -> Result<MalType> {
     env.enter();
-    match &args[0] {
-        MalType::List(inner) => {
-            for i in (0..inner.len()).step_by(2) {
-                let eval = crate::eval::eval(&inner[i + 1], &mut env)?;
-                env.set(inner[i].clone(), eval);
+    match args.first() {
+        Some(MalType::List(inner, _)) | Some(MalType::Vector(inner, _)) => {
+            if !inner.len().is_multiple_of(2) {
+                anyhow::bail!("let* expects an even number of binding forms")
             }
-        }
-        MalType::Vector(inner) => {
-            for i in (0..inner.len()).step_by(2) {
-                let eval = crate::eval::eval(&inner[i + 1], &mut env)?;
-                env.set(inner[i].clone(), eval);
+            for pair in inner.chunks(2) {
+                let eval = crate::eval::eval(&pair[1], &mut env)?;
+                env.bind(&pair[0], eval)?;
             }
         }
-        other => panic!(
+        other => anyhow::bail!(
             "Let binding received not list as first parameter: {:?}",
             other
         ),
@@ -187,66 +1879,113 @@ fn _let(args: &[MalType], mut env: Environment) -> Result<MalType> {
     ret
 }
 
-fn make_bin_op(hm: &mut HashMap<String, MalType>, s: &str, f: fn(&MalType, &MalType) -> MalType) {
-    let inner_fn = Rc::new(move |x: &[MalType], _| match x {
-        [arg1, arg2, ..] => Ok(f(arg1, arg2)),
-        _ => unreachable!(),
-    });
-
-    let op = MalType::BinOp(MalExpr {
-        symbol: s.to_string(),
-        arguments: 2,
-        inner: inner_fn,
-    });
-
-    hm.insert(s.to_string(), op);
+/// Metadata for a core function: its arglists, written as `"[x] [x y]"`, and
+/// its docstring.
+fn native_meta(arglists: &str, doc: &str) -> Meta {
+    let arglists = Parser::new(Lexer::tokenize(arglists))
+        .parse()
+        .expect("core arglists should parse");
+    Some(Rc::new(MalType::HashMap(
+        vec![
+            MalType::Keyword("arglists".into()),
+            MalType::List(arglists, None),
+            MalType::Keyword("doc".into()),
+            MalType::String(doc.to_string()),
+        ],
+        None,
+    )))
 }
 
-fn make_bind(
-    hm: &mut HashMap<String, MalType>,
-    s: &'static str,
-    f: fn(&[MalType], Environment) -> Result<MalType>,
-) {
-    let inner_fn = Rc::new(move |x: &[MalType], env| {
-        debug!("{}", s);
-        trace!("{}. args: {:?}", s, x);
-        let ret = f(x, env);
-        trace!("{}. ret: {:?}", s, ret);
-        ret
-    });
+type CoreFn = fn(&[MalType], Environment) -> Result<MalType>;
 
-    let op = MalType::Bind(MalExpr {
-        symbol: s.to_string(),
-        arguments: 0,
-        inner: inner_fn,
-    });
+/// How a core binding is made.
+#[derive(Clone, Copy)]
+enum Core {
+    /// A special form, given its arguments unevaluated.
+    Bind(CoreFn),
+    LibFn(CoreFn),
+    BinOp(fn(&MalType, &MalType) -> Result<MalType>),
+}
 
-    hm.insert(s.to_string(), op);
+impl Core {
+    fn make(self, name: &'static str, meta: Meta) -> MalType {
+        let traced = |f: CoreFn| -> NativeFn {
+            Rc::new(move |x: &[MalType], env| {
+                debug!("{}", name);
+                trace!("{}. args: {:?}", name, x);
+                let ret = f(x, env);
+                trace!("{}. ret: {:?}", name, ret);
+                ret
+            })
+        };
+        match self {
+            Bind(f) => MalType::Bind(MalExpr {
+                symbol: name.to_string(),
+                arguments: 0,
+                inner: traced(f),
+                meta,
+            }),
+            LibFn(f) => MalType::LibFn(MalLibFn {
+                expr: Box::new(MalExpr {
+                    symbol: name.to_string(),
+                    arguments: 0,
+                    inner: traced(f),
+                    meta,
+                }),
+                captured_env: Box::new(MalType::List(vec![], None)),
+            }),
+            BinOp(f) => MalType::BinOp(MalExpr {
+                symbol: name.to_string(),
+                arguments: 2,
+                inner: Rc::new(move |x: &[MalType], _| {
+                    check_arity(name, &[Kind::Required; 2], x.len())?;
+                    let [arg1, arg2] = x else {
+                        unreachable!("arity was checked")
+                    };
+                    f(arg1, arg2)
+                }),
+                meta,
+            }),
+        }
+    }
 }
 
-fn make_fn(
-    hm: &mut HashMap<String, MalType>,
-    s: &'static str,
-    f: fn(&[MalType], Environment) -> Result<MalType>,
-) {
-    let inner_fn = Rc::new(move |x: &[MalType], env| {
-        debug!("{}", s);
-        trace!("{}. args: {:?}", s, x);
-        let ret = f(x, env);
-        trace!("{}. ret: {:?}", s, ret);
-        ret
-    });
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let op = MalExpr {
-        symbol: s.to_string(),
-        arguments: 0,
-        inner: inner_fn,
-    };
+    #[test]
+    fn core_docs() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Info)
+            .is_test(true)
+            .try_init();
 
-    let _fn = MalType::LibFn(MalLibFn {
-        expr: Box::new(op),
-        captured_env: Box::new(MalType::List(vec![])),
-    });
+        let env = Environment::new();
+        let mut seen = HashSet::new();
+        for (name, arglists, doc, _) in CORE {
+            assert!(seen.insert(name), "{} is bound twice", name);
+            let value = env
+                .get(*name)
+                .unwrap_or_else(|| panic!("{} is not bound", name));
+            let Some(MalType::HashMap(meta, _)) = value.meta() else {
+                panic!("{} has no metadata", name)
+            };
+            match hash_map_get(meta, &MalType::Keyword("arglists".into())) {
+                Some(MalType::List(lists, _))
+                    if !lists.is_empty()
+                        && lists.iter().all(|list| matches!(list, MalType::Vector(..))) => {}
+                other => panic!("{} has arglists {:?}", name, other),
+            }
+            assert!(!doc.is_empty(), "{} has no doc", name);
 
-    hm.insert(s.to_string(), _fn);
+            let described = crate::doc::describe(name, &value);
+            assert!(
+                described.contains(&format!("({})", arglists)) && described.contains(doc),
+                "(doc {}) printed {}",
+                name,
+                described
+            );
+        }
+    }
 }
@@ -0,0 +1,423 @@
+use crate::{
+    environment::Environment,
+    reader::{Lexer, Parser},
+    types::MalType,
+};
+use anyhow::Result;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    io::{BufRead, Write},
+    rc::Rc,
+};
+
+const HELP: &str = "\
+s, step      evaluate up to the next form, stepping into calls
+n, next      evaluate this form and stop at the one after it
+o, out       finish the current fn call and stop after it
+c, continue  run until the next breakpoint
+l, locals    print the bindings of the enclosing scopes
+p, print x   evaluate x here and print its value
+q, quit      abort the evaluation
+h, help      print this";
+
+/// Traced functions, breakpoints and where evaluation is stepping to. Like
+/// the rest of the interpreter's registries, there is one per thread.
+struct Debugger {
+    /// Names of the traced and breakpointed functions, by `identity`.
+    traced: HashMap<usize, String>,
+    breakpoints: HashMap<usize, String>,
+    /// How deeply the form being evaluated is nested.
+    depth: usize,
+    /// The depth of the form each pending call was made from, innermost last.
+    calls: Vec<usize>,
+    /// Traced calls in progress, for indenting the trace.
+    traced_calls: usize,
+    /// Stop at the next list form nested at most this deep.
+    stop_at: Option<usize>,
+    /// The breakpointed fn whose body the next stop is in.
+    break_in: Option<String>,
+    /// Whether the prompt is reading commands, during which nothing is
+    /// traced or stopped in.
+    prompting: bool,
+    /// Where the debugger reads commands and prints to, stdin and stdout
+    /// when `None`.
+    input: Option<Box<dyn BufRead>>,
+    output: Option<Box<dyn Write>>,
+}
+
+thread_local! {
+    static DEBUGGER: RefCell<Debugger> = RefCell::new(Debugger {
+        traced: HashMap::new(),
+        breakpoints: HashMap::new(),
+        depth: 0,
+        calls: vec![],
+        traced_calls: 0,
+        stop_at: None,
+        break_in: None,
+        prompting: false,
+        input: None,
+        output: None,
+    });
+
+    /// Whether evaluation has to go through the debugger at all, checked
+    /// first so nothing else is paid while it's unused.
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn with<T>(f: impl FnOnce(&mut Debugger) -> T) -> T {
+    DEBUGGER.with(|debugger| {
+        let mut debugger = debugger.borrow_mut();
+        let ret = f(&mut debugger);
+        let enabled = !debugger.prompting
+            && (!debugger.traced.is_empty()
+                || !debugger.breakpoints.is_empty()
+                || debugger.stop_at.is_some());
+        ENABLED.with(|cell| cell.set(enabled));
+        ret
+    })
+}
+
+pub fn enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+/// Makes the debugger read commands from `input` and print, along with the
+/// trace, to `output` instead of stdin and stdout.
+pub fn set_io(input: impl BufRead + 'static, output: impl Write + 'static) {
+    with(|debugger| {
+        debugger.input = Some(Box::new(input));
+        debugger.output = Some(Box::new(output));
+    })
+}
+
+impl Debugger {
+    fn print(&mut self, text: &str) {
+        // Failing to print the trace shouldn't fail the program being traced
+        let _ = match &mut self.output {
+            Some(output) => writeln!(output, "{}", text),
+            None => writeln!(std::io::stdout(), "{}", text),
+        };
+    }
+
+    fn read_command(&mut self) -> Option<String> {
+        let prompt = match &mut self.output {
+            Some(output) => write!(output, "debug> ").and_then(|_| output.flush()),
+            None => write!(std::io::stdout(), "debug> ").and_then(|_| std::io::stdout().flush()),
+        };
+        prompt.ok()?;
+        let mut line = String::new();
+        let read = match &mut self.input {
+            Some(input) => input.read_line(&mut line),
+            None => std::io::stdin().read_line(&mut line),
+        };
+        match read {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim().to_string()),
+        }
+    }
+}
+
+/// Tells functions apart by what their clones share, so a fn stays traced
+/// when it's bound to another name or calls itself by its own.
+fn identity(value: &MalType) -> Option<usize> {
+    let ptr = match value {
+        MalType::Fn(f) => Rc::as_ptr(&f.arities) as *const (),
+        MalType::LibFn(f) => Rc::as_ptr(&f.expr.inner) as *const (),
+        MalType::BinOp(expr) => Rc::as_ptr(&expr.inner) as *const (),
+        _ => return None,
+    };
+    Some(ptr as usize)
+}
+
+/// Looks up the function a `(trace name)` style form names.
+fn named_fn(name: &str, args: &[MalType], env: &Environment) -> Result<(String, usize)> {
+    let symbol = match args {
+        [MalType::Symbol(symbol, _)] => symbol,
+        _ => anyhow::bail!("{} expects the name of a fn, received {:?}", name, args),
+    };
+    let value = env
+        .get(symbol)
+        .ok_or_else(|| anyhow::anyhow!("{} not found", symbol))?;
+    match identity(&value) {
+        Some(id) => Ok((symbol.to_string(), id)),
+        None => anyhow::bail!("{} can't {} {:b}", name, name, value),
+    }
+}
+
+/// `(trace name)` prints every call of the fn bound to `name`, indented by
+/// how many traced calls it's nested in, and what it returned.
+pub fn trace(args: &[MalType], env: &Environment) -> Result<MalType> {
+    let (name, id) = named_fn("trace", args, env)?;
+    with(|debugger| debugger.traced.insert(id, name));
+    Ok(MalType::Nil)
+}
+
+pub fn untrace(args: &[MalType], env: &Environment) -> Result<MalType> {
+    let (_, id) = named_fn("untrace", args, env)?;
+    with(|debugger| debugger.traced.remove(&id));
+    Ok(MalType::Nil)
+}
+
+/// `(break name)` stops in the debugger whenever the fn bound to `name` is
+/// called, at the first form of its body.
+pub fn set_breakpoint(args: &[MalType], env: &Environment) -> Result<MalType> {
+    let (name, id) = named_fn("break", args, env)?;
+    with(|debugger| debugger.breakpoints.insert(id, name));
+    Ok(MalType::Nil)
+}
+
+pub fn clear_breakpoint(args: &[MalType], env: &Environment) -> Result<MalType> {
+    let (_, id) = named_fn("unbreak", args, env)?;
+    with(|debugger| debugger.breakpoints.remove(&id));
+    Ok(MalType::Nil)
+}
+
+/// Calls `f`, tracing the call and stopping at breakpoints as asked.
+pub fn call(f: MalType, args: &[MalType], env: &Environment) -> Result<MalType> {
+    let (traced, breakpoint) = with(|debugger| {
+        let id = identity(&f);
+        let lookup = |names: &HashMap<usize, String>| id.and_then(|id| names.get(&id).cloned());
+        (lookup(&debugger.traced), lookup(&debugger.breakpoints))
+    });
+    let name = traced.clone().or_else(|| breakpoint.clone());
+    let form = || {
+        let mut form = vec![MalType::Symbol(
+            name.as_deref().unwrap_or_default().into(),
+            None,
+        )];
+        form.extend(args.iter().cloned());
+        MalType::List(form, None)
+    };
+
+    if let Some(name) = breakpoint {
+        match f {
+            // Stop once the params are bound, so they show up as locals
+            MalType::Fn(_) => with(|debugger| {
+                debugger.stop_at = Some(usize::MAX);
+                debugger.break_in = Some(name);
+            }),
+            _ => prompt(&format!("Break at {:b}", form()), env)?,
+        }
+    }
+    if traced.is_some() {
+        let line = format!("{}{:b}", indent(), form());
+        with(|debugger| {
+            debugger.print(&line);
+            debugger.traced_calls += 1;
+        });
+    }
+
+    with(|debugger| debugger.calls.push(debugger.depth));
+    let ret = f.invoke(args, env);
+    with(|debugger| debugger.calls.pop());
+
+    if traced.is_some() {
+        with(|debugger| debugger.traced_calls -= 1);
+        let line = match &ret {
+            Ok(value) => format!("{}=> {:b}", indent(), value),
+            Err(err) => format!("{}=> Error: {}", indent(), err),
+        };
+        with(|debugger| debugger.print(&line));
+    }
+    ret
+}
+
+fn indent() -> String {
+    with(|debugger| "| ".repeat(debugger.traced_calls))
+}
+
+/// Marks a form as being evaluated for as long as it lives.
+pub struct Step(bool);
+
+impl Drop for Step {
+    fn drop(&mut self) {
+        if self.0 {
+            with(|debugger| {
+                debugger.depth -= 1;
+                // Stepping ends along with the evaluation it started in
+                if debugger.depth == 0 {
+                    debugger.stop_at = None;
+                    debugger.break_in = None;
+                }
+            });
+        }
+    }
+}
+
+/// Called before `ast` is evaluated, stopping there if stepping calls for it.
+pub fn step(ast: &MalType, env: &Environment) -> Result<Step> {
+    if !enabled() {
+        return Ok(Step(false));
+    }
+    let stop = with(|debugger| {
+        debugger.depth += 1;
+        let nested = debugger
+            .stop_at
+            .is_some_and(|depth| debugger.depth <= depth);
+        nested && matches!(ast, MalType::List(items, _) if !items.is_empty())
+    });
+    let step = Step(true);
+    if stop {
+        let header = match with(|debugger| debugger.break_in.take()) {
+            Some(name) => format!("Break in {} at {:b}", name, ast),
+            None => format!("At {:b}", ast),
+        };
+        prompt(&header, env)?;
+    }
+    Ok(step)
+}
+
+/// Reads commands until one of them resumes evaluation.
+fn prompt(header: &str, env: &Environment) -> Result<()> {
+    with(|debugger| {
+        debugger.print(header);
+        debugger.stop_at = None;
+        debugger.prompting = true;
+    });
+    let resumed = read_commands(env);
+    with(|debugger| debugger.prompting = false);
+    resumed
+}
+
+fn read_commands(env: &Environment) -> Result<()> {
+    loop {
+        let Some(line) = with(|debugger| debugger.read_command()) else {
+            return Ok(());
+        };
+        let (command, arg) = line.split_once(' ').unwrap_or((&line, ""));
+        let stop_at: fn(&Debugger) -> Option<usize> = match command {
+            "s" | "step" => |_| Some(usize::MAX),
+            "n" | "next" => |debugger| Some(debugger.depth),
+            "o" | "out" => |debugger| debugger.calls.last().copied(),
+            "c" | "continue" => |_| None,
+            "l" | "locals" => {
+                let locals: Vec<String> = env
+                    .locals()
+                    .iter()
+                    .map(|(name, value)| format!("{} = {:b}", name, value))
+                    .collect();
+                with(|debugger| locals.iter().for_each(|line| debugger.print(line)));
+                continue;
+            }
+            "p" | "print" => {
+                let value = Parser::new(Lexer::tokenize(arg)).parse().and_then(|forms| {
+                    forms.iter().try_fold(MalType::Nil, |_, form| {
+                        crate::eval::eval(form, &mut env.clone())
+                    })
+                });
+                let line = match value {
+                    Ok(value) => format!("{:b}", value),
+                    Err(err) => format!("Error: {}", err),
+                };
+                with(|debugger| debugger.print(&line));
+                continue;
+            }
+            "q" | "quit" => anyhow::bail!("Quit from the debugger"),
+            _ => {
+                with(|debugger| debugger.print(HELP));
+                continue;
+            }
+        };
+        with(|debugger| debugger.stop_at = stop_at(debugger));
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+    use std::io::Cursor;
+
+    /// Collects what the debugger prints, to compare against.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn take(&self) -> String {
+            String::from_utf8(self.0.take()).unwrap()
+        }
+    }
+
+    #[test]
+    fn trace_and_break() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        let output = Output::default();
+        let commands = "l\np (* n 10)\ns\nn\no\nx\nc\n";
+        set_io(Cursor::new(commands), output.clone());
+
+        let interpreter = Interpreter::new();
+        interpreter
+            .eval_str("(defn fact [n] (if (= n 0) 1 (* n (fact (- n 1)))))")
+            .unwrap();
+
+        interpreter.eval_str("(trace fact)").unwrap();
+        assert_eq!(
+            interpreter.eval_str("(fact 2)").unwrap(),
+            MalType::Number(2)
+        );
+        assert_eq!(
+            output.take(),
+            "\
+(fact 2)
+| (fact 1)
+| | (fact 0)
+| | => 1
+| => 1
+=> 2
+"
+        );
+
+        interpreter.eval_str("(untrace fact) (break fact)").unwrap();
+        assert_eq!(
+            interpreter.eval_str("(fact 1)").unwrap(),
+            MalType::Number(1)
+        );
+        let body = "(if (= n 0) 1 (* n (fact (- n 1))))";
+        let printed = output.take();
+        let mut lines = printed.lines();
+        assert_eq!(
+            lines.next(),
+            Some(format!("Break in fact at {}", body).as_str())
+        );
+        assert_eq!(lines.next(), Some("debug> n = 1"));
+        assert!(lines.next().unwrap().starts_with("fact = "));
+        assert_eq!(lines.next(), Some("debug> 10"));
+        assert_eq!(lines.next(), Some("debug> At (= n 0)"));
+        assert_eq!(lines.next(), Some("debug> At (* n (fact (- n 1)))"));
+        // Stepping out of fact skips the rest of it, up to the recursive call
+        // hitting the breakpoint again
+        assert_eq!(
+            lines.next(),
+            Some(format!("debug> Break in fact at {}", body).as_str())
+        );
+        assert_eq!(
+            lines.next(),
+            Some(format!("debug> {}", HELP.lines().next().unwrap()).as_str())
+        );
+        assert_eq!(lines.last(), Some("debug> "));
+
+        interpreter.eval_str("(unbreak fact)").unwrap();
+        assert!(!enabled());
+        assert_eq!(
+            interpreter.eval_str("(fact 3)").unwrap(),
+            MalType::Number(6)
+        );
+        assert_eq!(output.take(), "");
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{
+    environment::Environment,
+    types::{hash_map_get, hash_map_insert, MalFn, MalType},
+};
+use anyhow::Result;
+use std::rc::Rc;
+
+/// `(def! name value)`, or `(def! name "docstring" value)`. The docstring and
+/// any metadata on `name` are added to the value's metadata.
+pub fn def(args: &[MalType], env: &mut Environment) -> Result<MalType> {
+    let (name, doc, form) = match args {
+        [name @ MalType::Symbol(..), form] => (name, None, form),
+        [name @ MalType::Symbol(..), MalType::String(doc), form] => (name, Some(doc), form),
+        _ => anyhow::bail!("def! expects a name and a value, received {:?}", args),
+    };
+
+    let mut value = crate::eval::eval(form, env)?;
+    let mut meta = definition_meta(name);
+    if let Some(doc) = doc {
+        hash_map_insert(
+            &mut meta,
+            MalType::Keyword("doc".into()),
+            MalType::String(doc.clone()),
+        );
+    }
+    if !meta.is_empty() {
+        value = annotate(value, meta)?;
+    }
+    if is_fn_form(form) {
+        value = with_source("def!", args, value);
+    }
+
+    env.set(name.clone(), value.clone());
+    Ok(value)
+}
+
+/// `(defn name "docstring"? {attrs}? [params] body...)`, or with a list per
+/// arity as `fn*` takes them. The docstring, attrs and `:arglists` go into
+/// the fn's metadata.
+pub fn defn(args: &[MalType], env: &mut Environment) -> Result<MalType> {
+    let (name, rest) = match args.split_first() {
+        Some((name @ MalType::Symbol(..), rest)) => (name, rest),
+        _ => anyhow::bail!("defn expects a name, received {:?}", args),
+    };
+    let (doc, rest) = match rest {
+        [MalType::String(doc), rest @ ..] if !rest.is_empty() => (Some(doc), rest),
+        rest => (None, rest),
+    };
+    let (attrs, rest) = match rest {
+        [MalType::HashMap(attrs, _), rest @ ..] if !rest.is_empty() => (attrs.as_slice(), rest),
+        rest => (&[][..], rest),
+    };
+
+    let mut fn_form = vec![
+        MalType::Symbol("fn*".into(), None),
+        MalType::Symbol(name.to_string().into(), None),
+    ];
+    fn_form.extend(rest.iter().cloned());
+    let value = crate::eval::eval(&MalType::List(fn_form, None), env)?;
+    let arglists = match &value {
+        MalType::Fn(f) => f.arities.iter().map(|arity| arity.params.clone()).collect(),
+        _ => vec![],
+    };
+
+    let mut meta = definition_meta(name);
+    for pair in attrs.chunks(2) {
+        hash_map_insert(&mut meta, pair[0].clone(), pair[1].clone());
+    }
+    if let Some(doc) = doc {
+        hash_map_insert(
+            &mut meta,
+            MalType::Keyword("doc".into()),
+            MalType::String(doc.clone()),
+        );
+    }
+    hash_map_insert(
+        &mut meta,
+        MalType::Keyword("arglists".into()),
+        MalType::List(arglists, None),
+    );
+    let value = with_source("defn", args, annotate(value, meta)?);
+
+    env.set(name.clone(), value.clone());
+    Ok(value)
+}
+
+/// The metadata the reader put on the name being defined.
+fn definition_meta(name: &MalType) -> Vec<MalType> {
+    match name.meta() {
+        Some(MalType::HashMap(meta, _)) => meta.clone(),
+        _ => vec![],
+    }
+}
+
+/// Merges `meta` over whatever metadata `value` already has.
+fn annotate(value: MalType, meta: Vec<MalType>) -> Result<MalType> {
+    let mut merged = match value.meta() {
+        Some(MalType::HashMap(existing, _)) => existing.clone(),
+        _ => vec![],
+    };
+    for pair in meta.chunks(2) {
+        hash_map_insert(&mut merged, pair[0].clone(), pair[1].clone());
+    }
+    value.with_meta(Some(Rc::new(MalType::HashMap(merged, None))))
+}
+
+fn is_fn_form(form: &MalType) -> bool {
+    match form {
+        MalType::List(items, _) => items.first() == Some(&MalType::Symbol("fn*".into(), None)),
+        _ => false,
+    }
+}
+
+/// Remembers `(head args...)` as the form a user fn was defined with.
+fn with_source(head: &str, args: &[MalType], value: MalType) -> MalType {
+    match value {
+        MalType::Fn(mut f) => {
+            let mut form = vec![MalType::Symbol(head.into(), None)];
+            form.extend(args.iter().cloned());
+            f.source = Some(Rc::new(MalType::List(form, None)));
+            MalType::Fn(f)
+        }
+        other => other,
+    }
+}
+
+/// `(doc name)` prints the arglists and docstring of what `name` is bound to.
+pub fn doc(args: &[MalType], env: &Environment) -> Result<MalType> {
+    let name = match args {
+        [MalType::Symbol(name, _)] => name,
+        _ => anyhow::bail!("doc expects a name, received {:?}", args),
+    };
+    let value = env
+        .get(name)
+        .ok_or_else(|| anyhow::anyhow!("{} not found", name))?;
+    println!("{}", describe(name, &value));
+    Ok(MalType::Nil)
+}
+
+/// The text `doc` prints for `value`, bound to `name`.
+pub fn describe(name: &str, value: &MalType) -> String {
+    let meta = |key: &str| match value.meta() {
+        Some(MalType::HashMap(meta, _)) => {
+            hash_map_get(meta, &MalType::Keyword(key.into())).cloned()
+        }
+        _ => None,
+    };
+
+    let mut lines = vec!["-".repeat(25), name.to_string()];
+    let arglists = meta("arglists").or_else(|| match value {
+        MalType::Fn(f) => Some(MalType::List(
+            f.arities.iter().map(|arity| arity.params.clone()).collect(),
+            None,
+        )),
+        _ => None,
+    });
+    if let Some(arglists) = arglists {
+        lines.push(format!("{:b}", arglists));
+    }
+    match value {
+        MalType::Bind(_) => lines.push("Special Form".to_string()),
+        MalType::Multi(_) => lines.push("Multimethod".to_string()),
+        MalType::Protocol(_) => lines.push("Protocol".to_string()),
+        _ => (),
+    }
+    if let Some(MalType::String(doc)) = meta("doc") {
+        lines.extend(doc.lines().map(|line| format!("  {}", line)));
+    }
+    lines.join("\n")
+}
+
+/// `(source name)` prints the form that defined the user fn bound to `name`.
+pub fn source(args: &[MalType], env: &Environment) -> Result<MalType> {
+    let name = match args {
+        [MalType::Symbol(name, _)] => name,
+        _ => anyhow::bail!("source expects a name, received {:?}", args),
+    };
+    match env.get(name) {
+        Some(MalType::Fn(MalFn {
+            source: Some(form), ..
+        })) => println!("{:b}", form.as_ref()),
+        Some(_) => println!("Source not found"),
+        None => anyhow::bail!("{} not found", name),
+    }
+    Ok(MalType::Nil)
+}
+
+/// `(apropos "str")` lists the names resolvable from here that contain the
+/// string, or match the regex, it is given.
+pub fn apropos(args: &[MalType], env: &Environment) -> Result<MalType> {
+    let matches: Box<dyn Fn(&str) -> bool> = match args {
+        [MalType::String(s)] => {
+            let s = s.clone();
+            Box::new(move |name| name.contains(&s))
+        }
+        [MalType::Regex(re)] => {
+            let re = re.clone();
+            Box::new(move |name| re.is_match(name))
+        }
+        _ => anyhow::bail!("apropos received unexpected value {:?}", args),
+    };
+
+    let names = env
+        .names()
+        .into_iter()
+        .filter(|name| matches(name))
+        .map(|name| MalType::Symbol(name.into(), None))
+        .collect();
+    Ok(MalType::List(names, None))
+}
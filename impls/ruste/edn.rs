@@ -0,0 +1,205 @@
+use crate::{
+    environment::Environment,
+    reader::{Lexer, Parser},
+    types::{hash_map_get, MalType},
+};
+use anyhow::Result;
+use regex::Regex;
+
+/// Handlers for tagged literals, taken from the `:readers` and `:default`
+/// entries of the options map given to `read-edn`.
+pub struct TagReaders {
+    readers: Vec<(String, MalType)>,
+    default: Option<MalType>,
+}
+
+impl TagReaders {
+    pub fn new() -> Self {
+        Self {
+            readers: vec![],
+            default: None,
+        }
+    }
+
+    pub fn from_opts(opts: &MalType) -> Result<Self> {
+        let mut s = Self::new();
+        let opts = match opts {
+            MalType::HashMap(opts, _) => opts,
+            other => anyhow::bail!("read-edn expected an options map, got {:b}", other),
+        };
+
+        if let Some(readers) = hash_map_get(opts, &MalType::Keyword("readers".into())) {
+            let readers = match readers {
+                MalType::HashMap(readers, _) => readers,
+                other => anyhow::bail!("read-edn expected :readers to be a map, got {:b}", other),
+            };
+            for pair in readers.chunks(2) {
+                let tag = match &pair[0] {
+                    MalType::String(tag) => tag.as_str(),
+                    MalType::Keyword(tag) | MalType::Symbol(tag, _) => tag.as_str(),
+                    other => anyhow::bail!("read-edn received invalid tag {:b}", other),
+                };
+                s.readers.push((tag.to_string(), pair[1].clone()));
+            }
+        }
+        s.default = hash_map_get(opts, &MalType::Keyword("default".into())).cloned();
+
+        Ok(s)
+    }
+
+    fn get(&self, tag: &str) -> Option<&MalType> {
+        self.readers
+            .iter()
+            .find(|(name, _)| name == tag)
+            .map(|(_, reader)| reader)
+    }
+}
+
+/// Reads the first form of `input` as data. Nothing is evaluated, only tag
+/// handlers are called.
+pub fn read(input: &str, readers: &TagReaders, env: &Environment) -> Result<MalType> {
+    let mut parser = Parser::new(Lexer::tokenize(input)).edn();
+    match parser.parse()?.into_iter().next() {
+        Some(form) => resolve_tags(form, readers, env),
+        None => Ok(MalType::Nil),
+    }
+}
+
+/// Prints a data value as EDN, failing on values EDN can't express.
+pub fn print(value: &MalType) -> Result<String> {
+    crate::lazy::realize(value)?;
+    check_printable(value)?;
+    Ok(format!("{:b}", value))
+}
+
+fn resolve_tags(form: MalType, readers: &TagReaders, env: &Environment) -> Result<MalType> {
+    let resolve_all = |items: Vec<MalType>| -> Result<Vec<MalType>> {
+        items
+            .into_iter()
+            .map(|item| resolve_tags(item, readers, env))
+            .collect()
+    };
+
+    let form = match form {
+        MalType::List(items, _) => MalType::List(resolve_all(items)?, None),
+        MalType::Vector(items, _) => MalType::Vector(resolve_all(items)?, None),
+        MalType::Set(items, _) => MalType::Set(resolve_all(items)?, None),
+        MalType::HashMap(items, _) => MalType::HashMap(resolve_all(items)?, None),
+        MalType::Tagged(tag, value) => {
+            let value = resolve_tags(*value, readers, env)?;
+            if let Some(reader) = readers.get(&tag) {
+                reader.clone().eval(&[value], env)?
+            } else if tag == "inst" || tag == "uuid" {
+                check_builtin_tag(&tag, &value)?;
+                MalType::Tagged(tag, Box::new(value))
+            } else if let Some(default) = &readers.default {
+                default
+                    .clone()
+                    .eval(&[MalType::Symbol(tag.into(), None), value], env)?
+            } else {
+                MalType::Tagged(tag, Box::new(value))
+            }
+        }
+        other => other,
+    };
+    Ok(form)
+}
+
+fn check_builtin_tag(tag: &str, value: &MalType) -> Result<()> {
+    let pattern = match tag {
+        "inst" => r"^\d{4}(-\d{2}(-\d{2}(T\d{2}:\d{2}(:\d{2}(\.\d+)?)?(Z|[+-]\d{2}:\d{2})?)?)?)?$",
+        _ => r"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$",
+    };
+
+    match value {
+        MalType::String(s) if Regex::new(pattern)?.is_match(s) => Ok(()),
+        other => anyhow::bail!("read-edn: invalid #{} value {:b}", tag, other),
+    }
+}
+
+fn check_printable(value: &MalType) -> Result<()> {
+    match value {
+        MalType::List(items, _)
+        | MalType::Vector(items, _)
+        | MalType::Set(items, _)
+        | MalType::HashMap(items, _) => items.iter().try_for_each(check_printable),
+        MalType::Record(record) => record.entries().iter().try_for_each(check_printable),
+        MalType::Tagged(_, value) => check_printable(value),
+        MalType::Lazy(seq) => seq.cached().0.iter().try_for_each(check_printable),
+        MalType::String(_)
+        | MalType::Symbol(..)
+        | MalType::Keyword(_)
+        | MalType::Number(_)
+        | MalType::Float(_)
+        | MalType::Bool(_)
+        | MalType::Nil => Ok(()),
+        other => anyhow::bail!("pr-edn: {} has no EDN representation", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let env = Environment::new();
+        let edn = r#"{:id #uuid "0f0c6a5e-3b1c-4c7e-9d2a-6f1e2b3c4d5e" :tags #{:a :b} :body (x "y" 1.5)}"#;
+
+        let value = read(edn, &TagReaders::new(), &env).unwrap();
+
+        assert_eq!(print(&value).unwrap(), edn);
+
+        let value = read("[##Inf ##-Inf]", &TagReaders::new(), &env).unwrap();
+        assert_eq!(print(&value).unwrap(), "[##Inf ##-Inf]");
+        assert_eq!(value.to_string(), "[Infinity -Infinity]");
+        let nan = read("##NaN", &TagReaders::new(), &env).unwrap();
+        assert_eq!(
+            (format!("{:b}", nan), nan.to_string()),
+            ("##NaN".into(), "NaN".into())
+        );
+    }
+
+    #[test]
+    fn discard_and_sets() {
+        let env = Environment::new();
+
+        let value = read("#{3 #_ 4 1 2}", &TagReaders::new(), &env).unwrap();
+
+        assert_eq!(
+            value,
+            MalType::Set(
+                vec![MalType::Number(1), MalType::Number(2), MalType::Number(3)],
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn reader_macros() {
+        let env = Environment::new();
+        let error = |input: &str| {
+            read(input, &TagReaders::new(), &env)
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(error("@a"), "read-edn: @ is not valid EDN");
+        assert_eq!(error("[1 ^:m x]"), "read-edn: ^ is not valid EDN");
+        assert_eq!(error("'a"), "read-edn: ' is not valid EDN");
+        assert_eq!(error("`(a ~b)"), "read-edn: ` is not valid EDN");
+        assert_eq!(error("(~@a)"), "read-edn: ~@ is not valid EDN");
+        assert_eq!(
+            error(r#"{:re #"a+"}"#),
+            r#"read-edn: #"a+" is not valid EDN"#
+        );
+        // Still fine inside symbols and strings
+        assert_eq!(
+            read(r#"[a@b "'@^"]"#, &TagReaders::new(), &env).unwrap(),
+            MalType::Vector(
+                vec![MalType::Symbol("a@b".into(), None), "'@^".into()],
+                None
+            )
+        );
+    }
+}
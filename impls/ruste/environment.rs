@@ -1,101 +1,376 @@
 use log::{debug, trace};
 
 use crate::expr::Expressions;
-use crate::types::MalType;
-use std::{
-    cell::{OnceCell, Ref, RefCell},
-    fmt::Debug,
-    rc::Rc,
-    sync::Once,
-};
+use crate::intern::{Sym, AMP, AS, KEYS, OR, STRS, UNDERSCORE};
+use crate::lazy::{self, SeqIter};
+use crate::namespace::{Namespaces, DEFAULT_NS};
+use crate::types::{hash_map_get, MalType};
+use anyhow::Result;
+use std::{cell::RefCell, fmt::Debug, rc::Rc};
 
 pub struct Environment {
-    inner: Rc<RefCell<InnerEnv>>,
-    default_ns: Rc<Expressions>,
+    inner: Frame,
+    default_ns: Rc<RefCell<Expressions>>,
+    namespaces: Rc<RefCell<Namespaces>>,
+    ns: Rc<str>,
 }
 
+/// A scope, shared by every environment and closure that entered it.
+pub type Frame = Rc<RefCell<InnerEnv>>;
+
 impl Debug for Environment {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        Debug::fmt(&self.inner.as_ref().borrow(), f)
+        // Only names are shown: a namespace's functions capture the very
+        // scope holding them, so printing values would never end.
+        let mut scopes = vec![];
+        let mut scope = Some(Rc::clone(&self.inner));
+        while let Some(inner) = scope {
+            scopes.push(inner.borrow().names());
+            scope = inner.borrow().outer.clone();
+        }
+        f.debug_struct("Environment")
+            .field("ns", &self.ns)
+            .field("scopes", &scopes)
+            .finish()
     }
 }
 
-struct InnerEnv {
+pub struct InnerEnv {
     outer: Option<Rc<RefCell<InnerEnv>>>,
     expressions: Expressions,
 }
 
-impl Debug for InnerEnv {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "InnerEnv: ")?;
-        if let Some(outer) = self.outer.as_ref() {
-            let outer: Ref<InnerEnv> = outer.borrow();
-            writeln!(f, "\tOuter: {:?}", outer)?;
-        }
-        writeln!(f, "\t{:?}", self.expressions)
-    }
-}
-
 impl Clone for Environment {
     fn clone(&self) -> Self {
         Self {
             inner: Rc::clone(&self.inner),
             default_ns: Rc::clone(&self.default_ns),
+            namespaces: Rc::clone(&self.namespaces),
+            ns: Rc::clone(&self.ns),
         }
     }
 }
 
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Environment {
     pub fn new() -> Self {
+        let namespaces = Namespaces::new();
         Self {
-            inner: InnerEnv::new(Expressions::new()),
-            default_ns: Rc::new(Expressions::new_default()),
+            inner: Rc::clone(namespaces.frame(DEFAULT_NS).unwrap()),
+            default_ns: Rc::new(RefCell::new(Expressions::new_default())),
+            namespaces: Rc::new(RefCell::new(namespaces)),
+            ns: Rc::from(DEFAULT_NS),
         }
     }
 
-    pub fn from(outer: Environment, keys: MalType, values: &[MalType]) -> Environment {
+    /// The top level of whichever namespace `ns` last switched to, which is
+    /// where the REPL and loaded files evaluate each form.
+    pub fn current_ns(&self) -> Environment {
+        let namespaces = self.namespaces.borrow();
+        let ns = namespaces.current();
+        Self {
+            inner: Rc::clone(namespaces.frame(ns).unwrap()),
+            default_ns: Rc::clone(&self.default_ns),
+            namespaces: Rc::clone(&self.namespaces),
+            ns: Rc::from(ns),
+        }
+    }
+
+    /// The namespace this environment's top level belongs to.
+    pub fn ns_name(&self) -> &str {
+        &self.ns
+    }
+
+    pub fn namespaces(&self) -> &RefCell<Namespaces> {
+        &self.namespaces
+    }
+
+    /// The namespaces as shared by every environment of the interpreter, for
+    /// the collector to tell them apart.
+    pub fn shared_namespaces(&self) -> &Rc<RefCell<Namespaces>> {
+        &self.namespaces
+    }
+
+    /// The innermost scope of this environment.
+    pub fn frame(&self) -> &Frame {
+        &self.inner
+    }
+
+    pub fn from(outer: Environment, keys: MalType, values: &[MalType]) -> Result<Environment> {
         let inner = InnerEnv::new(Expressions::new());
         inner.as_ref().borrow_mut().enter(outer.inner);
         let mut s = Self {
             inner,
             default_ns: outer.default_ns,
+            namespaces: outer.namespaces,
+            ns: outer.ns,
         };
 
         match keys {
-            MalType::List(inner) => {
-                for i in 0..inner.len() {
-                    if inner[i] == "&" {
-                        let values = values[i..].to_vec();
-                        s.set(inner[i + 1].clone(), MalType::List(values));
-                        break;
-                    } else {
-                        s.set(inner[i].clone(), values[i].clone());
-                    }
+            MalType::List(keys, _) | MalType::Vector(keys, _) => {
+                s.bind_seq(&keys, MalType::List(values.to_vec(), None))?
+            }
+            other => anyhow::bail!("Expected a parameter list, got {:b}", other),
+        }
+
+        Ok(s)
+    }
+
+    /// Binds `value` to a destructuring `pattern`: a symbol, `_` to ignore the
+    /// value, a vector of patterns with optional `& rest` and `:as whole`, or a
+    /// map pattern using `:keys`, `:strs`, `:or` and `:as`.
+    pub fn bind(&mut self, pattern: &MalType, value: MalType) -> Result<()> {
+        match pattern {
+            MalType::Symbol(symbol, _) if *symbol == *UNDERSCORE => Ok(()),
+            MalType::Symbol(..) => {
+                self.set(pattern.clone(), value);
+                Ok(())
+            }
+            MalType::Vector(patterns, _) | MalType::List(patterns, _) => {
+                self.bind_seq(patterns, value)
+            }
+            MalType::HashMap(patterns, _) => self.bind_map(patterns, value),
+            other => anyhow::bail!("Cannot bind to {:b}, expected a symbol or pattern", other),
+        }
+    }
+
+    fn bind_seq(&mut self, patterns: &[MalType], value: MalType) -> Result<()> {
+        // Lists and vectors are sliced, so `& rest` stays a list. Anything else
+        // is walked a step at a time, which keeps lazy rests lazy.
+        let mut items = match &value {
+            MalType::List(items, _) | MalType::Vector(items, _) => Some(items.as_slice()),
+            MalType::Nil => Some(&[][..]),
+            _ => None,
+        };
+        let mut current = value.clone();
+        if items.is_none() && SeqIter::new(&value).is_err() {
+            anyhow::bail!(
+                "Cannot destructure {:b} with {:b}",
+                value,
+                MalType::Vector(patterns.to_vec(), None)
+            )
+        }
+
+        let mut i = 0;
+        while i < patterns.len() {
+            match &patterns[i] {
+                pattern
+                    if pattern.is_symbol(*AMP)
+                        || matches!(pattern, MalType::Keyword(as_) if *as_ == *AS) =>
+                {
+                    let target = patterns.get(i + 1).ok_or(anyhow::anyhow!(
+                        "Expected a binding after {} in {:b}",
+                        pattern,
+                        MalType::Vector(patterns.to_vec(), None)
+                    ))?;
+                    let value = match pattern.is_symbol(*AMP) {
+                        true => match items {
+                            Some(items) => MalType::List(items.to_vec(), None),
+                            None => current.clone(),
+                        },
+                        false => value.clone(),
+                    };
+                    self.bind(target, value)?;
+                    i += 2;
+                }
+                pattern => {
+                    let first = match &mut items {
+                        Some(rest) => match rest.split_first() {
+                            Some((first, rest)) => {
+                                items = Some(rest);
+                                first.clone()
+                            }
+                            None => MalType::Nil,
+                        },
+                        None => match lazy::step(&current)? {
+                            Some((first, rest)) => {
+                                current = rest;
+                                first
+                            }
+                            None => MalType::Nil,
+                        },
+                    };
+                    self.bind(pattern, first)?;
+                    i += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn bind_map(&mut self, patterns: &[MalType], value: MalType) -> Result<()> {
+        let map = match &value {
+            MalType::HashMap(map, _) => map.clone(),
+            MalType::Record(record) => record.entries().to_vec(),
+            MalType::Nil => vec![],
+            // Variadic keyword arguments, as in `[& {:keys [a]}]`
+            MalType::List(items, _) if items.len().is_multiple_of(2) => items.clone(),
+            other => anyhow::bail!(
+                "Cannot destructure {:b} with {:b}",
+                other,
+                MalType::HashMap(patterns.to_vec(), None)
+            ),
+        };
+        if !patterns.len().is_multiple_of(2) {
+            anyhow::bail!(
+                "Map pattern {:b} needs an even number of forms",
+                MalType::HashMap(patterns.to_vec(), None)
+            )
+        }
+
+        let defaults = match hash_map_get(patterns, &MalType::Keyword(*OR)) {
+            Some(MalType::HashMap(defaults, _)) => defaults.clone(),
+            Some(other) => anyhow::bail!("Expected a map after :or, got {:b}", other),
+            None => vec![],
+        };
+
+        for pair in patterns.chunks(2) {
+            let (pattern, key) = (&pair[0], &pair[1]);
+            let bindings: Vec<(MalType, MalType)> = match pattern {
+                MalType::Keyword(kind) if *kind == *KEYS || *kind == *STRS => {
+                    let symbols = match key {
+                        MalType::Vector(symbols, _) | MalType::List(symbols, _) => symbols,
+                        other => {
+                            anyhow::bail!("Expected a vector after :{}, got {:b}", kind, other)
+                        }
+                    };
+                    symbols
+                        .iter()
+                        .map(|symbol| {
+                            let name = symbol.to_string();
+                            let key = if *kind == *KEYS {
+                                MalType::Keyword(name.into())
+                            } else {
+                                MalType::String(name)
+                            };
+                            (symbol.clone(), key)
+                        })
+                        .collect()
+                }
+                MalType::Keyword(kind) if *kind == *AS => {
+                    self.bind(key, value.clone())?;
+                    continue;
                 }
+                MalType::Keyword(kind) if *kind == *OR => continue,
+                pattern => vec![(pattern.clone(), key.clone())],
+            };
+
+            for (pattern, key) in bindings {
+                let value = match hash_map_get(&map, &key) {
+                    Some(value) => value.clone(),
+                    None => match hash_map_get(&defaults, &pattern) {
+                        Some(default) => crate::eval::eval(default, self)?,
+                        None => MalType::Nil,
+                    },
+                };
+                self.bind(&pattern, value)?;
             }
-            _ => panic!("Environment::from received non list in keys"),
         }
+        Ok(())
+    }
 
-        s
+    /// Adds `name` next to the core functions, resolvable from every namespace
+    /// unless shadowed there.
+    pub fn set_core(&self, name: &str, value: MalType) {
+        self.default_ns.borrow_mut().set(Sym::new(name), value);
+    }
+
+    /// Takes `name` out of the core functions, returning what it was bound to.
+    pub fn remove_core(&self, name: &str) -> Option<MalType> {
+        self.default_ns.borrow_mut().remove(Sym::new(name))
     }
 
     pub fn set(&mut self, key: MalType, value: MalType) {
         trace!("Setting: {:?} -> {:?}", key, value);
         match key {
-            MalType::Symbol(symbol) => self.inner.as_ref().borrow_mut().set(symbol, value),
+            MalType::Symbol(symbol, _) => self.inner.as_ref().borrow_mut().set(symbol, value),
             key => panic!("Called set with not symbol {:?}", key),
         }
     }
 
-    pub fn get(&self, s: &str) -> Option<MalType> {
-        if let Some(core_fn) = self.default_ns.get(s).cloned() {
-            return Some(core_fn);
-        }
-        let ret = self.inner.borrow().get(s);
-        trace!("getting: {:?} -> {:?}", s, ret);
+    pub fn get(&self, name: impl Into<Sym>) -> Option<MalType> {
+        let sym = name.into();
+        // Local bindings shadow referred names, which shadow the core functions
+        let ret = match sym.qualified() {
+            Some((ns, name)) => self.get_qualified(ns, name),
+            _ => self
+                .inner
+                .borrow()
+                .get(sym)
+                .or_else(|| self.get_referred(sym))
+                .or_else(|| self.default_ns.borrow().get(sym).cloned()),
+        };
+        trace!("getting: {:?} -> {:?}", sym, ret);
         ret
     }
 
+    /// Looks up `ns/name`, where `ns` is an alias or a full namespace name.
+    fn get_qualified(&self, ns: Sym, name: Sym) -> Option<MalType> {
+        let namespaces = self.namespaces.borrow();
+        let ns = namespaces.resolve_alias(&self.ns, ns.as_str());
+        let value = namespaces.frame(ns)?.borrow().get(name);
+        value
+    }
+
+    fn get_referred(&self, sym: Sym) -> Option<MalType> {
+        let namespaces = self.namespaces.borrow();
+        let (ns, name) = namespaces.referred(&self.ns, sym)?;
+        let value = namespaces.frame(ns)?.borrow().get(name);
+        value
+    }
+
+    /// Every name resolvable from here, sorted: those in the enclosing scopes,
+    /// referred names, the core functions, and the names of the other
+    /// namespaces qualified as `ns/name`.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = vec![];
+        let mut scope = Some(Rc::clone(&self.inner));
+        while let Some(inner) = scope {
+            names.extend(inner.borrow().names());
+            scope = inner.borrow().outer.clone();
+        }
+
+        let namespaces = self.namespaces.borrow();
+        names.extend(namespaces.refers(&self.ns));
+        names.extend(self.default_ns.borrow().names());
+        for ns in namespaces.names().into_iter().filter(|ns| *ns != &*self.ns) {
+            let frame = namespaces.frame(ns).unwrap().borrow();
+            names.extend(frame.names().iter().map(|name| format!("{}/{}", ns, name)));
+        }
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// The bindings of the scopes inside the namespace's top level, innermost
+    /// first, leaving out the ones they shadow.
+    pub fn locals(&self) -> Vec<(String, MalType)> {
+        let namespaces = self.namespaces.borrow();
+        let top = namespaces.frame(&self.ns);
+        let mut locals: Vec<(String, MalType)> = vec![];
+        let mut scope = Some(Rc::clone(&self.inner));
+        while let Some(inner) = scope {
+            if top.is_some_and(|top| Rc::ptr_eq(top, &inner)) {
+                break;
+            }
+            let inner = inner.borrow();
+            for name in inner.names() {
+                if !locals.iter().any(|(local, _)| *local == name) {
+                    let value = inner.expressions.get(Sym::new(&name)).cloned().unwrap();
+                    locals.push((name, value));
+                }
+            }
+            scope = inner.outer.clone();
+        }
+        locals
+    }
+
     pub fn enter(&mut self) {
         debug!("entering scope");
         let new_inner = InnerEnv::new(Expressions::new());
@@ -108,7 +383,7 @@ impl Environment {
 
     pub fn exit(&mut self) {
         debug!("exiting scope");
-        let outer = self.inner.as_ref().borrow_mut().exit();
+        let outer = self.inner.as_ref().borrow().exit();
         if let Some(outer) = outer {
             self.inner = outer;
         }
@@ -116,30 +391,60 @@ impl Environment {
 }
 
 impl InnerEnv {
-    fn new(expressions: Expressions) -> Rc<RefCell<Self>> {
-        Rc::new(RefCell::new(InnerEnv {
+    pub fn new(expressions: Expressions) -> Rc<RefCell<Self>> {
+        let frame = Rc::new(RefCell::new(InnerEnv {
             outer: None,
             expressions,
-        }))
+        }));
+        crate::gc::track(&frame);
+        frame
     }
 
     fn enter(&mut self, outer: Rc<RefCell<Self>>) {
         self.outer = Some(outer);
     }
 
-    fn exit(&mut self) -> Option<Rc<RefCell<InnerEnv>>> {
-        self.outer.take()
+    fn exit(&self) -> Option<Rc<RefCell<InnerEnv>>> {
+        // Closures may still hold on to this scope, so it keeps its outer link
+        self.outer.clone()
     }
 
-    fn set(&mut self, key: String, value: MalType) {
+    fn set(&mut self, key: Sym, value: MalType) {
         self.expressions.set(key, value);
     }
 
-    fn get(&self, s: &str) -> Option<MalType> {
-        if let Some(expr) = self.expressions.get(s).cloned() {
+    /// Names bound in this scope itself, not in the scopes around it.
+    pub fn names(&self) -> Vec<String> {
+        self.expressions.names()
+    }
+
+    pub fn outer(&self) -> Option<&Frame> {
+        self.outer.as_ref()
+    }
+
+    /// The values bound in this scope itself.
+    pub fn values(&self) -> impl Iterator<Item = &MalType> {
+        self.expressions.values()
+    }
+
+    /// Unbinds everything and lets go of the scope around, returning them to
+    /// be dropped once nothing is borrowed.
+    pub fn clear(&mut self) -> (Expressions, Option<Frame>) {
+        (
+            std::mem::replace(&mut self.expressions, Expressions::new()),
+            self.outer.take(),
+        )
+    }
+
+    pub fn defines(&self, s: &str) -> bool {
+        self.expressions.get(Sym::new(s)).is_some()
+    }
+
+    fn get(&self, sym: Sym) -> Option<MalType> {
+        if let Some(expr) = self.expressions.get(sym).cloned() {
             Some(expr)
         } else {
-            self.outer.as_ref().map(|x| x.borrow().get(s)).flatten()
+            self.outer.as_ref().and_then(|x| x.borrow().get(sym))
         }
     }
 }
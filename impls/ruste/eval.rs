@@ -1,19 +1,27 @@
-use crate::{environment::Environment, types::MalType};
+use crate::{
+    environment::Environment,
+    types::{MalType, Meta},
+};
 use anyhow::Result;
 use log::{debug, trace};
+use std::rc::Rc;
 
 pub fn eval(ast: &MalType, env: &mut Environment) -> Result<MalType> {
     debug!("Eval: ast: {:?}", ast);
     trace!("Eval: ast: {:?}, env: {:?}", ast, env);
+    crate::interrupt::check()?;
+    crate::sandbox::tick()?;
+    let _depth = crate::sandbox::enter()?;
+    let _step = crate::debug::step(ast, env)?;
     let ret = match ast {
-        MalType::List(inner) => {
-            if inner.len() == 0 {
+        MalType::List(inner, _) => {
+            if inner.is_empty() {
                 trace!("Eval: empty list");
                 return Ok(ast.clone());
             }
 
             let bind = {
-                if let MalType::Symbol(sym) = &inner[0] {
+                if let MalType::Symbol(sym, _) = &inner[0] {
                     env.get(sym)
                 } else {
                     None
@@ -27,10 +35,11 @@ pub fn eval(ast: &MalType, env: &mut Environment) -> Result<MalType> {
             } else {
                 trace!("Eval: eval list");
                 match eval_ast(ast, env)? {
-                    MalType::List(inner) => {
+                    MalType::List(inner, _) => {
                         trace!("Eval->eval_ast: eval list: {:?}", inner);
                         let func = inner[0].clone();
                         func.eval(&inner[1..], env)
+                            .map_err(|err| crate::stack::unwind(err, &inner[0], ast))
                     }
                     _ => anyhow::bail!("Expected a list"),
                 }
@@ -39,6 +48,9 @@ pub fn eval(ast: &MalType, env: &mut Environment) -> Result<MalType> {
         _ => eval_ast(ast, env),
     };
     trace!("Eval: ret: {:?}", ret);
+    if let Ok(value) = &ret {
+        crate::sandbox::check_value(value)?;
+    }
     ret
 }
 
@@ -46,35 +58,61 @@ fn eval_ast(ast: &MalType, env: &mut Environment) -> Result<MalType> {
     debug!("EvalAst: ast: {:?}", ast);
     trace!("EvalAst: ast: {:?}, env: {:?}", ast, env);
     let ret = match ast {
-        MalType::List(inner) => {
+        MalType::List(inner, _) => {
             let mut list = vec![];
             for item in inner {
                 list.push(eval(item, env)?);
             }
-            Ok(MalType::List(list))
+            Ok(MalType::List(list, None))
         }
-        MalType::HashMap(map) => {
+        MalType::HashMap(map, meta) => {
             let mut list = vec![];
-            for item in map.windows(2) {
-                list.push(item[0].clone());
-                list.push(eval(&item[1], env)?);
+            for pair in map.chunks(2) {
+                match pair {
+                    [key, value] => {
+                        list.push(key.clone());
+                        list.push(eval(value, env)?);
+                    }
+                    _ => anyhow::bail!("Map literal must contain an even number of forms"),
+                }
             }
-            Ok(MalType::HashMap(list))
+            Ok(MalType::HashMap(list, eval_meta(meta, env)?))
         }
-        MalType::Vector(inner) => {
+        MalType::Vector(inner, meta) => {
             let mut list = vec![];
             for item in inner {
                 list.push(eval(item, env)?);
             }
-            Ok(MalType::Vector(list))
+            Ok(MalType::Vector(list, eval_meta(meta, env)?))
+        }
+        MalType::Set(inner, meta) => {
+            let mut set = vec![];
+            for item in inner {
+                let item = eval(item, env)?;
+                if set.contains(&item) {
+                    anyhow::bail!("Duplicate item in set literal: {:b}", item)
+                }
+                set.push(item);
+            }
+            Ok(MalType::Set(set, eval_meta(meta, env)?))
         }
-        MalType::Symbol(sym) => env.get(sym).ok_or(anyhow::anyhow!("{} not found", sym)),
+        MalType::Symbol(sym, _) => env
+            .get(sym)
+            .ok_or_else(|| anyhow::anyhow!("{} not found", sym)),
         _ => Ok(ast.clone()),
     };
     trace!("EvalAst: ret: {:?}", ret);
     ret
 }
 
+/// Metadata on a literal is evaluated along with it, as in `^{:id (next-id)} []`.
+fn eval_meta(meta: &Meta, env: &mut Environment) -> Result<Meta> {
+    match meta {
+        Some(meta) => Ok(Some(Rc::new(eval(meta, env)?))),
+        None => Ok(None),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::vec;
@@ -85,22 +123,24 @@ mod tests {
 
     #[test]
     fn apply_list() {
-        env_logger::builder()
+        let _ = env_logger::builder()
             .filter_level(log::LevelFilter::Trace)
-            .init();
-        let ast = MalType::List(vec![MalType::Symbol(String::from("list"))]);
+            .is_test(true)
+            .try_init();
+        let ast = MalType::List(vec![MalType::Symbol("list".into(), None)], None);
         let mut env = Environment::new();
 
         let r = eval(&ast, &mut env).unwrap();
 
-        assert_eq!(r, MalType::List(vec![]))
+        assert_eq!(r, MalType::List(vec![], None))
     }
 
     #[test]
     fn test_closures() {
-        env_logger::builder()
+        let _ = env_logger::builder()
             .filter_level(log::LevelFilter::Trace)
-            .init();
+            .is_test(true)
+            .try_init();
         // (fn* (b) (+ a b))
         // (fn* (a) (fn* (b) (+ a b)))
         // (  5)
@@ -118,14 +158,721 @@ mod tests {
 
     #[test]
     fn pr_str() {
-        env_logger::builder()
+        let _ = env_logger::builder()
             .filter_level(log::LevelFilter::Trace)
-            .init();
-        let ast = MalType::List(vec![MalType::Symbol(String::from("pr-str"))]);
+            .is_test(true)
+            .try_init();
+        let ast = MalType::List(vec![MalType::Symbol("pr-str".into(), None)], None);
         let mut env = Environment::new();
 
         let r = eval(&ast, &mut env).unwrap();
 
         assert_eq!(r, MalType::String(String::new()))
     }
+
+    #[test]
+    fn regex_captures() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize(r#"(re-seq #"(\w+)=(\d+)" "a=1, b=22")"#);
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        let r = eval(&ast[0], &mut env).unwrap();
+
+        let pair = |k: &str, v: &str| {
+            MalType::Vector(
+                vec![
+                    MalType::String(format!("{}={}", k, v)),
+                    MalType::String(k.to_string()),
+                    MalType::String(v.to_string()),
+                ],
+                None,
+            )
+        };
+        assert_eq!(
+            r,
+            MalType::List(vec![pair("a", "1"), pair("b", "22")], None)
+        )
+    }
+
+    #[test]
+    fn map_literals() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let read = |input: &str| {
+            Parser::new(Lexer::tokenize(input))
+                .parse()
+                .map_err(|err| err.to_string())
+        };
+
+        assert_eq!(
+            read("{:a}").unwrap_err(),
+            "Map literal must contain an even number of forms"
+        );
+        assert_eq!(
+            read("{:a 1 :a 2}").unwrap_err(),
+            "Duplicate key in map literal: :a"
+        );
+        // Values may repeat, and so may keys of nested maps
+        let ast = read("{:a 1 :b 1 :c {:a 1}}").unwrap();
+        let mut env = Environment::new();
+        assert_eq!(eval(&ast[0], &mut env).unwrap(), ast[0]);
+    }
+
+    #[test]
+    fn set_operations() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize("(difference (union #{1 2} (set (list 2 3 3))) #{(+ 1 1)})");
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        let r = eval(&ast[0], &mut env).unwrap();
+
+        assert_eq!(
+            r,
+            MalType::Set(vec![MalType::Number(3), MalType::Number(1)], None)
+        );
+
+        let mut eval_str = |input: &str| {
+            let ast = Parser::new(Lexer::tokenize(input)).parse().unwrap();
+            eval(&ast[0], &mut env).unwrap()
+        };
+        // Any sequence makes a set, and fns are only equal to themselves
+        assert_eq!(eval_str("(count (set (range 3)))"), MalType::Number(3));
+        assert_eq!(
+            eval_str("(count (set (map (fn* [x] (+ x 1)) [1 1])))"),
+            MalType::Number(1)
+        );
+        assert_eq!(
+            eval_str("(count (set [(fn* [] 1) (fn* [] 2)]))"),
+            MalType::Number(2)
+        );
+        assert_eq!(eval_str("(count #{+ -})"), MalType::Number(2));
+        assert_eq!(eval_str("[(= + -) (= + +)]"), eval_str("[false true]"));
+    }
+
+    #[test]
+    fn set_arity() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let mut env = Environment::new();
+        let mut error = |input: &str| {
+            let ast = Parser::new(Lexer::tokenize(input)).parse().unwrap();
+            eval(&ast[0], &mut env).unwrap_err().to_string()
+        };
+
+        assert_eq!(
+            error("(set)"),
+            "Wrong number of args (0) passed to set expecting 1"
+        );
+        assert_eq!(
+            error("(contains? #{})"),
+            "Wrong number of args (1) passed to contains? expecting 2"
+        );
+        assert_eq!(
+            error("(intersection)"),
+            "Wrong number of args (0) passed to intersection expecting at least 1"
+        );
+        assert_eq!(error("(set 1)"), "1 is not a sequence");
+        assert_eq!(
+            error("(+ 1 2 3)"),
+            "Wrong number of args (3) passed to + expecting 2"
+        );
+        assert_eq!(
+            error("(reduce + [])"),
+            "Wrong number of args (0) passed to + expecting 2"
+        );
+        assert_eq!(
+            error("(reduce + 0 [1] [2])"),
+            "Wrong number of args (4) passed to reduce expecting 2 to 3"
+        );
+        assert_eq!(
+            error("(range 1 \"a\")"),
+            "range expects a number as argument 2, got \"a\""
+        );
+        assert_eq!(
+            error("(get {})"),
+            "Wrong number of args (1) passed to get expecting 2 to 3"
+        );
+        assert_eq!(
+            error("(assoc {} :a 1 :b)"),
+            "assoc expects a value for every key"
+        );
+        assert_eq!(
+            error("(union #{} [])"),
+            "union expects a set as argument 2, got []"
+        );
+        assert_eq!(
+            error("(with-meta [] 1)"),
+            "with-meta expects metadata to be a map or nil, got 1"
+        );
+        assert_eq!(
+            error("(count)"),
+            "Wrong number of args (0) passed to count expecting 1"
+        );
+        assert_eq!(
+            error("(empty?)"),
+            "Wrong number of args (0) passed to empty? expecting 1"
+        );
+        assert_eq!(
+            error("(if true)"),
+            "Wrong number of args (1) passed to if expecting 2 to 3"
+        );
+    }
+
+    #[test]
+    fn lazy_sequences() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize("(take 5 (filter even? (range)))");
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        let r = eval(&ast[0], &mut env).unwrap();
+
+        assert_eq!(
+            r,
+            MalType::List((0..5).map(|x| MalType::Number(x * 2)).collect(), None)
+        )
+    }
+
+    #[test]
+    fn lazy_errors() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let mut env = Environment::new();
+        let mut eval_str = |input: &str| {
+            let ast = Parser::new(Lexer::tokenize(input)).parse().unwrap();
+            eval(&ast[0], &mut env).map_err(|err| err.to_string())
+        };
+
+        assert_eq!(
+            eval_str("(filter even?)").unwrap_err(),
+            "Wrong number of args (1) passed to filter expecting 2"
+        );
+        assert_eq!(
+            eval_str("(map even?)").unwrap_err(),
+            "Wrong number of args (1) passed to map expecting at least 2"
+        );
+        assert_eq!(
+            eval_str("(cycle)").unwrap_err(),
+            "Wrong number of args (0) passed to cycle expecting 1"
+        );
+        // A seq failing to realize is an error, not unequal
+        assert_eq!(
+            eval_str("(= (map (fn* [x] (no-such-fn x)) [1]) [1])").unwrap_err(),
+            "no-such-fn not found"
+        );
+        assert_eq!(
+            eval_str("(= (map (fn* [x] (+ x 1)) [1 2]) [2 3])"),
+            Ok(MalType::Bool(true))
+        );
+    }
+
+    #[test]
+    fn sequence_library() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize(
+            "(map (fn* (entry) (reduce + (first (rest entry)))) (sort-by first (group-by even? [1 2 3 4 5])))",
+        );
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        let r = eval(&ast[0], &mut env).unwrap();
+
+        assert_eq!(
+            r,
+            MalType::List(vec![MalType::Number(9), MalType::Number(6)], None)
+        )
+    }
+
+    #[test]
+    fn distinct_items() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Info)
+            .is_test(true)
+            .try_init();
+        let mut env = Environment::new();
+        let mut eval_str = |input: &str| {
+            let ast = Parser::new(Lexer::tokenize(input)).parse().unwrap();
+            eval(&ast[0], &mut env).map_err(|err| err.to_string())
+        };
+
+        assert_eq!(
+            eval_str("(count (distinct (take 4000 (cycle (range 1000)))))"),
+            Ok(MalType::Number(1000))
+        );
+        // Equal values hash alike, whatever kind of sequence or set order
+        assert_eq!(
+            eval_str("(count (distinct [[1 2] (map (fn* [x] x) [1 2]) #{1 2} #{2 1} -0.0 0.0]))"),
+            Ok(MalType::Number(3))
+        );
+        // The same in any order, as every kind of sequence equals the others
+        assert_eq!(
+            eval_str("(count (distinct [(map (fn* [x] x) [1 2]) [1 2] (list 1 2)]))"),
+            Ok(MalType::Number(1))
+        );
+        assert_eq!(
+            eval_str("(count (distinct [[1 2] (list 1 2) (map (fn* [x] x) [1 2])]))"),
+            Ok(MalType::Number(1))
+        );
+        assert_eq!(eval_str("(= [1 2] (list 1 2))"), Ok(MalType::Bool(true)));
+        assert_eq!(
+            eval_str("(some even?)").unwrap_err(),
+            "Wrong number of args (1) passed to some expecting 2"
+        );
+        assert_eq!(
+            eval_str("(zipmap [:a])").unwrap_err(),
+            "Wrong number of args (1) passed to zipmap expecting 2"
+        );
+    }
+
+    #[test]
+    fn destructuring() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize(
+            "(let* [[a [b] & rest] [1 [2] 3 4] {:keys [x y] :or {y 10}} {:x 5}] (list a b rest x y))",
+        );
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        let r = eval(&ast[0], &mut env).unwrap();
+
+        assert_eq!(
+            r,
+            MalType::List(
+                vec![
+                    MalType::Number(1),
+                    MalType::Number(2),
+                    MalType::List(vec![MalType::Number(3), MalType::Number(4)], None),
+                    MalType::Number(5),
+                    MalType::Number(10),
+                ],
+                None,
+            )
+        )
+    }
+
+    #[test]
+    fn multi_arity_fn() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize(
+            "((fn* down ([n] (down n (list))) ([n acc] (if (< n 1) acc (down (- n 1) (cons n acc))))) 3)",
+        );
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        let r = eval(&ast[0], &mut env).unwrap();
+
+        assert_eq!(
+            r,
+            MalType::List((1..4).map(MalType::Number).collect(), None)
+        )
+    }
+
+    #[test]
+    fn loop_recur() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize(
+            "(loop* [i 0 acc 0] (if (< i 100) (recur (+ i 1) (+ acc i)) acc)) (fn* [x] (+ 1 (recur x)))",
+        );
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        let r = eval(&ast[0], &mut env).unwrap();
+        let err = eval(&ast[1], &mut env).unwrap_err();
+
+        assert_eq!(r, MalType::Number(4950));
+        assert_eq!(
+            err.to_string(),
+            "Can only recur from tail position: (recur x)"
+        )
+    }
+
+    #[test]
+    fn protocols() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize(
+            r#"(defprotocol Describe (describe [this] [this prefix]))
+            (extend-type Number Describe (describe ([n] "number") ([n p] (str p (describe n)))))
+            (extend-protocol Describe nil (describe [_] "nothing") String (describe [s] s))
+            [(describe 1) (describe 1 "a ") (describe nil) (describe "str") (satisfies? Describe :k)]
+            (describe :k)"#,
+        );
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        for form in &ast[..3] {
+            eval(form, &mut env).unwrap();
+        }
+        let r = eval(&ast[3], &mut env).unwrap();
+        let err = eval(&ast[4], &mut env).unwrap_err();
+
+        assert_eq!(
+            r,
+            MalType::Vector(
+                vec![
+                    MalType::String("number".to_string()),
+                    MalType::String("a number".to_string()),
+                    MalType::String("nothing".to_string()),
+                    MalType::String("str".to_string()),
+                    MalType::Bool(false),
+                ],
+                None,
+            )
+        );
+        assert_eq!(
+            err.to_string(),
+            "No implementation of method describe of protocol Describe found for type Keyword"
+        )
+    }
+
+    #[test]
+    fn records() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize(
+            r#"(defrecord Point [x y])
+            (defprotocol Norm (norm [this]))
+            (extend-type Point Norm (norm [{:keys [x y]}] (+ (* x x) (* y y))))
+            (def! p (assoc (->Point 3 4) :label "a"))
+            [(:x p) (get p :y) (norm p) (Point? p) (Point? {:x 3 :y 4}) (= p (map->Point {:x 3 :y 4 :label "a"}))]
+            (pr-str p (dissoc p :x))"#,
+        );
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        for form in &ast[..4] {
+            eval(form, &mut env).unwrap();
+        }
+        let r = eval(&ast[4], &mut env).unwrap();
+        let printed = eval(&ast[5], &mut env).unwrap();
+
+        assert_eq!(
+            r,
+            MalType::Vector(
+                vec![
+                    MalType::Number(3),
+                    MalType::Number(4),
+                    MalType::Number(25),
+                    MalType::Bool(true),
+                    MalType::Bool(false),
+                    MalType::Bool(true),
+                ],
+                None,
+            )
+        );
+        assert_eq!(
+            printed,
+            MalType::String(r#"#Point{:x 3 :y 4 :label "a"} {:y 4 :label "a"}"#.to_string())
+        )
+    }
+
+    #[test]
+    fn multimethods() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize(
+            r#"(defmulti area :shape)
+            (defmethod area :square [{:keys [side]}] (* side side))
+            (defmethod area :polygon [_] "polygon")
+            (defmethod area :default [s] (str "unknown " (:shape s)))
+            (derive :triangle :polygon)
+            [(area {:shape :square :side 3}) (area {:shape :triangle}) (area {:shape :blob}) (isa? :triangle :polygon)]
+            (remove-method area :default)
+            (area {:shape :blob})"#,
+        );
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        for form in &ast[..5] {
+            eval(form, &mut env).unwrap();
+        }
+        let r = eval(&ast[5], &mut env).unwrap();
+        eval(&ast[6], &mut env).unwrap();
+        let err = eval(&ast[7], &mut env).unwrap_err();
+
+        assert_eq!(
+            r,
+            MalType::Vector(
+                vec![
+                    MalType::Number(9),
+                    MalType::String("polygon".to_string()),
+                    MalType::String("unknown :blob".to_string()),
+                    MalType::Bool(true),
+                ],
+                None,
+            )
+        );
+        assert_eq!(
+            err.to_string(),
+            "No method in multimethod area for dispatch value :blob"
+        )
+    }
+
+    #[test]
+    fn hierarchy_per_interpreter() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let first = crate::Interpreter::new();
+        let second = crate::Interpreter::new();
+        let program = r#"(defmulti kind (fn* [x] x))
+            (defmethod kind :animal [_] "animal")
+            (defmethod kind :default [_] "unknown")"#;
+        first.eval_str(program).unwrap();
+        second.eval_str(program).unwrap();
+
+        first.eval_str("(derive :dog :animal)").unwrap();
+        let eval = |interpreter: &crate::Interpreter| {
+            interpreter
+                .eval_str("[(isa? :dog :animal) (kind :dog) (ancestors :dog)]")
+                .unwrap()
+        };
+        assert_eq!(
+            eval(&first),
+            first.eval_str(r#"[true "animal" #{:animal}]"#).unwrap()
+        );
+        assert_eq!(
+            eval(&second),
+            second.eval_str(r#"[false "unknown" #{}]"#).unwrap()
+        );
+
+        // Nor does deriving the other way round in the second count as a cycle
+        second.eval_str("(derive :animal :dog)").unwrap();
+        first.eval_str("(underive :dog :animal)").unwrap();
+        assert_eq!(
+            first.eval_str("(kind :dog)").unwrap(),
+            MalType::String("unknown".to_string())
+        );
+    }
+
+    #[test]
+    fn atoms() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize(
+            r#"(def! counter (atom 0 :validator (fn* [n] (>= n 0))))
+            (def! log (atom []))
+            (add-watch counter :log (fn* [_ _ old new] (swap! log conj [old new])))
+            [(swap! counter + 5) (reset! counter 2) (compare-and-set! counter 1 7) @counter @log]
+            (reset! counter -1)"#,
+        );
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        for form in &ast[..3] {
+            eval(form, &mut env).unwrap();
+        }
+        let r = eval(&ast[3], &mut env).unwrap();
+        let err = eval(&ast[4], &mut env).unwrap_err();
+
+        let pair = |a, b| MalType::Vector(vec![MalType::Number(a), MalType::Number(b)], None);
+        assert_eq!(
+            r,
+            MalType::Vector(
+                vec![
+                    MalType::Number(5),
+                    MalType::Number(2),
+                    MalType::Bool(false),
+                    MalType::Number(2),
+                    MalType::Vector(vec![pair(0, 5), pair(5, 2)], None),
+                ],
+                None,
+            )
+        );
+        assert_eq!(err.to_string(), "Invalid reference state -1");
+    }
+
+    #[test]
+    fn atom_holding_infinite_seq() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let interpreter = crate::Interpreter::new();
+        interpreter
+            .eval_str("(def! a (atom (iterate (fn* [x] (+ x 1)) 0)))")
+            .unwrap();
+
+        assert_eq!(
+            interpreter
+                .eval_str("(do (swap! a rest) (swap! a rest) (first @a))")
+                .unwrap(),
+            MalType::Number(2)
+        );
+        assert_eq!(
+            interpreter
+                .eval_str(
+                    "[(compare-and-set! a (rest @a) 0) (compare-and-set! a @a [@a]) (count @a)]"
+                )
+                .unwrap(),
+            MalType::Vector(
+                vec![
+                    MalType::Bool(false),
+                    MalType::Bool(true),
+                    MalType::Number(1)
+                ],
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn metadata() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize(
+            r#"^:private ^{:doc "counter"} counter
+            (def! v ^{:id (+ 1 2)} [1 2])
+            [(meta v) (= v [1 2]) (meta (vary-meta v assoc :tag :x)) (meta (with-meta v nil))]"#,
+        );
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        eval(&ast[1], &mut env).unwrap();
+        let r = eval(&ast[2], &mut env).unwrap();
+
+        let map = |items: Vec<MalType>| MalType::HashMap(items, None);
+        let kw = |k: &str| MalType::Keyword(k.into());
+        assert_eq!(
+            ast[0].meta(),
+            Some(&map(vec![
+                kw("doc"),
+                MalType::String("counter".to_string()),
+                kw("private"),
+                MalType::Bool(true),
+            ]))
+        );
+        assert_eq!(
+            r,
+            MalType::Vector(
+                vec![
+                    map(vec![kw("id"), MalType::Number(3)]),
+                    MalType::Bool(true),
+                    map(vec![kw("id"), MalType::Number(3), kw("tag"), kw("x")]),
+                    MalType::Nil,
+                ],
+                None,
+            )
+        );
+    }
+
+    #[test]
+    fn docs() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+        let lexer = Lexer::tokenize(
+            r#"(defn area "Area of a w by h rectangle." [w h] (* w h))
+            (def! side "Length of a side." (fn* [] 3))
+            [(area 2 3) (:doc (meta side)) (apropos "are") (apropos #"^are")]"#,
+        );
+        let mut parser = Parser::new(lexer);
+
+        let ast = parser.parse().unwrap();
+        let mut env = Environment::new();
+
+        eval(&ast[0], &mut env).unwrap();
+        eval(&ast[1], &mut env).unwrap();
+        let r = eval(&ast[2], &mut env).unwrap();
+
+        let symbol = |s: &str| MalType::Symbol(s.into(), None);
+        assert_eq!(
+            r,
+            MalType::Vector(
+                vec![
+                    MalType::Number(6),
+                    MalType::String("Length of a side.".to_string()),
+                    MalType::List(vec![symbol("area"), symbol("compare-and-set!")], None),
+                    MalType::List(vec![symbol("area")], None),
+                ],
+                None,
+            )
+        );
+        assert_eq!(
+            crate::doc::describe("area", &env.get("area").unwrap()),
+            "-------------------------\narea\n([w h])\n  Area of a w by h rectangle."
+        );
+        assert_eq!(
+            crate::doc::describe("count", &env.get("count").unwrap()),
+            "-------------------------\ncount\n([coll])\n  The number of items in coll."
+        );
+        assert_eq!(
+            crate::doc::describe("if", &env.get("if").unwrap())
+                .lines()
+                .nth(3),
+            Some("Special Form")
+        );
+        match env.get("area") {
+            Some(MalType::Fn(f)) => assert_eq!(
+                format!("{:b}", f.source.unwrap().as_ref()),
+                "(defn area \"Area of a w by h rectangle.\" [w h] (* w h))"
+            ),
+            other => panic!("Expected a fn, got {:?}", other),
+        }
+    }
 }
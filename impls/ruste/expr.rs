@@ -1,14 +1,17 @@
-use crate::types::MalType;
-use std::{collections::HashMap, fmt::Debug};
+use crate::{
+    intern::{Sym, SymMap},
+    types::MalType,
+};
+use std::fmt::Debug;
 
 pub struct Expressions {
-    expressions: HashMap<String, MalType>,
+    expressions: SymMap<MalType>,
 }
 
 impl Expressions {
     pub fn new() -> Self {
         Self {
-            expressions: HashMap::new(),
+            expressions: SymMap::default(),
         }
     }
 
@@ -18,12 +21,26 @@ impl Expressions {
         s
     }
 
-    pub fn set(&mut self, k: String, v: MalType) {
+    pub fn set(&mut self, k: Sym, v: MalType) {
         self.expressions.insert(k, v);
     }
 
-    pub fn get(&self, k: &str) -> Option<&MalType> {
-        self.expressions.get(k)
+    pub fn get(&self, k: Sym) -> Option<&MalType> {
+        self.expressions.get(&k)
+    }
+
+    pub fn remove(&mut self, k: Sym) -> Option<MalType> {
+        self.expressions.remove(&k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &MalType> {
+        self.expressions.values()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.expressions.keys().map(|k| k.to_string()).collect();
+        names.sort();
+        names
     }
 }
 
@@ -0,0 +1,231 @@
+use crate::{
+    environment::{Environment, Frame},
+    namespace::Namespaces,
+    types::MalType,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+/// How many scopes are made between collections the interpreter runs on its
+/// own.
+const THRESHOLD: usize = 10_000;
+
+thread_local! {
+    /// Every scope made on this thread. Dead entries are pruned whenever the
+    /// list doubles, so it stays proportional to the live ones.
+    static FRAMES: RefCell<Vec<Weak<RefCell<crate::environment::InnerEnv>>>> =
+        const { RefCell::new(vec![]) };
+    static PRUNE_AT: Cell<usize> = const { Cell::new(1024) };
+    static SINCE_COLLECT: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Registers a new scope, so cycles through it can be collected.
+pub fn track(frame: &Frame) {
+    FRAMES.with(|frames| {
+        let mut frames = frames.borrow_mut();
+        frames.push(Rc::downgrade(frame));
+        if frames.len() >= PRUNE_AT.with(Cell::get) {
+            frames.retain(|frame| frame.strong_count() > 0);
+            PRUNE_AT.with(|prune_at| prune_at.set((frames.len() * 2).max(1024)));
+        }
+    });
+    SINCE_COLLECT.with(|since| since.set(since.get() + 1));
+}
+
+/// How many scopes are alive on this thread.
+pub fn live_frames() -> usize {
+    FRAMES.with(|frames| {
+        frames
+            .borrow()
+            .iter()
+            .filter(|frame| frame.strong_count() > 0)
+            .count()
+    })
+}
+
+/// Whether enough scopes were made since the last collection to run another.
+pub fn due() -> bool {
+    SINCE_COLLECT.with(Cell::get) >= THRESHOLD
+}
+
+/// The identity of a scope or namespace table.
+fn id<T>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+/// The environments a value holds on to itself, rather than through an `Rc`
+/// it may share with values outside the scopes being collected.
+fn environments<'a>(value: &'a MalType, found: &mut Vec<&'a Environment>) {
+    match value {
+        MalType::List(items, _)
+        | MalType::Vector(items, _)
+        | MalType::HashMap(items, _)
+        | MalType::Set(items, _) => items.iter().for_each(|item| environments(item, found)),
+        MalType::Tagged(_, value) => environments(value, found),
+        MalType::Record(record) => record
+            .entries()
+            .iter()
+            .for_each(|item| environments(item, found)),
+        MalType::Fn(f) => found.push(&f.captured_env),
+        _ => (),
+    }
+}
+
+/// Frees the scopes, and the namespace tables, that are only kept alive by
+/// references among themselves, such as a fn bound in the very scope it
+/// captured. Returns how many scopes were freed.
+///
+/// This is trial deletion, as CPython's collector does it: the references
+/// each one gets from the others are subtracted from its reference count,
+/// and whatever is left over comes from outside, from values on the Rust
+/// stack, atoms, lazy seqs and the like. Everything reachable from those
+/// stays, the rest has its bindings cleared so the cycles fall apart.
+/// References held by `ignoring` count as gone, for an interpreter being
+/// dropped.
+pub fn collect(ignoring: Option<&Environment>) -> usize {
+    SINCE_COLLECT.with(|since| since.set(0));
+    let frames: Vec<Frame> = FRAMES.with(|frames| {
+        let mut frames = frames.borrow_mut();
+        frames.retain(|frame| frame.strong_count() > 0);
+        frames.iter().filter_map(Weak::upgrade).collect()
+    });
+    let mut spaces: HashMap<usize, Rc<RefCell<Namespaces>>> = HashMap::new();
+    let mut edges: HashMap<usize, Vec<usize>> = HashMap::new();
+
+    for frame in &frames {
+        // Something is changing this scope right now, try again later
+        let Ok(inner) = frame.try_borrow() else {
+            return 0;
+        };
+        let mut found = vec![];
+        inner
+            .values()
+            .for_each(|value| environments(value, &mut found));
+        let mut children: Vec<usize> = inner.outer().map(id).into_iter().collect();
+        for env in found {
+            children.push(id(env.frame()));
+            let namespaces = env.shared_namespaces();
+            spaces
+                .entry(id(namespaces))
+                .or_insert_with(|| Rc::clone(namespaces));
+            children.push(id(namespaces));
+        }
+        edges.insert(id(frame), children);
+    }
+    for (space, namespaces) in &spaces {
+        let Ok(namespaces) = namespaces.try_borrow() else {
+            return 0;
+        };
+        edges.insert(*space, namespaces.frames().map(id).collect());
+    }
+
+    // The references from outside, leaving out the ones held just now
+    let mut refs: HashMap<usize, usize> = HashMap::new();
+    for frame in &frames {
+        refs.insert(id(frame), Rc::strong_count(frame) - 1);
+    }
+    for (space, namespaces) in &spaces {
+        refs.insert(*space, Rc::strong_count(namespaces) - 1);
+    }
+    let mut internal: Vec<usize> = edges.values().flatten().copied().collect();
+    if let Some(env) = ignoring {
+        internal.push(id(env.frame()));
+        internal.push(id(env.shared_namespaces()));
+    }
+    for child in internal {
+        if let Some(count) = refs.get_mut(&child) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    let mut reachable: Vec<usize> = refs
+        .iter()
+        .filter(|(_, count)| **count > 0)
+        .map(|(node, _)| *node)
+        .collect();
+    let mut live: HashMap<usize, ()> = HashMap::new();
+    while let Some(node) = reachable.pop() {
+        if live.insert(node, ()).is_none() {
+            reachable.extend(edges.get(&node).into_iter().flatten());
+        }
+    }
+
+    // Values are only dropped once nothing here is borrowed anymore, as
+    // dropping them frees the other garbage along with them
+    let mut cleared_frames = vec![];
+    for frame in frames.iter().filter(|frame| !live.contains_key(&id(frame))) {
+        cleared_frames.push(frame.borrow_mut().clear());
+    }
+    let mut cleared_spaces = vec![];
+    for (_, namespaces) in spaces.iter().filter(|(space, _)| !live.contains_key(space)) {
+        cleared_spaces.push(namespaces.borrow_mut().clear());
+    }
+    let freed = cleared_frames.len();
+    drop(frames);
+    drop(spaces);
+    drop(cleared_frames);
+    drop(cleared_spaces);
+    freed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    #[test]
+    fn collect_cycles() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Info)
+            .is_test(true)
+            .try_init();
+
+        let baseline = live_frames();
+        let interpreter = Interpreter::new();
+        interpreter
+            .eval_str("(defn fact [n] (if (= n 0) 1 (* n (fact (- n 1)))))")
+            .unwrap();
+
+        // Each g captures the let* scope it's bound in
+        let mut grown = vec![];
+        for _ in 0..3 {
+            for _ in 0..200 {
+                interpreter
+                    .eval_str("(def! f (let* [g (fn* [] g)] g))")
+                    .unwrap();
+            }
+            grown.push(live_frames());
+            collect(None);
+        }
+        let after = live_frames();
+        assert!(grown[0] >= baseline + 200, "{:?}", grown);
+        assert!(
+            after < baseline + 10,
+            "{} live scopes after collecting",
+            after
+        );
+
+        // What is still reachable keeps working
+        assert!(matches!(
+            interpreter.eval_str("(f)").unwrap(),
+            MalType::Fn(_)
+        ));
+        assert_eq!(
+            interpreter.eval_str("(fact 5)").unwrap(),
+            MalType::Number(120)
+        );
+        // A fn held from Rust keeps the scope it captured
+        let kept = interpreter.eval_str("(let* [x 7 h (fn* [] x)] h)").unwrap();
+        collect(None);
+        interpreter.set("h", kept);
+        assert_eq!(interpreter.eval_str("(h)").unwrap(), MalType::Number(7));
+
+        // Dropping the interpreter frees its namespaces, despite every fn in
+        // them capturing the namespace it is defined in
+        drop(interpreter);
+        assert_eq!(live_frames(), baseline);
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::{Debug, Display},
+    hash::{BuildHasherDefault, Hasher},
+    ops::Deref,
+    sync::{LazyLock, RwLock},
+};
+
+/// The name of a symbol or keyword, interned: equal names share one small
+/// id, so comparing and hashing them never looks at the characters.
+///
+/// ```
+/// use ruste::intern::Sym;
+///
+/// let sym = Sym::new("reduce");
+/// assert_eq!(sym, Sym::from("reduce".to_string()));
+/// assert_eq!(sym.as_str(), "reduce");
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sym(u32);
+
+/// A map keyed by symbols, hashing just their ids.
+pub type SymMap<V> = HashMap<Sym, V, BuildHasherDefault<SymHasher>>;
+
+/// Spreads an id over the hash, which the hash table needs its top bits to
+/// vary, rather than hashing it all over again.
+#[derive(Default)]
+pub struct SymHasher(u64);
+
+impl Hasher for SymHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    /// Only ids are ever hashed, through `write_u32`.
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("SymHasher only hashes symbol ids")
+    }
+
+    fn write_u32(&mut self, id: u32) {
+        self.0 = (id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+/// An interned name, and for `ns/name` its two halves, split once when it
+/// is interned rather than on every lookup.
+#[derive(Clone, Copy)]
+struct Entry {
+    name: &'static str,
+    qualified: Option<(Sym, Sym)>,
+}
+
+#[derive(Default)]
+struct Interner {
+    entries: Vec<Entry>,
+    ids: HashMap<&'static str, u32>,
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> Sym {
+        if let Some(id) = self.ids.get(name) {
+            return Sym(*id);
+        }
+        let qualified = match name.split_once('/') {
+            Some((ns, name)) if !ns.is_empty() && !name.is_empty() => {
+                Some((self.intern(ns), self.intern(name)))
+            }
+            _ => None,
+        };
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let id = self.entries.len() as u32;
+        self.entries.push(Entry { name, qualified });
+        self.ids.insert(name, id);
+        Sym(id)
+    }
+}
+
+/// Shared by every thread. Names are never freed, see `Sym::new`.
+static INTERNER: LazyLock<RwLock<Interner>> = LazyLock::new(RwLock::default);
+
+thread_local! {
+    /// The names interned so far, so resolving an id doesn't take the lock
+    /// unless the name is newer than the last one seen on this thread.
+    static ENTRIES: RefCell<Vec<Entry>> = const { RefCell::new(vec![]) };
+}
+
+/// The `&` of variadic params and rest destructuring.
+pub static AMP: LazyLock<Sym> = LazyLock::new(|| Sym::new("&"));
+
+/// The `:as` of destructuring and `require`.
+pub static AS: LazyLock<Sym> = LazyLock::new(|| Sym::new("as"));
+
+/// The `_` that discards a destructured value.
+pub static UNDERSCORE: LazyLock<Sym> = LazyLock::new(|| Sym::new("_"));
+
+/// The `:keys` of map destructuring.
+pub static KEYS: LazyLock<Sym> = LazyLock::new(|| Sym::new("keys"));
+
+/// The `:strs` of map destructuring.
+pub static STRS: LazyLock<Sym> = LazyLock::new(|| Sym::new("strs"));
+
+/// The `:or` defaults of map destructuring.
+pub static OR: LazyLock<Sym> = LazyLock::new(|| Sym::new("or"));
+
+impl Sym {
+    /// Interns `name`, which then stays in memory until the process exits,
+    /// as ids must resolve on every thread for as long as any copy lives.
+    /// Code only ever names so many symbols, but reading data that makes
+    /// keywords, as `read-edn` and `json-parse` with `{:keywords true}` do,
+    /// keeps every distinct key it sees: untrusted input with unbounded keys
+    /// is better read with string keys. No core fn makes keywords or symbols
+    /// out of strings at runtime for the same reason.
+    pub fn new(name: &str) -> Self {
+        if let Some(id) = INTERNER.read().unwrap().ids.get(name) {
+            return Sym(*id);
+        }
+        // Another thread may have added it in between, which intern checks
+        INTERNER.write().unwrap().intern(name)
+    }
+
+    pub fn id(self) -> u32 {
+        self.0
+    }
+
+    pub fn as_str(self) -> &'static str {
+        self.entry().name
+    }
+
+    /// The namespace and name of a qualified `ns/name`.
+    pub fn qualified(self) -> Option<(Sym, Sym)> {
+        self.entry().qualified
+    }
+
+    fn entry(self) -> Entry {
+        let index = self.0 as usize;
+        ENTRIES.with(|entries| {
+            let mut entries = entries.borrow_mut();
+            if index >= entries.len() {
+                let interner = INTERNER.read().unwrap();
+                let seen = entries.len();
+                entries.extend_from_slice(&interner.entries[seen..]);
+            }
+            entries[index]
+        })
+    }
+}
+
+impl Deref for Sym {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for Sym {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Debug for Sym {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Sorted by name, not by when they were interned.
+impl Ord for Sym {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.0 == other.0 {
+            true => Ordering::Equal,
+            false => self.as_str().cmp(other.as_str()),
+        }
+    }
+}
+
+impl PartialOrd for Sym {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq<str> for Sym {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Sym {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl From<&str> for Sym {
+    fn from(name: &str) -> Self {
+        Sym::new(name)
+    }
+}
+
+impl From<String> for Sym {
+    fn from(name: String) -> Self {
+        Sym::new(&name)
+    }
+}
+
+impl From<&String> for Sym {
+    fn from(name: &String) -> Self {
+        Sym::new(name)
+    }
+}
+
+impl From<&Sym> for Sym {
+    fn from(sym: &Sym) -> Self {
+        *sym
+    }
+}
+
+impl From<Sym> for String {
+    fn from(sym: Sym) -> Self {
+        sym.as_str().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Interpreter, MalType};
+
+    #[test]
+    fn interning() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        let sym = Sym::new("interned");
+        assert_eq!(sym, Sym::from("interned".to_string()));
+        assert_ne!(sym, Sym::new("other"));
+        // Ids are the same on every thread
+        let id = std::thread::spawn(|| Sym::new("interned").id())
+            .join()
+            .unwrap();
+        assert_eq!(sym.id(), id);
+        let late = std::thread::spawn(|| Sym::new("made-on-another-thread"))
+            .join()
+            .unwrap();
+        assert_eq!(late.as_str(), "made-on-another-thread");
+        // Sorted by name rather than id
+        assert!(Sym::new("zz-first-interned") > Sym::new("aa-interned-after"));
+        // Qualified names are split once, when interned
+        assert_eq!(
+            Sym::new("my.ns/thing").qualified(),
+            Some((Sym::new("my.ns"), Sym::new("thing")))
+        );
+        assert_eq!(Sym::new("/").qualified(), None);
+        assert_eq!(Sym::new("thing").qualified(), None);
+
+        let interpreter = Interpreter::new();
+        assert_eq!(
+            interpreter
+                .eval_str("[(= :b :b) (= :b :a) (sort [:zz-first-interned :aa-interned-after])]")
+                .unwrap(),
+            interpreter
+                .eval_str("[true false (list :aa-interned-after :zz-first-interned)]")
+                .unwrap()
+        );
+        assert_eq!(
+            interpreter.eval_str("(:x {:x 1})").unwrap(),
+            MalType::Number(1)
+        );
+    }
+}
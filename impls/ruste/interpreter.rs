@@ -0,0 +1,488 @@
+use crate::{
+    environment::Environment,
+    interrupt::{Interrupt, Interrupted},
+    lazy::SeqIter,
+    reader::{Lexer, Parser},
+    sandbox::{LimitExceeded, Sandbox},
+    stack::Frame,
+    types::MalType,
+};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+/// What went wrong running mal code, or getting a Rust value out of it.
+#[derive(Debug)]
+pub enum Error {
+    /// The input is not well formed.
+    Read(anyhow::Error),
+    /// Evaluating a form failed, including in functions registered from Rust.
+    Eval(anyhow::Error),
+    /// A file passed to `eval_file` could not be read.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A value isn't of the type it was converted to.
+    Type {
+        expected: &'static str,
+        found: String,
+    },
+    /// Evaluation was stopped by the interpreter's sandbox.
+    Limit(LimitExceeded),
+    /// Evaluation was stopped through `Interpreter::interrupt_handle`.
+    Interrupted,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // The alternate form includes the context, as in `while loading`
+            Error::Read(err) | Error::Eval(err) => write!(f, "{:#}", err),
+            Error::Io { path, source } => {
+                write!(f, "Could not read {}: {}", path.display(), source)
+            }
+            Error::Type { expected, found } => write!(f, "Expected {}, got {}", expected, found),
+            Error::Limit(limit) => write!(f, "{}", limit),
+            Error::Interrupted => write!(f, "{}", Interrupted),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Limit(limit) => Some(limit),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// The mal calls an evaluation error unwound through, innermost first.
+    /// `MalType::from` turns them into maps with `:fn`, `:form`, and the
+    /// `:file`, `:line` and `:column` of the form when it was read from source.
+    pub fn stack(&self) -> &[Frame] {
+        match self {
+            Error::Eval(err) => crate::stack::stack(err),
+            _ => &[],
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Sandbox breaches and interrupts get their own variants wherever they were
+/// raised from.
+fn eval_error(err: anyhow::Error) -> Error {
+    let cause = crate::stack::cause(&err);
+    if let Some(limit) = cause.downcast_ref::<LimitExceeded>() {
+        return Error::Limit(limit.clone());
+    }
+    match cause.is::<Interrupted>() {
+        true => Error::Interrupted,
+        false => Error::Eval(err),
+    }
+}
+
+/// A mal interpreter to embed in a Rust program. Forms are evaluated in the
+/// current namespace, which starts out as `user`, just like at the REPL.
+///
+/// ```
+/// use ruste::{native_fn, Interpreter};
+///
+/// native_fn! {
+///     #[name = "greet"]
+///     fn greet(name: &str) -> anyhow::Result<String> {
+///         Ok(format!("hi {}", name))
+///     }
+/// }
+///
+/// let interpreter = Interpreter::new();
+/// interpreter.register("greet", greet);
+/// let n: i64 = interpreter.eval_str("(count (greet \"mal\"))")?.try_into()?;
+/// assert_eq!(n, 6);
+/// # Ok::<(), ruste::Error>(())
+/// ```
+///
+/// To run code that isn't trusted, create the interpreter with
+/// [`Interpreter::sandboxed`].
+pub struct Interpreter {
+    env: Environment,
+    sandbox: Sandbox,
+    interrupt: Interrupt,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::sandboxed(Sandbox {
+            allow_io: true,
+            ..Sandbox::default()
+        })
+    }
+
+    /// An interpreter that stops evaluation with `Error::Limit` once one of
+    /// the sandbox's limits is reached. Every call to `eval_str`, `eval`,
+    /// `eval_file` or `call` gets the full budget. Without `allow_io`, the
+    /// functions in `sandbox::IO_FUNCTIONS` aren't defined at all.
+    ///
+    /// ```
+    /// use ruste::{Error, Interpreter, LimitExceeded, Sandbox};
+    ///
+    /// let interpreter = Interpreter::sandboxed(Sandbox {
+    ///     max_steps: Some(10_000),
+    ///     ..Sandbox::default()
+    /// });
+    /// assert!(matches!(
+    ///     interpreter.eval_str("(loop* [n 0] (recur (+ n 1)))"),
+    ///     Err(Error::Limit(LimitExceeded::Steps(10_000)))
+    /// ));
+    /// # Ok::<(), ruste::Error>(())
+    /// ```
+    pub fn sandboxed(sandbox: Sandbox) -> Self {
+        let env = Environment::new();
+        if !sandbox.allow_io {
+            for name in crate::sandbox::IO_FUNCTIONS {
+                env.remove_core(name);
+            }
+        }
+        Self {
+            env,
+            sandbox,
+            interrupt: Interrupt::new(),
+        }
+    }
+
+    /// A handle that stops whatever this interpreter is evaluating with
+    /// `Error::Interrupted`, leaving its definitions intact. It can be
+    /// triggered from another thread or a signal handler.
+    pub fn interrupt_handle(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    /// Runs `f` as one evaluation, with a fresh sandbox budget.
+    fn guard<T>(&self, f: impl FnOnce() -> T) -> T {
+        let ret = crate::interrupt::run(&self.interrupt, || crate::sandbox::run(&self.sandbox, f));
+        if crate::gc::due() {
+            crate::gc::collect(None);
+        }
+        ret
+    }
+
+    /// Frees the scopes only kept alive by reference cycles, such as a fn
+    /// captured by the `let*` it is bound in, returning how many were freed.
+    /// This also happens on its own every so often.
+    pub fn collect_garbage(&self) -> usize {
+        crate::gc::collect(None)
+    }
+
+    /// Evaluates every form in `input`, returning the value of the last one,
+    /// or nil if there are none.
+    pub fn eval_str(&self, input: &str) -> Result<MalType> {
+        self.eval_source(input, None)
+    }
+
+    /// Like `eval_str`, with `file` as where the forms' positions are in.
+    fn eval_source(&self, input: &str, file: Option<&str>) -> Result<MalType> {
+        let forms = Parser::new(Lexer::tokenize(input))
+            .with_positions(file)
+            .parse()
+            .map_err(Error::Read)?;
+        self.guard(|| {
+            forms
+                .iter()
+                .try_fold(MalType::Nil, |_, form| self.eval_form(form))
+        })
+    }
+
+    /// Evaluates a form that has already been read.
+    pub fn eval(&self, form: &MalType) -> Result<MalType> {
+        self.guard(|| self.eval_form(form))
+    }
+
+    fn eval_form(&self, form: &MalType) -> Result<MalType> {
+        crate::eval::eval(form, &mut self.env.current_ns()).map_err(eval_error)
+    }
+
+    pub fn eval_file(&self, path: impl AsRef<Path>) -> Result<MalType> {
+        let path = path.as_ref();
+        let input = std::fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        self.eval_source(&input, Some(&path.display().to_string()))
+    }
+
+    /// Makes `f` callable from mal as `name`, in every namespace. Functions
+    /// defined with `native_fn!` have the right signature.
+    pub fn register(
+        &self,
+        name: &str,
+        f: impl Fn(&[MalType], Environment) -> anyhow::Result<MalType> + 'static,
+    ) {
+        self.env.set_core(name, MalType::native(name, f));
+    }
+
+    /// Binds `name` to `value` in the current namespace, as `def!` would.
+    pub fn set(&self, name: &str, value: impl Into<MalType>) {
+        self.env
+            .current_ns()
+            .set(MalType::Symbol(name.into(), None), value.into());
+    }
+
+    pub fn get(&self, name: &str) -> Option<MalType> {
+        self.env.current_ns().get(name)
+    }
+
+    /// Calls the function bound to `name` with `args`.
+    pub fn call(&self, name: &str, args: &[MalType]) -> Result<MalType> {
+        let f = self
+            .get(name)
+            .ok_or_else(|| Error::Eval(anyhow::anyhow!("{} not found", name)))?;
+        self.guard(|| f.eval(args, &self.env.current_ns()).map_err(eval_error))
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.env
+    }
+}
+
+/// Every fn defined in a namespace captures it, so the namespaces are only
+/// freed by collecting them.
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        crate::gc::collect(Some(&self.env));
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<i64> for MalType {
+    fn from(value: i64) -> Self {
+        MalType::Number(value)
+    }
+}
+
+impl From<f64> for MalType {
+    fn from(value: f64) -> Self {
+        MalType::Float(value)
+    }
+}
+
+impl From<bool> for MalType {
+    fn from(value: bool) -> Self {
+        MalType::Bool(value)
+    }
+}
+
+impl From<&str> for MalType {
+    fn from(value: &str) -> Self {
+        MalType::String(value.to_string())
+    }
+}
+
+impl From<String> for MalType {
+    fn from(value: String) -> Self {
+        MalType::String(value)
+    }
+}
+
+impl From<()> for MalType {
+    fn from(_: ()) -> Self {
+        MalType::Nil
+    }
+}
+
+impl<T: Into<MalType>> From<Option<T>> for MalType {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(MalType::Nil)
+    }
+}
+
+impl<T: Into<MalType>> From<Vec<T>> for MalType {
+    fn from(value: Vec<T>) -> Self {
+        MalType::Vector(value.into_iter().map(Into::into).collect(), None)
+    }
+}
+
+/// String keys become keywords, the way mal code usually writes maps.
+impl<T: Into<MalType>> From<HashMap<String, T>> for MalType {
+    fn from(value: HashMap<String, T>) -> Self {
+        MalType::HashMap(
+            value
+                .into_iter()
+                .flat_map(|(key, value)| [MalType::Keyword(key.into()), value.into()])
+                .collect(),
+            None,
+        )
+    }
+}
+
+fn type_error(expected: &'static str, found: &MalType) -> Error {
+    Error::Type {
+        expected,
+        found: format!("{:b}", found),
+    }
+}
+
+impl TryFrom<MalType> for i64 {
+    type Error = Error;
+
+    fn try_from(value: MalType) -> Result<Self> {
+        match value {
+            MalType::Number(n) => Ok(n),
+            other => Err(type_error("a number", &other)),
+        }
+    }
+}
+
+/// Integers widen to floats.
+impl TryFrom<MalType> for f64 {
+    type Error = Error;
+
+    fn try_from(value: MalType) -> Result<Self> {
+        match value {
+            MalType::Float(n) => Ok(n),
+            MalType::Number(n) => Ok(n as f64),
+            other => Err(type_error("a float", &other)),
+        }
+    }
+}
+
+/// Only booleans convert: use `MalType::truthy` for mal's notion of truth.
+impl TryFrom<MalType> for bool {
+    type Error = Error;
+
+    fn try_from(value: MalType) -> Result<Self> {
+        match value {
+            MalType::Bool(b) => Ok(b),
+            other => Err(type_error("a bool", &other)),
+        }
+    }
+}
+
+impl TryFrom<MalType> for String {
+    type Error = Error;
+
+    fn try_from(value: MalType) -> Result<Self> {
+        match value {
+            MalType::String(s) => Ok(s),
+            other => Err(type_error("a string", &other)),
+        }
+    }
+}
+
+/// Any sequence converts, lazy ones by walking them to the end.
+impl<T: TryFrom<MalType, Error = Error>> TryFrom<MalType> for Vec<T> {
+    type Error = Error;
+
+    fn try_from(value: MalType) -> Result<Self> {
+        match value {
+            MalType::List(..)
+            | MalType::Vector(..)
+            | MalType::Set(..)
+            | MalType::Lazy(_)
+            | MalType::Nil => SeqIter::new(&value)
+                .map_err(Error::Eval)?
+                .map(|item| T::try_from(item.map_err(Error::Eval)?))
+                .collect(),
+            other => Err(type_error("a sequence", &other)),
+        }
+    }
+}
+
+/// Keyword, string and symbol keys all become strings.
+impl<T: TryFrom<MalType, Error = Error>> TryFrom<MalType> for HashMap<String, T> {
+    type Error = Error;
+
+    fn try_from(value: MalType) -> Result<Self> {
+        let entries = match &value {
+            MalType::HashMap(entries, _) => entries.as_slice(),
+            MalType::Record(record) => record.entries(),
+            MalType::Nil => &[],
+            other => return Err(type_error("a map", other)),
+        };
+        entries
+            .chunks(2)
+            .map(|pair| {
+                let key = match &pair[0] {
+                    MalType::Keyword(key) | MalType::Symbol(key, _) => key.to_string(),
+                    MalType::String(key) => key.clone(),
+                    other => return Err(type_error("a keyword or string key", other)),
+                };
+                Ok((key, T::try_from(pair[1].clone())?))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embed() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        let interpreter = Interpreter::new();
+        interpreter.register("add-all", |args, _| {
+            let numbers: Vec<i64> = args[0].clone().try_into()?;
+            Ok(numbers.iter().sum::<i64>().into())
+        });
+        interpreter.set("prices", vec![3, 4, 5]);
+
+        let total: i64 = interpreter
+            .eval_str("(def! tax 2) (+ tax (add-all prices))")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(total, 14);
+
+        let doubled: Vec<i64> = interpreter
+            .call(
+                "map",
+                &[
+                    interpreter.eval_str("(fn* [x] (* 2 x))").unwrap(),
+                    MalType::from(vec![1, 2]),
+                ],
+            )
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(doubled, vec![2, 4]);
+
+        let config: HashMap<String, String> = interpreter
+            .eval_str(r#"{:name "mal" "lang" "lisp"}"#)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(config["name"], "mal");
+        assert_eq!(config["lang"], "lisp");
+
+        // Registered functions are visible from other namespaces too
+        interpreter.eval_str("(ns other)").unwrap();
+        assert!(interpreter.eval_str("(add-all [1])").is_ok());
+
+        assert!(matches!(interpreter.eval_str("(+ 1"), Err(Error::Read(_))));
+        assert!(matches!(
+            interpreter.eval_str("(nope)"),
+            Err(Error::Eval(_))
+        ));
+        assert!(matches!(
+            interpreter.eval_file("/nonexistent/file.mal"),
+            Err(Error::Io { .. })
+        ));
+        let err = String::try_from(MalType::Number(1)).unwrap_err();
+        assert_eq!(err.to_string(), "Expected a string, got 1");
+    }
+}
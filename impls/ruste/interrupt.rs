@@ -0,0 +1,105 @@
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// A flag that stops the evaluation running on another thread, or under a
+/// signal handler, the next time it is polled. Clones share the flag.
+#[derive(Clone, Debug, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the current evaluation. Does nothing if none is running.
+    pub fn trigger(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn clear(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// The error evaluation stops with once interrupted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Interrupted;
+
+impl Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+thread_local! {
+    /// The interrupt evaluation on this thread currently polls, if any.
+    static ACTIVE: RefCell<Option<Interrupt>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` so that triggering `interrupt` stops it. The flag is cleared once
+/// the outermost run is over, so an interrupt never carries over to the next
+/// evaluation.
+pub fn run<T>(interrupt: &Interrupt, f: impl FnOnce() -> T) -> T {
+    let previous = ACTIVE.with(|active| active.borrow_mut().replace(interrupt.clone()));
+    if previous.is_none() {
+        interrupt.clear();
+    }
+    let ret = f();
+    if previous.is_none() {
+        interrupt.clear();
+    }
+    ACTIVE.with(|active| *active.borrow_mut() = previous);
+    ret
+}
+
+/// Fails if the active interrupt was triggered.
+pub fn check() -> Result<(), Interrupted> {
+    ACTIVE.with(|active| match active.borrow().as_ref() {
+        Some(interrupt) if interrupt.is_triggered() => Err(Interrupted),
+        _ => Ok(()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Interpreter, MalType};
+    use std::time::Duration;
+
+    #[test]
+    fn interrupt() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        let interpreter = Interpreter::new();
+        interpreter.eval_str("(def! x 1)").unwrap();
+
+        let interrupt = interpreter.interrupt_handle();
+        let trigger = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            interrupt.trigger();
+        });
+        let ret = interpreter.eval_str("(loop* [n 0] (recur (+ n 1)))");
+        trigger.join().unwrap();
+        assert!(matches!(ret, Err(Error::Interrupted)));
+        assert_eq!(ret.unwrap_err().to_string(), "Interrupted");
+
+        // Definitions survive, and the next evaluation runs normally
+        assert_eq!(interpreter.eval_str("x").unwrap(), MalType::Number(1));
+        interpreter.interrupt_handle().trigger();
+        assert_eq!(interpreter.eval_str("(+ x 1)").unwrap(), MalType::Number(2));
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{lazy::SeqIter, types::MalType};
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Number, Value};
+
+/// Reads a JSON document, turning object keys into keywords when asked to.
+pub fn parse(input: &str, keywords: bool) -> Result<MalType> {
+    let value: Value = serde_json::from_str(input).map_err(|err| anyhow!("json-parse: {}", err))?;
+    Ok(from_json(value, keywords))
+}
+
+pub fn stringify(value: &MalType, pretty: bool) -> Result<String> {
+    let value = to_json(value)?;
+    let json = if pretty {
+        serde_json::to_string_pretty(&value)?
+    } else {
+        serde_json::to_string(&value)?
+    };
+    Ok(json)
+}
+
+fn from_json(value: Value, keywords: bool) -> MalType {
+    match value {
+        Value::Null => MalType::Nil,
+        Value::Bool(b) => MalType::Bool(b),
+        Value::Number(nr) => match nr.as_i64() {
+            Some(nr) => MalType::Number(nr),
+            // Fractions, exponents and integers beyond i64 all end up as floats
+            None => MalType::Float(nr.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(str) => MalType::String(str),
+        Value::Array(items) => MalType::Vector(
+            items
+                .into_iter()
+                .map(|item| from_json(item, keywords))
+                .collect(),
+            None,
+        ),
+        Value::Object(map) => {
+            let mut list = vec![];
+            for (key, value) in map {
+                list.push(match keywords {
+                    true => MalType::Keyword(key.into()),
+                    false => MalType::String(key),
+                });
+                list.push(from_json(value, keywords));
+            }
+            MalType::HashMap(list, None)
+        }
+    }
+}
+
+fn to_json(value: &MalType) -> Result<Value> {
+    let json = match value {
+        MalType::Nil => Value::Null,
+        MalType::Bool(b) => Value::Bool(*b),
+        MalType::Number(nr) => Value::Number(Number::from(*nr)),
+        MalType::Float(nr) => Value::Number(
+            Number::from_f64(*nr)
+                .ok_or(anyhow!("json-stringify: cannot encode {} as a number", nr))?,
+        ),
+        MalType::String(str) => Value::String(str.clone()),
+        MalType::Keyword(str) | MalType::Symbol(str, _) => Value::String(str.to_string()),
+        MalType::List(items, _) | MalType::Vector(items, _) => {
+            Value::Array(items.iter().map(to_json).collect::<Result<_>>()?)
+        }
+        MalType::Lazy(_) => Value::Array(
+            SeqIter::new(value)?
+                .map(|item| to_json(&item?))
+                .collect::<Result<_>>()?,
+        ),
+        MalType::HashMap(items, _) => {
+            let mut map = Map::new();
+            for pair in items.chunks(2) {
+                let key = match &pair[0] {
+                    MalType::String(key) => key.clone(),
+                    MalType::Keyword(key) | MalType::Symbol(key, _) => key.to_string(),
+                    MalType::Number(nr) => nr.to_string(),
+                    other => {
+                        anyhow::bail!("json-stringify: cannot use {:b} as an object key", other)
+                    }
+                };
+                map.insert(key, to_json(&pair[1])?);
+            }
+            Value::Object(map)
+        }
+        MalType::Record(record) => to_json(&MalType::HashMap(record.entries().to_vec(), None))?,
+        other => anyhow::bail!("json-stringify: cannot encode {:b}", other),
+    };
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let json = r#"{"name":"mal","tags":["a","b"],"version":1.5,"meta":null}"#;
+
+        let value = parse(json, true).unwrap();
+
+        assert_eq!(
+            value,
+            MalType::HashMap(
+                vec![
+                    MalType::Keyword("name".into()),
+                    MalType::String("mal".to_string()),
+                    MalType::Keyword("tags".into()),
+                    MalType::Vector(
+                        vec![
+                            MalType::String("a".to_string()),
+                            MalType::String("b".to_string())
+                        ],
+                        None
+                    ),
+                    MalType::Keyword("version".into()),
+                    MalType::Float(1.5),
+                    MalType::Keyword("meta".into()),
+                    MalType::Nil,
+                ],
+                None
+            )
+        );
+        assert_eq!(stringify(&value, false).unwrap(), json)
+    }
+
+    #[test]
+    fn error_position() {
+        let err = parse("{\"a\": 1,\n \"b\" 2}", false).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "json-parse: expected `:` at line 2 column 6"
+        )
+    }
+
+    #[test]
+    fn options() {
+        let interpreter = crate::Interpreter::new();
+
+        assert_eq!(
+            interpreter
+                .eval_str(r#"[(json-parse "{\"a\": 1}" {:keywords true}) (json-parse "1" nil)]"#)
+                .unwrap(),
+            interpreter.eval_str("[{:a 1} 1]").unwrap()
+        );
+        assert_eq!(
+            interpreter
+                .eval_str(r#"(json-parse "{}" :keywords)"#)
+                .unwrap_err()
+                .to_string(),
+            "Expected an options map, got :keywords"
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use crate::types::MalType;
+use anyhow::Result;
+use std::{cell::RefCell, fmt::Debug, rc::Rc};
+
+/// The first item of a sequence and the sequence holding the rest, or `None`
+/// once the sequence is exhausted.
+pub type Step = Option<(MalType, MalType)>;
+
+pub type Thunk = Rc<dyn Fn() -> Result<Step> + 'static>;
+
+enum LazyState {
+    Unrealized(Thunk),
+    Realizing,
+    Realized(Step),
+}
+
+/// A sequence cell whose thunk runs at most once, on first access, with the
+/// result cached for every clone sharing the cell.
+#[derive(Clone)]
+pub struct LazySeq(Rc<RefCell<LazyState>>);
+
+impl LazySeq {
+    pub fn new(thunk: impl Fn() -> Result<Step> + 'static) -> Self {
+        Self(Rc::new(RefCell::new(LazyState::Unrealized(Rc::new(thunk)))))
+    }
+
+    pub fn realized(step: Step) -> Self {
+        Self(Rc::new(RefCell::new(LazyState::Realized(step))))
+    }
+
+    pub fn ptr_eq(&self, other: &LazySeq) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub fn step(&self) -> Result<Step> {
+        let thunk = match &*self.0.borrow() {
+            LazyState::Realized(step) => return Ok(step.clone()),
+            LazyState::Realizing => anyhow::bail!("Lazy sequence depends on its own value"),
+            LazyState::Unrealized(thunk) => Rc::clone(thunk),
+        };
+
+        // The borrow is released while the thunk runs, as it may well touch
+        // other cells of this very sequence.
+        *self.0.borrow_mut() = LazyState::Realizing;
+        match thunk() {
+            Ok(step) => {
+                *self.0.borrow_mut() = LazyState::Realized(step.clone());
+                Ok(step)
+            }
+            Err(err) => {
+                *self.0.borrow_mut() = LazyState::Unrealized(thunk);
+                Err(err)
+            }
+        }
+    }
+
+    /// Items realized so far, and whether that is the whole sequence.
+    pub fn cached(&self) -> (Vec<MalType>, bool) {
+        let mut items = vec![];
+        let mut next = self.clone();
+        loop {
+            let rest = match &*next.0.borrow() {
+                LazyState::Realized(Some((first, rest))) => {
+                    items.push(first.clone());
+                    rest.clone()
+                }
+                LazyState::Realized(None) => return (items, true),
+                _ => return (items, false),
+            };
+
+            match rest {
+                MalType::Lazy(seq) => next = seq,
+                MalType::Nil => return (items, true),
+                MalType::List(rest, _) | MalType::Vector(rest, _) => {
+                    items.extend(rest);
+                    return (items, true);
+                }
+                _ => return (items, false),
+            }
+        }
+    }
+}
+
+/// Takes the realized tail out of a cell nothing else holds on to, so the
+/// cell can be dropped without dropping the rest of the chain along with it.
+fn take_tail(seq: &LazySeq) -> Option<LazySeq> {
+    if Rc::strong_count(&seq.0) != 1 {
+        return None;
+    }
+    match &mut *seq.0.try_borrow_mut().ok()? {
+        LazyState::Realized(Some((_, rest @ MalType::Lazy(_)))) => {
+            match std::mem::replace(rest, MalType::Nil) {
+                MalType::Lazy(tail) => Some(tail),
+                _ => unreachable!(),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Dropping a realized chain would otherwise recurse once per cell, and
+/// overflow the stack on long sequences.
+impl Drop for LazySeq {
+    fn drop(&mut self) {
+        let mut next = take_tail(self);
+        while let Some(seq) = next {
+            next = take_tail(&seq);
+        }
+    }
+}
+
+impl Debug for LazySeq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (items, complete) = self.cached();
+        f.debug_struct("LazySeq")
+            .field("items", &items)
+            .field("complete", &complete)
+            .finish()
+    }
+}
+
+/// Takes the first step of any seqable value, realizing lazy cells as needed.
+pub fn step(value: &MalType) -> Result<Step> {
+    match value {
+        MalType::Lazy(seq) => seq.step(),
+        MalType::Nil => Ok(None),
+        MalType::List(inner, _) | MalType::Vector(inner, _) | MalType::Set(inner, _) => {
+            Ok(items_step(Rc::from(inner.as_slice()), 0))
+        }
+        other => match SeqIter::new(other)? {
+            SeqIter::Items(items) => Ok(items_step(items.collect(), 0)),
+            _ => unreachable!(),
+        },
+    }
+}
+
+/// Steps through an eager collection by index, copied once when the first
+/// step is taken, so walking it stays linear.
+fn items_step(items: Rc<[MalType]>, index: usize) -> Step {
+    let first = items.get(index)?.clone();
+    let rest = match index + 1 < items.len() {
+        true => MalType::Lazy(LazySeq::new(move || {
+            Ok(items_step(Rc::clone(&items), index + 1))
+        })),
+        false => MalType::Nil,
+    };
+    Some((first, rest))
+}
+
+/// Walks any seqable value. Hash-maps yield `[key value]` vectors and strings
+/// yield one string per character.
+pub enum SeqIter {
+    Items(std::vec::IntoIter<MalType>),
+    /// A lazy sequence, and how many of its items were walked so far.
+    Lazy(LazySeq, usize),
+    Done,
+}
+
+impl SeqIter {
+    pub fn new(value: &MalType) -> Result<Self> {
+        let iter = match value {
+            MalType::List(inner, _) | MalType::Vector(inner, _) | MalType::Set(inner, _) => {
+                SeqIter::Items(inner.clone().into_iter())
+            }
+            MalType::HashMap(inner, _) => SeqIter::Items(
+                inner
+                    .chunks(2)
+                    .map(|pair| MalType::Vector(pair.to_vec(), None))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ),
+            MalType::Record(record) => SeqIter::Items(
+                record
+                    .entries()
+                    .chunks(2)
+                    .map(|pair| MalType::Vector(pair.to_vec(), None))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ),
+            MalType::String(s) => SeqIter::Items(
+                s.chars()
+                    .map(|c| MalType::String(c.to_string()))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ),
+            MalType::Lazy(seq) => SeqIter::Lazy(seq.clone(), 0),
+            MalType::Nil => SeqIter::Done,
+            other => anyhow::bail!("{} is not a sequence", other),
+        };
+        Ok(iter)
+    }
+}
+
+impl Iterator for SeqIter {
+    type Item = Result<MalType>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SeqIter::Items(items) => items.next().map(Ok),
+            SeqIter::Lazy(seq, walked) => match step_within_limits(seq, *walked) {
+                Ok(Some((first, rest))) => match SeqIter::new(&rest) {
+                    Ok(rest) => {
+                        *self = match rest {
+                            SeqIter::Lazy(rest, _) => SeqIter::Lazy(rest, *walked + 1),
+                            rest => rest,
+                        };
+                        Some(Ok(first))
+                    }
+                    Err(err) => {
+                        *self = SeqIter::Done;
+                        Some(Err(err))
+                    }
+                },
+                Ok(None) => {
+                    *self = SeqIter::Done;
+                    None
+                }
+                Err(err) => {
+                    *self = SeqIter::Done;
+                    Some(Err(err))
+                }
+            },
+            SeqIter::Done => None,
+        }
+    }
+}
+
+/// Realizes the next item of a sequence that already yielded `walked` items,
+/// as long as the active sandbox allows it and nothing interrupted it.
+fn step_within_limits(seq: &LazySeq, walked: usize) -> Result<Step> {
+    crate::interrupt::check()?;
+    crate::sandbox::tick()?;
+    crate::sandbox::check_size(walked + 1)?;
+    seq.step()
+}
+
+/// How many items of a lazy sequence the REPL realizes before printing `...`,
+/// so echoing an infinite sequence doesn't hang the session.
+pub const PRINT_LENGTH: usize = 100;
+
+/// Realizes every lazy sequence reachable from `value`, so it can be printed
+/// without running mal code from inside `Display`.
+pub fn realize(value: &MalType) -> Result<()> {
+    realize_up_to(value, usize::MAX)
+}
+
+/// Like `realize`, but stops each lazy sequence after `limit` items.
+pub fn realize_up_to(value: &MalType, limit: usize) -> Result<()> {
+    match value {
+        MalType::Lazy(_) => {
+            for item in SeqIter::new(value)?.take(limit) {
+                realize_up_to(&item?, limit)?;
+            }
+            Ok(())
+        }
+        MalType::List(inner, _)
+        | MalType::Vector(inner, _)
+        | MalType::Set(inner, _)
+        | MalType::HashMap(inner, _) => {
+            inner.iter().try_for_each(|item| realize_up_to(item, limit))
+        }
+        MalType::Record(record) => record
+            .entries()
+            .iter()
+            .try_for_each(|item| realize_up_to(item, limit)),
+        MalType::Tagged(_, value) => realize_up_to(value, limit),
+        MalType::Atom(atom) => realize_up_to(&atom.deref(), limit),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    #[test]
+    fn drop_long_chains() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        // Realized, then dropped along with the arguments of count
+        let interpreter = Interpreter::new();
+        assert_eq!(
+            interpreter.eval_str("(count (range 1000000))").unwrap(),
+            MalType::Number(1_000_000)
+        );
+
+        let mut chain = MalType::Nil;
+        for i in 0..1_000_000 {
+            chain = MalType::Lazy(LazySeq::realized(Some((MalType::Number(i), chain))));
+        }
+        let (items, complete) = match &chain {
+            MalType::Lazy(seq) => seq.cached(),
+            _ => unreachable!(),
+        };
+        assert_eq!((items.len(), complete), (1_000_000, true));
+        drop(chain);
+    }
+}
@@ -0,0 +1,37 @@
+//! A mal interpreter, usable as a library through [`Interpreter`]. The step
+//! binaries are REPLs built on top of it.
+
+mod atom;
+pub mod console;
+mod core;
+pub mod debug;
+mod doc;
+mod edn;
+pub mod environment;
+pub mod eval;
+mod expr;
+pub mod gc;
+pub mod intern;
+mod interpreter;
+pub mod interrupt;
+mod json;
+pub mod lazy;
+mod multimethod;
+mod namespace;
+pub mod native;
+pub mod profile;
+mod protocol;
+pub mod reader;
+mod record;
+pub mod sandbox;
+pub mod stack;
+pub mod types;
+
+pub use interpreter::{Error, Interpreter, Result};
+pub use interrupt::Interrupt;
+pub use sandbox::{LimitExceeded, Sandbox};
+pub use types::MalType;
+
+// Used by `native_fn!`
+#[doc(hidden)]
+pub use anyhow;
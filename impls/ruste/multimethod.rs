@@ -0,0 +1,234 @@
+use crate::{environment::Environment, types::MalType};
+use anyhow::Result;
+use std::{cell::RefCell, fmt::Debug, rc::Rc};
+
+/// Parent relations set up by `derive`, one set per interpreter. The version
+/// changes with every edit, which tells multimethods their cached dispatch
+/// is stale.
+#[derive(Default)]
+pub struct Hierarchy {
+    parents: Vec<(MalType, Vec<MalType>)>,
+    version: usize,
+}
+
+impl Hierarchy {
+    fn parents_of(&self, child: &MalType) -> Vec<MalType> {
+        self.parents
+            .iter()
+            .find(|(tag, _)| tag == child)
+            .map(|(_, parents)| parents.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn ancestors(&self, child: &MalType) -> Vec<MalType> {
+        let mut ancestors = vec![];
+        let mut pending = self.parents_of(child);
+        while let Some(parent) = pending.pop() {
+            if !ancestors.contains(&parent) {
+                pending.extend(self.parents_of(&parent));
+                ancestors.push(parent);
+            }
+        }
+        ancestors
+    }
+
+    /// Whether `child` equals `parent` or derives from it. Vectors match item
+    /// by item, so `[:circle :square]` isa `[:shape :shape]`.
+    pub fn isa(&self, child: &MalType, parent: &MalType) -> bool {
+        if child == parent {
+            return true;
+        }
+        match (child, parent) {
+            (MalType::Vector(children, _), MalType::Vector(parents, _)) => {
+                children.len() == parents.len()
+                    && children.iter().zip(parents).all(|(c, p)| self.isa(c, p))
+            }
+            _ => self.ancestors(child).contains(parent),
+        }
+    }
+
+    /// Makes `child` a kind of `parent`, refusing to create a cycle.
+    pub fn derive(&mut self, child: &MalType, parent: &MalType) -> Result<()> {
+        if self.isa(parent, child) {
+            anyhow::bail!("Cyclic derivation: {:b} already isa {:b}", parent, child)
+        }
+        match self.parents.iter_mut().find(|(tag, _)| tag == child) {
+            Some((_, parents)) if parents.contains(parent) => (),
+            Some((_, parents)) => parents.push(parent.clone()),
+            None => self.parents.push((child.clone(), vec![parent.clone()])),
+        }
+        self.version += 1;
+        Ok(())
+    }
+
+    pub fn underive(&mut self, child: &MalType, parent: &MalType) {
+        if let Some((_, parents)) = self.parents.iter_mut().find(|(tag, _)| tag == child) {
+            parents.retain(|p| p != parent);
+        }
+        self.version += 1;
+    }
+}
+
+struct Table {
+    name: String,
+    dispatch: MalType,
+    methods: Vec<(MalType, MalType)>,
+    // dispatch value -> method, valid for one version of the hierarchy
+    cache: Vec<(MalType, MalType)>,
+    cache_version: usize,
+}
+
+/// A function that calls `dispatch` on its arguments and runs the method
+/// registered for the value it returns, or for a value it derives from.
+#[derive(Clone)]
+pub struct MultiFn(Rc<RefCell<Table>>);
+
+impl MultiFn {
+    pub fn name(&self) -> String {
+        self.0.borrow().name.clone()
+    }
+
+    pub fn ptr_eq(&self, other: &MultiFn) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub fn eval(&self, args: &[MalType], env: &Environment) -> Result<MalType> {
+        let dispatch = self.0.borrow().dispatch.clone();
+        let value = dispatch.eval(args, env)?;
+        let method = self.method(&value, env.namespaces().borrow().hierarchy())?;
+        method.eval(args, env)
+    }
+
+    pub fn add_method(&self, value: MalType, method: MalType) {
+        let mut table = self.0.borrow_mut();
+        table.methods.retain(|(v, _)| v != &value);
+        table.methods.push((value, method));
+        table.cache.clear();
+    }
+
+    pub fn remove_method(&self, value: &MalType) {
+        let mut table = self.0.borrow_mut();
+        table.methods.retain(|(v, _)| v != value);
+        table.cache.clear();
+    }
+
+    /// The methods by dispatch value, as a map.
+    pub fn methods(&self) -> MalType {
+        let table = self.0.borrow();
+        MalType::HashMap(
+            table
+                .methods
+                .iter()
+                .flat_map(|(value, method)| [value.clone(), method.clone()])
+                .collect(),
+            None,
+        )
+    }
+
+    fn method(&self, value: &MalType, hierarchy: &Hierarchy) -> Result<MalType> {
+        let mut table = self.0.borrow_mut();
+        if table.cache_version != hierarchy.version {
+            table.cache.clear();
+            table.cache_version = hierarchy.version;
+        }
+        if let Some((_, method)) = table.cache.iter().find(|(v, _)| v == value) {
+            return Ok(method.clone());
+        }
+
+        let method = table.find(value, hierarchy)?;
+        table.cache.push((value.clone(), method.clone()));
+        Ok(method)
+    }
+}
+
+impl Table {
+    /// An exact match, else the most specific method `value` derives from,
+    /// else the `:default` method.
+    fn find(&self, value: &MalType, hierarchy: &Hierarchy) -> Result<MalType> {
+        if let Some((_, method)) = self.methods.iter().find(|(v, _)| v == value) {
+            return Ok(method.clone());
+        }
+
+        let matches: Vec<&(MalType, MalType)> = self
+            .methods
+            .iter()
+            .filter(|(v, _)| hierarchy.isa(value, v))
+            .collect();
+        let best = matches
+            .iter()
+            .find(|(best, _)| matches.iter().all(|(other, _)| hierarchy.isa(best, other)));
+        match (best, matches.as_slice()) {
+            (Some((_, method)), _) => return Ok(method.clone()),
+            (None, [(first, _), (second, _), ..]) => anyhow::bail!(
+                "Multiple methods in multimethod {} match dispatch value {:b}: {:b} and {:b}",
+                self.name,
+                value,
+                first,
+                second
+            ),
+            _ => (),
+        }
+
+        let default = MalType::Keyword("default".into());
+        match self.methods.iter().find(|(v, _)| v == &default) {
+            Some((_, method)) => Ok(method.clone()),
+            None => anyhow::bail!(
+                "No method in multimethod {} for dispatch value {:b}",
+                self.name,
+                value
+            ),
+        }
+    }
+}
+
+impl Debug for MultiFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MultiFn({})", self.0.borrow().name)
+    }
+}
+
+/// `(defmulti name dispatch-fn)`, where a docstring may precede the dispatch
+/// fn. Redefining starts over without any methods.
+pub fn defmulti(args: &[MalType], env: &mut Environment) -> Result<MalType> {
+    let (name, dispatch) = match args {
+        [MalType::Symbol(name, _), dispatch]
+        | [MalType::Symbol(name, _), MalType::String(_), dispatch] => (name, dispatch),
+        _ => anyhow::bail!(
+            "defmulti expects a name and a dispatch fn, received {:?}",
+            args
+        ),
+    };
+
+    let dispatch = crate::eval::eval(dispatch, env)?;
+    let multi = MalType::Multi(MultiFn(Rc::new(RefCell::new(Table {
+        name: name.to_string(),
+        dispatch,
+        methods: vec![],
+        cache: vec![],
+        cache_version: 0,
+    }))));
+    env.set(MalType::Symbol(*name, None), multi.clone());
+    Ok(multi)
+}
+
+/// `(defmethod name dispatch-value [params] body...)`, or with a list per
+/// arity as `fn*` takes them.
+pub fn defmethod(args: &[MalType], env: &mut Environment) -> Result<MalType> {
+    let (multi, value, fn_tail) = match args {
+        [multi, value, fn_tail @ ..] if !fn_tail.is_empty() => (multi, value, fn_tail),
+        _ => anyhow::bail!("defmethod received unexpected value {:?}", args),
+    };
+
+    let multi = match crate::eval::eval(multi, env)? {
+        MalType::Multi(multi) => multi,
+        other => anyhow::bail!("defmethod expects a multimethod, got {:b}", other),
+    };
+    let value = crate::eval::eval(value, env)?;
+
+    let mut fn_form = vec![MalType::Symbol("fn*".into(), None)];
+    fn_form.extend(fn_tail.iter().cloned());
+    let method = crate::eval::eval(&MalType::List(fn_form, None), env)?;
+
+    multi.add_method(value, method);
+    Ok(MalType::Multi(multi))
+}
@@ -0,0 +1,436 @@
+use crate::{
+    environment::{Environment, Frame, InnerEnv},
+    expr::Expressions,
+    intern::{Sym, SymMap, AS},
+    multimethod::Hierarchy,
+    reader::{Lexer, Parser},
+    record::RecordType,
+    types::MalType,
+};
+use anyhow::{Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// The namespace the REPL starts in.
+pub const DEFAULT_NS: &str = "user";
+
+/// A namespace is a top level scope of its own, along with the aliases and
+/// referred names that forms evaluated in it resolve through.
+pub struct Namespace {
+    frame: Frame,
+    aliases: HashMap<String, String>,
+    refers: SymMap<(String, Sym)>,
+    records: SymMap<Rc<RecordType>>,
+}
+
+impl Namespace {
+    fn new() -> Self {
+        Self {
+            frame: InnerEnv::new(Expressions::new()),
+            aliases: HashMap::new(),
+            refers: SymMap::default(),
+            records: SymMap::default(),
+        }
+    }
+}
+
+pub struct Namespaces {
+    current: String,
+    spaces: HashMap<String, Namespace>,
+    loaded: HashSet<String>,
+    loading: Vec<String>,
+    load_path: Vec<PathBuf>,
+    hierarchy: Hierarchy,
+}
+
+impl Namespaces {
+    /// Starts out in `user`, loading from the directories in `MAL_LOAD_PATH`,
+    /// or the working directory when it isn't set.
+    pub fn new() -> Self {
+        let load_path = match std::env::var_os("MAL_LOAD_PATH") {
+            Some(paths) => std::env::split_paths(&paths).collect(),
+            None => vec![PathBuf::from(".")],
+        };
+
+        let mut s = Self {
+            current: DEFAULT_NS.to_string(),
+            spaces: HashMap::new(),
+            loaded: HashSet::new(),
+            loading: vec![],
+            load_path,
+            hierarchy: Hierarchy::default(),
+        };
+        s.spaces.insert(DEFAULT_NS.to_string(), Namespace::new());
+        s
+    }
+
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    /// The top level scope of every namespace.
+    pub fn frames(&self) -> impl Iterator<Item = &Frame> {
+        self.spaces.values().map(|ns| &ns.frame)
+    }
+
+    /// Drops every namespace, returning them to be dropped once nothing is
+    /// borrowed.
+    pub fn clear(&mut self) -> HashMap<String, Namespace> {
+        std::mem::take(&mut self.spaces)
+    }
+
+    pub fn frame(&self, ns: &str) -> Option<&Frame> {
+        self.spaces.get(ns).map(|ns| &ns.frame)
+    }
+
+    /// Switches to `ns`, creating it on first use.
+    pub fn enter(&mut self, ns: &str) {
+        self.spaces
+            .entry(ns.to_string())
+            .or_insert_with(Namespace::new);
+        self.current = ns.to_string();
+    }
+
+    /// The full name `alias` stands for inside `from`, or `alias` itself.
+    pub fn resolve_alias<'a>(&'a self, from: &str, alias: &'a str) -> &'a str {
+        self.spaces
+            .get(from)
+            .and_then(|ns| ns.aliases.get(alias))
+            .map(String::as_str)
+            .unwrap_or(alias)
+    }
+
+    /// Every namespace, sorted by name.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.spaces.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// The names referred into `from`.
+    pub fn refers(&self, from: &str) -> Vec<String> {
+        self.spaces
+            .get(from)
+            .map(|ns| ns.refers.keys().map(ToString::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    /// The namespace and name that `name` was referred from inside `from`.
+    pub fn referred(&self, from: &str, name: Sym) -> Option<(&str, Sym)> {
+        let (ns, name) = self.spaces.get(from)?.refers.get(&name)?;
+        Some((ns, *name))
+    }
+
+    /// The record type `name` refers to inside `from`: a bare name is looked
+    /// up in `from` itself, `alias/Name` and `my.ns.Name` in the namespace
+    /// they name.
+    pub fn record_type(&self, from: &str, name: &str) -> Option<&Rc<RecordType>> {
+        let (ns, name) = match (name.split_once('/'), name.rsplit_once('.')) {
+            (Some((alias, name)), _) => (self.resolve_alias(from, alias), name),
+            (None, Some((ns, name))) => (ns, name),
+            (None, None) => (from, name),
+        };
+        self.spaces.get(ns)?.records.get(&Sym::new(name))
+    }
+
+    pub fn add_record(&mut self, ns: &str, name: Sym, ty: Rc<RecordType>) {
+        if let Some(ns) = self.spaces.get_mut(ns) {
+            ns.records.insert(name, ty);
+        }
+    }
+
+    /// The relations `derive` set up, which multimethods dispatch through.
+    pub fn hierarchy(&self) -> &Hierarchy {
+        &self.hierarchy
+    }
+
+    pub fn hierarchy_mut(&mut self) -> &mut Hierarchy {
+        &mut self.hierarchy
+    }
+
+    pub fn load_path(&self) -> &[PathBuf] {
+        &self.load_path
+    }
+
+    pub fn set_load_path(&mut self, load_path: Vec<PathBuf>) {
+        self.load_path = load_path;
+    }
+
+    /// Finds the file defining `ns` on the load path, so `my.cool-lib` is
+    /// read from `my/cool_lib.mal`.
+    fn locate(&self, ns: &str) -> Option<PathBuf> {
+        let file = file_name(ns);
+        self.load_path
+            .iter()
+            .map(|dir| dir.join(&file))
+            .find(|path| path.is_file())
+    }
+
+    fn add_alias(&mut self, from: &str, alias: &str, ns: &str) {
+        if let Some(from) = self.spaces.get_mut(from) {
+            from.aliases.insert(alias.to_string(), ns.to_string());
+        }
+    }
+
+    fn add_refer(&mut self, from: &str, name: &str, ns: &str) {
+        if let Some(from) = self.spaces.get_mut(from) {
+            from.refers
+                .insert(Sym::new(name), (ns.to_string(), Sym::new(name)));
+        }
+    }
+}
+
+fn file_name(ns: &str) -> PathBuf {
+    PathBuf::from(format!("{}.mal", ns.replace('.', "/").replace('-', "_")))
+}
+
+/// `(ns name (:require specs...))` switches to `name`, creating it if need
+/// be, and then requires each spec from inside it.
+pub fn ns(args: &[MalType], env: &Environment) -> Result<MalType> {
+    let (name, clauses) = match args.split_first() {
+        Some((MalType::Symbol(name, _), clauses)) => (name, clauses),
+        _ => anyhow::bail!("ns expects a namespace name, received {:?}", args),
+    };
+
+    env.namespaces().borrow_mut().enter(name);
+    let env = env.current_ns();
+    for clause in clauses {
+        match clause {
+            MalType::List(items, _)
+                if items.first() == Some(&MalType::Keyword("require".into())) =>
+            {
+                items[1..].iter().try_for_each(|spec| require(spec, &env))?
+            }
+            other => anyhow::bail!("ns received unexpected clause {:b}", other),
+        }
+    }
+
+    Ok(MalType::Nil)
+}
+
+/// Loads a namespace unless that already happened, given either as a bare
+/// name or as `[name :as alias :refer [names...]]`.
+pub fn require(spec: &MalType, env: &Environment) -> Result<()> {
+    let (ns, opts) = match spec {
+        MalType::Symbol(ns, _) => (ns, &[][..]),
+        MalType::Vector(items, _) | MalType::List(items, _) => match items.split_first() {
+            Some((MalType::Symbol(ns, _), opts)) if opts.len().is_multiple_of(2) => (ns, opts),
+            _ => anyhow::bail!("require received unexpected spec {:b}", spec),
+        },
+        other => anyhow::bail!("require received unexpected spec {:b}", other),
+    };
+
+    load(ns, env)?;
+    for opt in opts.chunks(2) {
+        match (&opt[0], &opt[1]) {
+            (MalType::Keyword(key), MalType::Symbol(alias, _)) if *key == *AS => env
+                .namespaces()
+                .borrow_mut()
+                .add_alias(env.ns_name(), alias, ns),
+            (MalType::Keyword(key), names) if key == "refer" => refer(ns, names, env)?,
+            (key, _) => anyhow::bail!("require received unexpected option {:b}", key),
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes `names` from `ns` resolvable without qualification, where `names`
+/// is a list of symbols or `:all`.
+pub fn refer(ns: &str, names: &MalType, env: &Environment) -> Result<()> {
+    let mut namespaces = env.namespaces().borrow_mut();
+    let names = {
+        let frame = match namespaces.frame(ns) {
+            Some(frame) => frame.borrow(),
+            None => anyhow::bail!("No namespace {} to refer from", ns),
+        };
+        match names {
+            MalType::Keyword(all) if all == "all" => frame.names(),
+            MalType::Vector(names, _) | MalType::List(names, _) => names
+                .iter()
+                .map(|name| match name {
+                    MalType::Symbol(name, _) if frame.defines(name) => Ok(name.to_string()),
+                    MalType::Symbol(name, _) => anyhow::bail!("{} does not define {}", ns, name),
+                    other => anyhow::bail!("refer expects symbols to refer, received {:b}", other),
+                })
+                .collect::<Result<Vec<_>>>()?,
+            other => anyhow::bail!("refer received unexpected value {:b}", other),
+        }
+    };
+
+    for name in names {
+        namespaces.add_refer(env.ns_name(), &name, ns);
+    }
+    Ok(())
+}
+
+/// Loads the file for `ns` once, keeping track of what is being loaded so a
+/// cycle of requires is reported rather than recursing forever.
+fn load(ns: &str, env: &Environment) -> Result<()> {
+    let path = {
+        let namespaces = env.namespaces().borrow();
+        if namespaces.loaded.contains(ns) {
+            return Ok(());
+        }
+        if namespaces.loading.iter().any(|loading| loading == ns) {
+            anyhow::bail!(
+                "Cyclic require of {} while loading {}",
+                ns,
+                namespaces.loading.join(" -> ")
+            )
+        }
+        if !namespaces.spaces.contains_key(ns) {
+            crate::sandbox::check_io(&format!("Loading {}", file_name(ns).display()))?;
+        }
+        match namespaces.locate(ns) {
+            Some(path) => path,
+            // Namespaces made at the REPL have no file behind them
+            None if namespaces.spaces.contains_key(ns) => return Ok(()),
+            None => anyhow::bail!(
+                "Could not locate {} on the load path {:?}",
+                file_name(ns).display(),
+                namespaces.load_path
+            ),
+        }
+    };
+
+    let previous = {
+        let mut namespaces = env.namespaces().borrow_mut();
+        namespaces.loading.push(ns.to_string());
+        namespaces.current.clone()
+    };
+    let ret = load_file(&path, env);
+    {
+        let mut namespaces = env.namespaces().borrow_mut();
+        namespaces.loading.pop();
+        namespaces.current = previous;
+        if ret.is_ok() {
+            namespaces.loaded.insert(ns.to_string());
+        }
+    }
+    ret.with_context(|| format!("while loading {}", path.display()))?;
+
+    if !env.namespaces().borrow().spaces.contains_key(ns) {
+        anyhow::bail!("{} did not define namespace {}", path.display(), ns)
+    }
+    Ok(())
+}
+
+/// Evaluates every form in a file at the top level of the current namespace,
+/// returning the value of the last one.
+pub fn load_file(path: &Path, env: &Environment) -> Result<MalType> {
+    crate::sandbox::check_io(&format!("Loading {}", path.display()))?;
+    let input = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    let forms = Parser::new(Lexer::tokenize(&input))
+        .with_positions(Some(&path.display().to_string()))
+        .parse()?;
+
+    let mut ret = MalType::Nil;
+    for form in forms {
+        ret = crate::eval::eval(&form, &mut env.current_ns())?;
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str, env: &Environment) -> Result<MalType> {
+        let forms = Parser::new(Lexer::tokenize(input)).parse()?;
+        crate::eval::eval(&forms[0], &mut env.current_ns())
+    }
+
+    #[test]
+    fn require_and_refer() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        let dir = std::env::temp_dir().join(format!("ruste-ns-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("geometry")).unwrap();
+        std::fs::write(
+            dir.join("geometry/shapes.mal"),
+            "(ns geometry.shapes)\n(def! side 3)\n(def! area (fn* () (* side side)))\n",
+        )
+        .unwrap();
+
+        let env = Environment::new();
+        env.namespaces()
+            .borrow_mut()
+            .set_load_path(vec![dir.clone()]);
+
+        eval("(require [geometry.shapes :as s :refer [area]])", &env).unwrap();
+        assert_eq!(eval("(s/area)", &env).unwrap(), MalType::Number(9));
+        assert_eq!(eval("(area)", &env).unwrap(), MalType::Number(9));
+        assert_eq!(
+            eval("geometry.shapes/side", &env).unwrap(),
+            MalType::Number(3)
+        );
+        assert!(eval("side", &env).is_err());
+
+        // Loaded once, so a second require doesn't need the file any more
+        std::fs::remove_dir_all(&dir).unwrap();
+        eval("(require geometry.shapes)", &env).unwrap();
+        assert!(eval("(require missing.lib)", &env).is_err());
+
+        eval("(ns other)", &env).unwrap();
+        eval("(def! side 10)", &env).unwrap();
+        assert_eq!(eval("side", &env).unwrap(), MalType::Number(10));
+        assert_eq!(
+            eval("s/side", &env).unwrap_err().to_string(),
+            "s/side not found"
+        );
+    }
+
+    #[test]
+    fn records_per_namespace() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        let env = Environment::new();
+        eval("(defprotocol Shape (area [this]))", &env).unwrap();
+        eval("(defrecord Sq [side])", &env).unwrap();
+        eval(
+            "(extend-type Sq Shape (area [s] (* (:side s) (:side s))))",
+            &env,
+        )
+        .unwrap();
+        eval("(def! sq (->Sq 3))", &env).unwrap();
+
+        // Another Sq, which the one in user doesn't dispatch as
+        eval("(ns other)", &env).unwrap();
+        eval("(defrecord Sq [w])", &env).unwrap();
+        eval("(extend-type Sq user/Shape (area [s] (:w s)))", &env).unwrap();
+        assert_eq!(
+            eval("(user/area (->Sq 5))", &env).unwrap(),
+            MalType::Number(5)
+        );
+        assert_eq!(
+            eval("(user/area user/sq)", &env).unwrap(),
+            MalType::Number(9)
+        );
+        eval("(extend-type user.Sq user/Shape (area [s] 0))", &env).unwrap();
+        assert_eq!(
+            eval("(user/area user/sq)", &env).unwrap(),
+            MalType::Number(0)
+        );
+
+        // Nor does a separate environment know about either
+        let separate = Environment::new();
+        eval("(defprotocol Shape (area [this]))", &separate).unwrap();
+        assert_eq!(
+            eval("(extend-type Sq Shape (area [s] 1))", &separate)
+                .unwrap_err()
+                .to_string(),
+            "Cannot extend protocol Shape to unknown type Sq"
+        );
+    }
+}
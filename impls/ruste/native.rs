@@ -0,0 +1,310 @@
+use crate::{
+    atom::Atom,
+    interpreter::Error,
+    multimethod::MultiFn,
+    protocol::Protocol,
+    types::{MalRegex, MalType},
+};
+use std::collections::HashMap;
+
+/// How a parameter of a `native_fn!` takes its arguments.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Exactly one argument.
+    Required,
+    /// One argument if there is one left, as for `Option<T>`.
+    Optional,
+    /// All remaining arguments, as for `&[MalType]`.
+    Rest,
+}
+
+/// A type a `native_fn!` parameter can have, converted from the argument
+/// passed in from mal.
+pub trait FromArg<'a>: Sized {
+    const KIND: Kind = Kind::Required;
+
+    fn from_value(value: &'a MalType) -> Result<Self, Error>;
+
+    /// Takes this parameter's arguments off the front of `args`, which the
+    /// arity check made sure has enough of them.
+    fn take(args: &mut &'a [MalType]) -> Result<Self, Error> {
+        let (first, rest) = args.split_first().expect("arity was checked");
+        *args = rest;
+        Self::from_value(first)
+    }
+}
+
+pub fn mismatch(expected: &'static str, found: &MalType) -> Error {
+    Error::Type {
+        expected,
+        found: format!("{:b}", found),
+    }
+}
+
+impl<'a> FromArg<'a> for MalType {
+    fn from_value(value: &'a MalType) -> Result<Self, Error> {
+        Ok(value.clone())
+    }
+}
+
+impl<'a> FromArg<'a> for &'a MalType {
+    fn from_value(value: &'a MalType) -> Result<Self, Error> {
+        Ok(value)
+    }
+}
+
+impl<'a> FromArg<'a> for &'a str {
+    fn from_value(value: &'a MalType) -> Result<Self, Error> {
+        match value {
+            MalType::String(s) => Ok(s),
+            other => Err(mismatch("a string", other)),
+        }
+    }
+}
+
+/// Owned Rust values convert the same way `Interpreter` results do.
+macro_rules! from_arg_by_try_from {
+    ($($ty:ty),*) => {
+        $(impl<'a> FromArg<'a> for $ty {
+            fn from_value(value: &'a MalType) -> Result<Self, Error> {
+                Self::try_from(value.clone())
+            }
+        })*
+    };
+}
+
+from_arg_by_try_from!(i64, f64, bool, String);
+
+impl<'a, T: TryFrom<MalType, Error = Error>> FromArg<'a> for Vec<T> {
+    fn from_value(value: &'a MalType) -> Result<Self, Error> {
+        Self::try_from(value.clone())
+    }
+}
+
+impl<'a, T: TryFrom<MalType, Error = Error>> FromArg<'a> for HashMap<String, T> {
+    fn from_value(value: &'a MalType) -> Result<Self, Error> {
+        Self::try_from(value.clone())
+    }
+}
+
+/// Optional parameters may be left out or passed nil.
+impl<'a, T: FromArg<'a>> FromArg<'a> for Option<T> {
+    const KIND: Kind = Kind::Optional;
+
+    fn from_value(value: &'a MalType) -> Result<Self, Error> {
+        match value {
+            MalType::Nil => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+
+    fn take(args: &mut &'a [MalType]) -> Result<Self, Error> {
+        match args.is_empty() {
+            true => Ok(None),
+            false => {
+                let (first, rest) = args.split_first().unwrap();
+                *args = rest;
+                Self::from_value(first)
+            }
+        }
+    }
+}
+
+impl<'a> FromArg<'a> for &'a [MalType] {
+    const KIND: Kind = Kind::Rest;
+
+    fn from_value(value: &'a MalType) -> Result<Self, Error> {
+        Ok(std::slice::from_ref(value))
+    }
+
+    fn take(args: &mut &'a [MalType]) -> Result<Self, Error> {
+        Ok(std::mem::take(args))
+    }
+}
+
+macro_rules! from_arg_by_variant {
+    ($ty:ty, $variant:ident, $expected:literal) => {
+        impl<'a> FromArg<'a> for &'a $ty {
+            fn from_value(value: &'a MalType) -> Result<Self, Error> {
+                match value {
+                    MalType::$variant(inner) => Ok(inner),
+                    other => Err(mismatch($expected, other)),
+                }
+            }
+        }
+    };
+}
+
+from_arg_by_variant!(Atom, Atom, "an atom");
+from_arg_by_variant!(MalRegex, Regex, "a regex");
+from_arg_by_variant!(MultiFn, Multi, "a multimethod");
+from_arg_by_variant!(Protocol, Protocol, "a protocol");
+
+/// Fails unless `count` arguments fit parameters of the given kinds.
+pub fn check_arity(name: &str, kinds: &[Kind], count: usize) -> anyhow::Result<()> {
+    let required = kinds.iter().filter(|kind| **kind == Kind::Required).count();
+    let expecting = match kinds.contains(&Kind::Rest) {
+        true if count >= required => return Ok(()),
+        true => format!("at least {}", required),
+        false if (required..=kinds.len()).contains(&count) => return Ok(()),
+        false if required == kinds.len() => required.to_string(),
+        false => format!("{} to {}", required, kinds.len()),
+    };
+    anyhow::bail!(
+        "Wrong number of args ({}) passed to {} expecting {}",
+        count,
+        name,
+        expecting
+    )
+}
+
+/// Names the argument at `position`, counting from 1, in a type error.
+pub fn arg_error(name: &str, position: usize, err: Error) -> anyhow::Error {
+    match err {
+        Error::Type { expected, found } => anyhow::anyhow!(
+            "{} expects {} as argument {}, got {}",
+            name,
+            expected,
+            position,
+            found
+        ),
+        Error::Read(err) | Error::Eval(err) => err,
+        err => err.into(),
+    }
+}
+
+/// Defines a native function from a Rust fn with typed parameters. The
+/// generated fn has the signature core functions and `Interpreter::register`
+/// take: it checks the number of arguments, converts each one through
+/// [`FromArg`] and turns the result into a `MalType`.
+///
+/// ```
+/// use ruste::{native_fn, Interpreter};
+///
+/// native_fn! {
+///     #[name = "subs"]
+///     fn subs(s: &str, start: i64, end: Option<i64>) -> anyhow::Result<String> {
+///         let chars: Vec<char> = s.chars().collect();
+///         let end = end.unwrap_or(chars.len() as i64);
+///         match chars.get(start as usize..end as usize) {
+///             Some(chars) => Ok(chars.iter().collect()),
+///             None => anyhow::bail!("subs range {}..{} out of bounds", start, end),
+///         }
+///     }
+/// }
+///
+/// let interpreter = Interpreter::new();
+/// interpreter.register("subs", subs);
+/// let s: String = interpreter.eval_str(r#"(subs "hello" 1 3)"#)?.try_into()?;
+/// assert_eq!(s, "el");
+/// # Ok::<(), ruste::Error>(())
+/// ```
+///
+/// A first parameter of type `&Environment` receives the caller's
+/// environment, for functions that call back into mal.
+#[macro_export]
+macro_rules! native_fn {
+    (
+        #[name = $mal:literal]
+        $(#[$attr:meta])*
+        $vis:vis fn $name:ident($env:ident: &Environment $(, $arg:ident: $ty:ty)* $(,)?) -> $ret:ty $body:block
+    ) => {
+        $(#[$attr])*
+        $vis fn $name(
+            args: &[$crate::types::MalType],
+            env: $crate::environment::Environment,
+        ) -> $crate::anyhow::Result<$crate::types::MalType> {
+            fn inner(
+                $env: &$crate::environment::Environment,
+                $($arg: $ty),*
+            ) -> $ret $body
+
+            $crate::native_fn!(@convert $mal, args, $($arg: $ty),*);
+            inner(&env, $($arg),*).map(Into::into).map_err(Into::into)
+        }
+    };
+    (
+        #[name = $mal:literal]
+        $(#[$attr:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block
+    ) => {
+        $(#[$attr])*
+        $vis fn $name(
+            args: &[$crate::types::MalType],
+            _: $crate::environment::Environment,
+        ) -> $crate::anyhow::Result<$crate::types::MalType> {
+            fn inner($($arg: $ty),*) -> $ret $body
+
+            $crate::native_fn!(@convert $mal, args, $($arg: $ty),*);
+            inner($($arg),*).map(Into::into).map_err(Into::into)
+        }
+    };
+    (@convert $mal:literal, $args:ident, $($arg:ident: $ty:ty),*) => {
+        $crate::native::check_arity(
+            $mal,
+            &[$(<$ty as $crate::native::FromArg>::KIND),*],
+            $args.len(),
+        )?;
+        #[allow(unused_mut, unused_variables)]
+        let mut rest = $args;
+        $(
+            let position = $args.len() - rest.len() + 1;
+            let $arg = <$ty as $crate::native::FromArg>::take(&mut rest)
+                .map_err(|err| $crate::native::arg_error($mal, position, err))?;
+        )*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Interpreter, MalType};
+
+    #[test]
+    fn conversion_and_arity() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        native_fn! {
+            #[name = "sum-of"]
+            fn sum_of(first: i64, rest: &[MalType]) -> anyhow::Result<i64> {
+                let rest: Vec<i64> = MalType::List(rest.to_vec(), None).try_into()?;
+                Ok(first + rest.iter().sum::<i64>())
+            }
+        }
+
+        let interpreter = Interpreter::new();
+        interpreter.register("sum-of", sum_of);
+        let eval = |input: &str| interpreter.eval_str(input);
+        let error = |input: &str| eval(input).unwrap_err().to_string();
+
+        assert_eq!(eval("(sum-of 1 2 3)").unwrap(), MalType::Number(6));
+        assert_eq!(eval("(sum-of 1)").unwrap(), MalType::Number(1));
+        assert_eq!(
+            error("(sum-of)"),
+            "Wrong number of args (0) passed to sum-of expecting at least 1"
+        );
+
+        assert_eq!(
+            eval(r#"[(subs "hello" 1) (subs "hello" 1 3) (subs "hello" 1 nil)]"#).unwrap(),
+            MalType::Vector(vec!["ello".into(), "el".into(), "ello".into()], None,)
+        );
+        assert_eq!(
+            error(r#"(subs "hello")"#),
+            "Wrong number of args (1) passed to subs expecting 2 to 3"
+        );
+        assert_eq!(
+            error(r#"(subs "hello" "1")"#),
+            r#"subs expects a number as argument 2, got "1""#
+        );
+        assert_eq!(
+            error("(swap! 1 list)"),
+            "swap! expects an atom as argument 1, got 1"
+        );
+        assert_eq!(
+            error("(even? 1 2)"),
+            "Wrong number of args (2) passed to even? expecting 1"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use crate::{environment::Environment, types::MalType};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting allocations so the profiler can report
+/// them. Binaries that want allocation counts install it:
+///
+/// ```
+/// #[global_allocator]
+/// static ALLOCATOR: ruste::profile::CountingAllocator = ruste::profile::CountingAllocator;
+/// ```
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// What was measured for one function.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub calls: u64,
+    /// Time spent in the function and the functions it called. Recursive
+    /// calls are only counted once, for the outermost one.
+    pub inclusive: Duration,
+    /// Time spent in the function itself.
+    pub exclusive: Duration,
+    /// Allocations made by the function itself, if `CountingAllocator` is
+    /// installed.
+    pub allocations: u64,
+}
+
+/// The result of profiling, per function name.
+#[derive(Debug, Default)]
+pub struct Profile {
+    pub stats: HashMap<String, Stats>,
+    /// Exclusive time by stack of function names, outermost first.
+    pub stacks: HashMap<Vec<String>, Duration>,
+}
+
+impl Profile {
+    /// A table of the functions, those that took the most time themselves
+    /// first.
+    pub fn report(&self) -> String {
+        let mut stats: Vec<_> = self.stats.iter().collect();
+        stats.sort_by(|(a_name, a), (b_name, b)| {
+            b.exclusive.cmp(&a.exclusive).then(a_name.cmp(b_name))
+        });
+        let width = stats
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("fn".len());
+
+        let mut lines = vec![format!(
+            "{:<width$} {:>10} {:>12} {:>12} {:>12}",
+            "fn", "calls", "total ms", "self ms", "self allocs"
+        )];
+        let ms = |duration: &Duration| format!("{:.3}", duration.as_secs_f64() * 1000.0);
+        for (name, stats) in stats {
+            lines.push(format!(
+                "{:<width$} {:>10} {:>12} {:>12} {:>12}",
+                name,
+                stats.calls,
+                ms(&stats.inclusive),
+                ms(&stats.exclusive),
+                stats.allocations
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// One `outer;inner <microseconds>` line per stack, the folded format
+    /// flamegraph tools read.
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, time)| format!("{} {}", stack.join(";"), time.as_micros()))
+            .collect();
+        lines.sort();
+        lines.join("\n")
+    }
+}
+
+struct Call {
+    name: String,
+    start: Instant,
+    allocations: u64,
+    /// Time and allocations of the calls made from this one.
+    children: Duration,
+    child_allocations: u64,
+}
+
+struct Profiler {
+    calls: Vec<Call>,
+    profile: Profile,
+}
+
+thread_local! {
+    static PROFILER: RefCell<Option<Profiler>> = const { RefCell::new(None) };
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+/// Starts recording every function call on this thread.
+pub fn start() {
+    PROFILER.with(|profiler| {
+        *profiler.borrow_mut() = Some(Profiler {
+            calls: vec![],
+            profile: Profile::default(),
+        })
+    });
+    ENABLED.with(|enabled| enabled.set(true));
+}
+
+/// Stops recording, returning what was recorded since `start`.
+pub fn stop() -> Profile {
+    ENABLED.with(|enabled| enabled.set(false));
+    PROFILER
+        .with(|profiler| profiler.borrow_mut().take())
+        .map(|profiler| profiler.profile)
+        .unwrap_or_default()
+}
+
+/// Records a call for as long as it lives.
+pub struct Profiled(bool);
+
+/// Starts recording a call of `f`.
+pub fn enter(f: &MalType) -> Profiled {
+    if !enabled() {
+        return Profiled(false);
+    }
+    let name = match f {
+        MalType::Fn(f) => f.name.clone().unwrap_or_else(|| "fn*".to_string()),
+        MalType::LibFn(f) => f.expr.symbol.clone(),
+        MalType::BinOp(expr) => expr.symbol.clone(),
+        MalType::Multi(multi) => multi.name(),
+        other => format!("{:b}", other),
+    };
+    PROFILER.with(|profiler| {
+        if let Some(profiler) = profiler.borrow_mut().as_mut() {
+            profiler.calls.push(Call {
+                name,
+                start: Instant::now(),
+                allocations: ALLOCATIONS.load(Ordering::Relaxed),
+                children: Duration::ZERO,
+                child_allocations: 0,
+            });
+        }
+    });
+    Profiled(true)
+}
+
+impl Drop for Profiled {
+    fn drop(&mut self) {
+        if !self.0 {
+            return;
+        }
+        PROFILER.with(|profiler| {
+            let mut profiler = profiler.borrow_mut();
+            let Some(profiler) = profiler.as_mut() else {
+                return;
+            };
+            let Some(call) = profiler.calls.pop() else {
+                return;
+            };
+            let elapsed = call.start.elapsed();
+            let allocations = ALLOCATIONS.load(Ordering::Relaxed) - call.allocations;
+            let exclusive = elapsed.saturating_sub(call.children);
+
+            let recursive = profiler.calls.iter().any(|outer| outer.name == call.name);
+            let stats = profiler.profile.stats.entry(call.name.clone()).or_default();
+            stats.calls += 1;
+            stats.exclusive += exclusive;
+            stats.allocations += allocations - call.child_allocations;
+            if !recursive {
+                stats.inclusive += elapsed;
+            }
+
+            let mut stack: Vec<String> = profiler.calls.iter().map(|c| c.name.clone()).collect();
+            stack.push(call.name);
+            *profiler.profile.stacks.entry(stack).or_default() += exclusive;
+
+            if let Some(caller) = profiler.calls.last_mut() {
+                caller.children += elapsed;
+                caller.child_allocations += allocations;
+            }
+        });
+    }
+}
+
+/// `(profile expr)` evaluates expr, recording the calls it makes, and prints
+/// a report of them. `(profile "file" expr)` also writes the folded stacks
+/// to file.
+pub fn profile(args: &[MalType], env: &mut Environment) -> anyhow::Result<MalType> {
+    let (file, expr) = match args {
+        [expr] => (None, expr),
+        [MalType::String(file), expr] => (Some(file), expr),
+        _ => anyhow::bail!(
+            "profile expects a form, and optionally a file first, received {:?}",
+            args
+        ),
+    };
+    // Profiling the inside of a profile adds to the outer one
+    if enabled() {
+        return crate::eval::eval(expr, env);
+    }
+
+    start();
+    let ret = crate::eval::eval(expr, env);
+    let profile = stop();
+    println!("{}", profile.report());
+    if let Some(file) = file {
+        crate::sandbox::check_io(&format!("Writing {}", file))?;
+        std::fs::write(file, profile.folded() + "\n")?;
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    #[test]
+    fn profile() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        let interpreter = Interpreter::new();
+        interpreter
+            .eval_str("(defn fib [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))")
+            .unwrap();
+
+        start();
+        assert_eq!(
+            interpreter.eval_str("(fib 10)").unwrap(),
+            MalType::Number(55)
+        );
+        let profile = stop();
+        assert!(!enabled());
+
+        let fib = &profile.stats["fib"];
+        assert_eq!(fib.calls, 177);
+        assert_eq!(profile.stats["<"].calls, 177);
+        assert_eq!(profile.stats["+"].calls, 88);
+        // The outermost call covers everything, recursion isn't counted twice
+        assert!(fib.inclusive >= fib.exclusive);
+        let total: Duration = profile.stats.values().map(|stats| stats.exclusive).sum();
+        assert!(fib.inclusive >= total);
+
+        let report = profile.report();
+        assert!(report.starts_with("fn "));
+        assert!(report
+            .lines()
+            .any(|line| line.starts_with("fib ") && line.contains(" 177 ")));
+
+        let folded = profile.folded();
+        assert!(folded.lines().any(|line| line.starts_with("fib;fib;+ ")));
+        assert!(folded.lines().all(|line| line.starts_with("fib")));
+
+        assert_eq!(
+            interpreter.eval_str("(profile (fib 5))").unwrap(),
+            MalType::Number(5)
+        );
+    }
+}
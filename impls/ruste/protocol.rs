@@ -0,0 +1,331 @@
+use crate::{
+    environment::Environment,
+    intern::AMP,
+    types::{MalArity, MalType},
+};
+use anyhow::Result;
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+
+/// Implementations registered for this type are used by any value whose own
+/// type has none.
+pub const DEFAULT_TYPE: &str = "Object";
+
+/// Defines `TYPE_NAMES`, the types protocols can be extended to, and the
+/// match from the variants to them, from the one list. The match has no
+/// catch-all, so a new variant has to be added here.
+macro_rules! builtin_types {
+    ($($name:literal => $($variant:pat_param)|+,)*) => {
+        const TYPE_NAMES: &[&str] = &[$($name),*];
+
+        fn builtin_type_name(value: &MalType) -> Option<&'static str> {
+            match value {
+                $($($variant)|+ => Some($name),)*
+                MalType::Record(_) => None,
+            }
+        }
+    };
+}
+
+builtin_types! {
+    "List" => MalType::List(..),
+    "HashMap" => MalType::HashMap(..),
+    "Vector" => MalType::Vector(..),
+    "Set" => MalType::Set(..),
+    "Lazy" => MalType::Lazy(_),
+    "String" => MalType::String(_),
+    "Symbol" => MalType::Symbol(..),
+    "Keyword" => MalType::Keyword(_),
+    "Number" => MalType::Number(_),
+    "Float" => MalType::Float(_),
+    "Bool" => MalType::Bool(_),
+    "Nil" => MalType::Nil,
+    "Regex" => MalType::Regex(_),
+    "Tagged" => MalType::Tagged(..),
+    "Fn" => MalType::Bind(_)
+        | MalType::BinOp(_)
+        | MalType::Fn(_)
+        | MalType::LibFn(_)
+        | MalType::Multi(_),
+    "Protocol" => MalType::Protocol(_),
+    "Atom" => MalType::Atom(_),
+}
+
+/// The type a value dispatches on, named after its `MalType` variant or
+/// else its record type, qualified by the namespace it's defined in.
+pub fn type_name(value: &MalType) -> String {
+    match value {
+        MalType::Record(record) => record.qualified_name().to_string(),
+        other => builtin_type_name(other)
+            .expect("only records have no builtin type")
+            .to_string(),
+    }
+}
+
+struct Method {
+    name: String,
+    arities: Vec<MalArity>,
+}
+
+struct Table {
+    name: String,
+    methods: Vec<Method>,
+    // type name -> method name -> implementation
+    impls: HashMap<String, HashMap<String, MalType>>,
+}
+
+/// A named set of methods, each dispatching on the type of its first
+/// argument through the protocol's table of implementations.
+#[derive(Clone)]
+pub struct Protocol(Rc<RefCell<Table>>);
+
+impl Protocol {
+    pub fn name(&self) -> String {
+        self.0.borrow().name.clone()
+    }
+
+    pub fn ptr_eq(&self, other: &Protocol) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Whether `value` has implementations, either for its own type or
+    /// through `Object`.
+    pub fn satisfied_by(&self, value: &MalType) -> bool {
+        let table = self.0.borrow();
+        table.impls.contains_key(&type_name(value)) || table.impls.contains_key(DEFAULT_TYPE)
+    }
+
+    fn dispatch(&self, method: &str, args: &[MalType], env: &Environment) -> Result<MalType> {
+        let implementation = {
+            let table = self.0.borrow();
+            let declared = table.methods.iter().find(|m| m.name == method).unwrap();
+            let this = match args.first() {
+                Some(this)
+                    if declared
+                        .arities
+                        .iter()
+                        .any(|arity| arity.accepts(args.len())) =>
+                {
+                    this
+                }
+                _ => anyhow::bail!(
+                    "Wrong number of args ({}) passed to {} of protocol {}",
+                    args.len(),
+                    method,
+                    table.name
+                ),
+            };
+
+            let ty = type_name(this);
+            [ty.as_str(), DEFAULT_TYPE]
+                .iter()
+                .find_map(|ty| table.impls.get(*ty).and_then(|impls| impls.get(method)))
+                .cloned()
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "No implementation of method {} of protocol {} found for type {}",
+                        method,
+                        table.name,
+                        ty
+                    )
+                })?
+        };
+
+        implementation.eval(args, env)
+    }
+
+    /// The function bound to a method's name, which looks up the
+    /// implementation on every call so later extensions are picked up.
+    fn method_fn(&self, method: &str) -> MalType {
+        let protocol = self.clone();
+        let name = method.to_string();
+        MalType::native(method, move |args, env| {
+            protocol.dispatch(&name, args, &env)
+        })
+    }
+
+    fn extend(&self, ty: &str, forms: &[&MalType], env: &Environment) -> Result<()> {
+        let record = env
+            .namespaces()
+            .borrow()
+            .record_type(env.ns_name(), ty)
+            .map(|record| record.qualified_name().to_string());
+        let ty = match record {
+            Some(record) => record,
+            None if ty == DEFAULT_TYPE || TYPE_NAMES.contains(&ty) => ty.to_string(),
+            None => anyhow::bail!(
+                "Cannot extend protocol {} to unknown type {}",
+                self.name(),
+                ty
+            ),
+        };
+
+        let mut impls = HashMap::new();
+        for form in forms {
+            let (method, fn_tail) = match form {
+                MalType::List(inner, _) => match inner.split_first() {
+                    Some((MalType::Symbol(method, _), fn_tail)) => (method, fn_tail),
+                    _ => anyhow::bail!("Expected (method [params] body...), got {:b}", form),
+                },
+                other => anyhow::bail!("Expected (method [params] body...), got {:b}", other),
+            };
+            if !self.0.borrow().methods.iter().any(|m| *method == *m.name) {
+                anyhow::bail!("{} is not a method of protocol {}", method, self.name())
+            }
+
+            let mut fn_form = vec![MalType::Symbol("fn*".into(), None)];
+            fn_form.extend(fn_tail.iter().cloned());
+            let f = crate::eval::eval(&MalType::List(fn_form, None), &mut env.clone())?;
+            impls.insert(method.to_string(), f);
+        }
+
+        self.0
+            .borrow_mut()
+            .impls
+            .entry(ty)
+            .or_default()
+            .extend(impls);
+        Ok(())
+    }
+}
+
+impl Debug for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Protocol({})", self.0.borrow().name)
+    }
+}
+
+/// `(defprotocol Name (method [this ...] ...) ...)` binds the protocol and a
+/// dispatching function for each of its methods. Docstrings are skipped.
+pub fn defprotocol(args: &[MalType], env: &mut Environment) -> Result<MalType> {
+    let (name, specs) = match args.split_first() {
+        Some((MalType::Symbol(name, _), specs)) => (name, specs),
+        _ => anyhow::bail!("defprotocol expects a name, received {:?}", args),
+    };
+
+    let mut methods = vec![];
+    for spec in specs
+        .iter()
+        .filter(|spec| !matches!(spec, MalType::String(_)))
+    {
+        let (method, params) = match spec {
+            MalType::List(inner, _) => match inner.split_first() {
+                Some((MalType::Symbol(method, _), params)) => (method, params),
+                _ => anyhow::bail!("defprotocol expects (method [this ...]), got {:b}", spec),
+            },
+            other => anyhow::bail!("defprotocol expects (method [this ...]), got {:b}", other),
+        };
+
+        let mut arities = vec![];
+        for params in params
+            .iter()
+            .filter(|params| !matches!(params, MalType::String(_)))
+        {
+            match params {
+                MalType::Vector(inner, _) if !inner.is_empty() && !inner[0].is_symbol(*AMP) => {
+                    arities.push(MalArity {
+                        params: params.clone(),
+                        body: vec![],
+                    })
+                }
+                other => anyhow::bail!(
+                    "Method {} of protocol {} expects [this ...] params, got {:b}",
+                    method,
+                    name,
+                    other
+                ),
+            }
+        }
+        if arities.is_empty() {
+            anyhow::bail!(
+                "Method {} of protocol {} has no [this ...] params",
+                method,
+                name
+            )
+        }
+        methods.push(Method {
+            name: method.to_string(),
+            arities,
+        });
+    }
+
+    let protocol = Protocol(Rc::new(RefCell::new(Table {
+        name: name.to_string(),
+        methods,
+        impls: HashMap::new(),
+    })));
+
+    let names: Vec<String> = protocol
+        .0
+        .borrow()
+        .methods
+        .iter()
+        .map(|m| m.name.clone())
+        .collect();
+    for method in names {
+        env.set(
+            MalType::Symbol(method.as_str().into(), None),
+            protocol.method_fn(&method),
+        );
+    }
+    let protocol = MalType::Protocol(protocol);
+    env.set(MalType::Symbol(*name, None), protocol.clone());
+    Ok(protocol)
+}
+
+/// `(extend-type Type Protocol (method [this ...] body...) ... Protocol ...)`
+pub fn extend_type(args: &[MalType], env: &Environment) -> Result<MalType> {
+    let ty = match args.first() {
+        Some(ty) => type_symbol(ty)?,
+        None => anyhow::bail!("extend-type expects a type name"),
+    };
+
+    for (protocol, forms) in group(&args[1..], |form| !matches!(form, MalType::List(..))) {
+        resolve_protocol(protocol, env)?.extend(&ty, &forms, env)?;
+    }
+    Ok(MalType::Nil)
+}
+
+/// `(extend-protocol Protocol Type (method [this ...] body...) ... Type ...)`
+pub fn extend_protocol(args: &[MalType], env: &Environment) -> Result<MalType> {
+    let protocol = match args.first() {
+        Some(protocol) => resolve_protocol(protocol, env)?,
+        None => anyhow::bail!("extend-protocol expects a protocol"),
+    };
+
+    for (ty, forms) in group(&args[1..], |form| !matches!(form, MalType::List(..))) {
+        protocol.extend(&type_symbol(ty)?, &forms, env)?;
+    }
+    Ok(MalType::Nil)
+}
+
+/// Splits `forms` into a head, as picked by `is_head`, and the forms after it.
+fn group(forms: &[MalType], is_head: impl Fn(&MalType) -> bool) -> Vec<(&MalType, Vec<&MalType>)> {
+    let mut groups: Vec<(&MalType, Vec<&MalType>)> = vec![];
+    for form in forms {
+        match groups.last_mut() {
+            Some((_, body)) if !is_head(form) => body.push(form),
+            _ => groups.push((form, vec![])),
+        }
+    }
+    groups
+}
+
+/// Type names are written as symbols, except that `nil` reads as the value.
+fn type_symbol(ty: &MalType) -> Result<String> {
+    match ty {
+        MalType::Symbol(ty, _) => Ok(ty.to_string()),
+        MalType::Nil => Ok("Nil".to_string()),
+        other => anyhow::bail!("Expected a type name, got {:b}", other),
+    }
+}
+
+fn resolve_protocol(symbol: &MalType, env: &Environment) -> Result<Protocol> {
+    let value = match symbol {
+        MalType::Symbol(..) => crate::eval::eval(symbol, &mut env.clone())?,
+        other => other.clone(),
+    };
+    match value {
+        MalType::Protocol(protocol) => Ok(protocol),
+        _ => anyhow::bail!("{:b} is not a protocol", symbol),
+    }
+}
@@ -1,36 +1,41 @@
-use crate::types::{MalCollection, MalType};
+use crate::types::{hash_map_insert, MalCollection, MalType};
 use anyhow::{anyhow, Ok, Result};
 use regex::Regex;
-
-#[derive(Debug)]
-pub enum MalToken {
-    Whitespace(String),
-    Special(String),
-    Quote(String),
-    CharSeq(String),
-    Symbols(String),
-}
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Lexer {
     position: usize,
     tokens: Vec<String>,
+    /// The line and column each token starts at, counting from 1.
+    lines: Vec<(usize, usize)>,
 }
 
 impl Lexer {
     pub fn tokenize(buffer: &str) -> Self {
         let regex = regex::Regex::new(
-            r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"###,
+            r###"[\s,]*(~@|#\{|#_|[\[\]{}()'`~^@]|#"(?:\\.|[^\\"])*"?|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"###,
         )
         .unwrap();
 
         let mut tokens = vec![];
+        let mut lines = vec![];
+        let (mut line, mut line_start, mut scanned) = (1, 0, 0);
 
         for capture in regex.captures_iter(buffer) {
             if let Some(token) = capture.get(1) {
                 match token.as_str() {
-                    tok if tok.len() == 0 || tok.starts_with(';') => (),
-                    tok => tokens.push(tok.to_string()),
+                    tok if tok.is_empty() || tok.starts_with(';') => (),
+                    tok => {
+                        for (i, _) in buffer[scanned..token.start()].match_indices('\n') {
+                            line += 1;
+                            line_start = scanned + i + 1;
+                        }
+                        scanned = token.start();
+                        let column = buffer[line_start..token.start()].chars().count() + 1;
+                        tokens.push(tok.to_string());
+                        lines.push((line, column));
+                    }
                 }
             }
         }
@@ -38,10 +43,11 @@ impl Lexer {
         Self {
             position: 0,
             tokens,
+            lines,
         }
     }
 
-    pub fn next(&mut self) -> Option<&String> {
+    pub fn next_token(&mut self) -> Option<&String> {
         self.position += 1;
         self.tokens.get(self.position - 1)
     }
@@ -49,16 +55,44 @@ impl Lexer {
     pub fn peek(&self) -> Option<&String> {
         self.tokens.get(self.position)
     }
+
+    /// The line and column the next token starts at.
+    pub fn peek_line(&self) -> Option<(usize, usize)> {
+        self.lines.get(self.position).copied()
+    }
 }
 
 #[derive(Debug)]
 pub struct Parser {
     lexer: Lexer,
+    /// Whether lists get their position in the source as metadata, and the
+    /// file it is read from, if any.
+    positions: Option<Option<String>>,
+    /// Whether only EDN is accepted, without code's reader macros.
+    edn: bool,
 }
 
 impl Parser {
     pub fn new(lexer: Lexer) -> Self {
-        Self { lexer }
+        Self {
+            lexer,
+            positions: None,
+            edn: false,
+        }
+    }
+
+    /// Reads code rather than data: every list gets `:line` and `:column`
+    /// metadata, and `:file` when given, so errors can point back at it.
+    pub fn with_positions(mut self, file: Option<&str>) -> Self {
+        self.positions = Some(file.map(str::to_string));
+        self
+    }
+
+    /// Reads data as EDN: reader macros such as `@`, `^`, `'` and regex
+    /// literals are errors rather than code.
+    pub fn edn(mut self) -> Self {
+        self.edn = true;
+        self
     }
 
     pub fn parse(&mut self) -> Result<Vec<MalType>> {
@@ -72,6 +106,7 @@ impl Parser {
     }
 
     fn read_next(&mut self) -> Result<Option<MalType>> {
+        self.skip_discarded()?;
         let val = if let Some(val) = self.lexer.peek() {
             val
         } else {
@@ -81,19 +116,93 @@ impl Parser {
         match val.as_str() {
             "" => Ok(None),
 
-            "(" | "[" | "{" => self.read_collection(),
+            macro_ if self.edn && is_reader_macro(macro_) => {
+                anyhow::bail!("read-edn: {} is not valid EDN", macro_)
+            }
+
+            "(" | "[" | "{" | "#{" => self.read_collection(),
             ")" | "]" | "}" => anyhow::bail!("Received collection end while trying to read next"),
+            "@" => self.read_wrapped("deref"),
+            "^" => self.read_meta(),
+            tag if tag.starts_with('#') && tag[1..].starts_with(|x: char| x.is_alphabetic()) => {
+                self.read_tagged()
+            }
             _ => self.read_symbol(),
         }
     }
 
+    /// Drops every form prefixed by `#_`, including the nested `#_ #_ a b` case.
+    fn skip_discarded(&mut self) -> Result<()> {
+        while self.lexer.peek().is_some_and(|token| token == "#_") {
+            self.lexer.next_token();
+            if self.read_next()?.is_none() {
+                anyhow::bail!("EOF: Expected a form to discard after #_")
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a reader macro such as `@a` into `(deref a)`.
+    fn read_wrapped(&mut self, symbol: &str) -> Result<Option<MalType>> {
+        let token = self.lexer.next_token().unwrap().clone();
+        match self.read_next()? {
+            Some(form) => Ok(Some(MalType::List(
+                vec![MalType::Symbol(symbol.into(), None), form],
+                None,
+            ))),
+            None => anyhow::bail!("EOF: Expected a form after {}", token),
+        }
+    }
+
+    /// Reads `^{:doc "..."} form`, `^:private form` or `^Tag form`, merging
+    /// the metadata over whatever the form already carries.
+    fn read_meta(&mut self) -> Result<Option<MalType>> {
+        self.lexer.next_token();
+        let meta = match self.read_next()? {
+            Some(MalType::HashMap(meta, _)) => meta,
+            Some(key @ MalType::Keyword(_)) => vec![key, MalType::Bool(true)],
+            Some(tag @ (MalType::Symbol(..) | MalType::String(_))) => {
+                vec![MalType::Keyword("tag".into()), tag]
+            }
+            Some(other) => {
+                anyhow::bail!("Metadata must be a map, keyword or symbol, got {:b}", other)
+            }
+            None => anyhow::bail!("EOF: Expected metadata after ^"),
+        };
+        let form = match self.read_next()? {
+            Some(form) => form,
+            None => anyhow::bail!("EOF: Expected a form after metadata"),
+        };
+
+        let mut merged = match form.meta() {
+            Some(MalType::HashMap(existing, _)) => existing.clone(),
+            _ => vec![],
+        };
+        for pair in meta.chunks(2) {
+            hash_map_insert(&mut merged, pair[0].clone(), pair[1].clone());
+        }
+        Ok(Some(form.with_meta(Some(Rc::new(MalType::HashMap(
+            merged, None,
+        ))))?))
+    }
+
+    fn read_tagged(&mut self) -> Result<Option<MalType>> {
+        let tag = self.lexer.next_token().unwrap()[1..].to_string();
+        match self.read_next()? {
+            Some(value) => Ok(Some(MalType::Tagged(tag, Box::new(value)))),
+            None => anyhow::bail!("EOF: Expected a form after tag #{}", tag),
+        }
+    }
+
     fn read_collection(&mut self) -> Result<Option<MalType>> {
+        let position = self.lexer.peek_line();
         // eat start
-        let collection_type = MalCollection::get(self.lexer.next().unwrap().as_str());
+        let collection_type = MalCollection::get(self.lexer.next_token().unwrap().as_str());
         let mut list = vec![];
 
         // Take while next token is not END OF LIST
         loop {
+            self.skip_discarded()?;
             let token = if let Some(next) = self.lexer.peek() {
                 next
             } else {
@@ -101,9 +210,48 @@ impl Parser {
             };
 
             if token.as_str() == collection_type.end() {
+                if let MalCollection::HashMap = collection_type {
+                    if !list.len().is_multiple_of(2) {
+                        anyhow::bail!("Map literal must contain an even number of forms")
+                    }
+                }
                 // Eat end of list
-                self.lexer.next();
-                return Ok(Some(collection_type.into(list)));
+                self.lexer.next_token();
+                return Ok(Some(match (&collection_type, &self.positions, position) {
+                    (MalCollection::List, Some(file), Some((line, column))) => {
+                        let mut meta = vec![
+                            MalType::Keyword("line".into()),
+                            MalType::Number(line as i64),
+                            MalType::Keyword("column".into()),
+                            MalType::Number(column as i64),
+                        ];
+                        if let Some(file) = file {
+                            meta.push(MalType::Keyword("file".into()));
+                            meta.push(MalType::String(file.clone()));
+                        }
+                        MalType::List(list, Some(Rc::new(MalType::HashMap(meta, None))))
+                    }
+                    _ => collection_type.into(list),
+                }));
+            } else if let MalCollection::Set = collection_type {
+                match self.read_next()? {
+                    Some(item) if list.contains(&item) => {
+                        anyhow::bail!("Duplicate item in set literal: {:b}", item)
+                    }
+                    Some(item) => list.push(item),
+                    None => anyhow::bail!("Got None in read_next while reading set! {:?}", self),
+                }
+            } else if let MalCollection::HashMap = collection_type {
+                match self.read_next()? {
+                    Some(key)
+                        if list.len().is_multiple_of(2)
+                            && list.iter().step_by(2).any(|k| k == &key) =>
+                    {
+                        anyhow::bail!("Duplicate key in map literal: {:b}", key)
+                    }
+                    Some(item) => list.push(item),
+                    None => anyhow::bail!("Got None in read_next while reading map! {:?}", self),
+                }
             } else {
                 if let Some(token) = self.read_next()? {
                     list.push(token)
@@ -115,7 +263,7 @@ impl Parser {
     }
 
     fn read_symbol(&mut self) -> Result<Option<MalType>> {
-        let symbol = match self.lexer.next() {
+        let symbol = match self.lexer.next_token() {
             Some(symbol) => symbol,
             wat => anyhow::bail!("Unexpected token in read_symbol. {:?}", wat),
         };
@@ -124,6 +272,9 @@ impl Parser {
             "true" => Ok(Some(MalType::Bool(true))),
             "false" => Ok(Some(MalType::Bool(false))),
             "nil" => Ok(Some(MalType::Nil)),
+            "##NaN" => Ok(Some(MalType::Float(f64::NAN))),
+            "##Inf" => Ok(Some(MalType::Float(f64::INFINITY))),
+            "##-Inf" => Ok(Some(MalType::Float(f64::NEG_INFINITY))),
             number if number.chars().all(|x| x.is_ascii_digit()) => {
                 let number: i64 = number.parse()?;
                 Ok(Some(MalType::Number(number)))
@@ -136,17 +287,74 @@ impl Parser {
                 let number: i64 = number.parse()?;
                 Ok(Some(MalType::Number(number)))
             }
-            str if str.starts_with('"') => {
-                if str.len() == 1 {
-                    anyhow::bail!("EOF: Received only opening quote")
-                }
-                if !str.ends_with('"') {
-                    anyhow::bail!("EOF: String ended unexpectantly")
+            number
+                if number
+                    .trim_start_matches('-')
+                    .starts_with(|x: char| x.is_ascii_digit())
+                    && number.parse::<f64>().is_ok() =>
+            {
+                Ok(Some(MalType::Float(number.parse()?)))
+            }
+            keyword if keyword.starts_with(':') && keyword.len() > 1 => {
+                Ok(Some(MalType::Keyword(keyword[1..].into())))
+            }
+            str if str.starts_with('"') => Ok(Some(MalType::String(read_string(str)?))),
+            re if re.starts_with("#\"") => {
+                if re.len() == 2 || !is_closed(&re[1..]) {
+                    anyhow::bail!("EOF: Regex ended unexpectantly")
                 }
-                Ok(Some(MalType::String(str.to_string())))
+                let regex = Regex::new(&re[2..re.len() - 1])
+                    .map_err(|err| anyhow!("Invalid regex literal {}: {}", re, err))?;
+                Ok(Some(MalType::Regex(regex.into())))
             }
-            other => Ok(Some(MalType::Symbol(other.to_string()))),
+            other => Ok(Some(MalType::Symbol(other.into(), None))),
             // _ => anyhow::bail!("Received unexpected symbol. {:?}", symbol),
         }
     }
 }
+
+/// Tokens that only mean something in code, not in EDN.
+fn is_reader_macro(token: &str) -> bool {
+    matches!(token, "@" | "^" | "'" | "`" | "~" | "~@") || token.starts_with("#\"")
+}
+
+/// Strips the surrounding quotes of a string token and resolves its escapes.
+fn read_string(token: &str) -> Result<String> {
+    if token.len() == 1 {
+        anyhow::bail!("EOF: Received only opening quote")
+    }
+    if !is_closed(token) {
+        anyhow::bail!("EOF: String ended unexpectantly")
+    }
+
+    let mut buffer = String::new();
+    let mut chars = token[1..token.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => buffer.push('\n'),
+                Some(other) => buffer.push(other),
+                None => anyhow::bail!("EOF: String ended unexpectantly"),
+            },
+            c => buffer.push(c),
+        }
+    }
+    Ok(buffer)
+}
+
+/// A quoted token is closed if its final quote is not consumed by an escape.
+fn is_closed(token: &str) -> bool {
+    let mut chars = token.chars().skip(1);
+    let mut closed = false;
+    while let Some(c) = chars.next() {
+        closed = false;
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => closed = true,
+            _ => (),
+        }
+    }
+    closed
+}
@@ -0,0 +1,192 @@
+use crate::{
+    environment::Environment,
+    types::{hash_map_get, hash_map_insert, MalType},
+};
+use anyhow::Result;
+use std::{fmt::Debug, rc::Rc};
+
+/// A type made by `defrecord`: its name and the fields its constructor takes.
+pub struct RecordType {
+    name: String,
+    /// The name qualified by the namespace it's defined in, as `my.ns.Point`,
+    /// which protocols dispatch on.
+    qualified: String,
+    fields: Vec<MalType>,
+}
+
+impl RecordType {
+    pub fn qualified_name(&self) -> &str {
+        &self.qualified
+    }
+}
+
+/// A map of keyword fields that knows which record type it is. Fields beyond
+/// the declared ones can be added with `assoc`.
+#[derive(Clone)]
+pub struct Record {
+    ty: Rc<RecordType>,
+    entries: Vec<MalType>,
+}
+
+impl Record {
+    pub fn name(&self) -> &str {
+        &self.ty.name
+    }
+
+    pub fn qualified_name(&self) -> &str {
+        &self.ty.qualified
+    }
+
+    /// The fields as the flat key/value list `MalType::HashMap` uses.
+    pub fn entries(&self) -> &[MalType] {
+        &self.entries
+    }
+
+    pub fn get(&self, key: &MalType) -> Option<&MalType> {
+        hash_map_get(&self.entries, key)
+    }
+
+    pub fn assoc(&self, key: MalType, value: MalType) -> Record {
+        let mut record = self.clone();
+        hash_map_insert(&mut record.entries, key, value);
+        record
+    }
+
+    /// Without one of its declared fields a record is just a map.
+    pub fn dissoc(&self, key: &MalType) -> MalType {
+        let entries = self
+            .entries
+            .chunks(2)
+            .filter(|pair| &pair[0] != key)
+            .flatten()
+            .cloned()
+            .collect();
+        match self.ty.fields.contains(key) {
+            true => MalType::HashMap(entries, None),
+            false => MalType::Record(Record {
+                ty: Rc::clone(&self.ty),
+                entries,
+            }),
+        }
+    }
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.ty, &other.ty)
+            && self.entries.len() == other.entries.len()
+            && self
+                .entries
+                .chunks(2)
+                .all(|pair| other.get(&pair[0]) == Some(&pair[1]))
+    }
+}
+
+impl Debug for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Record")
+            .field("name", &self.ty.name)
+            .field("entries", &self.entries)
+            .finish()
+    }
+}
+
+/// `(defrecord Name [fields...])` defines the positional constructor
+/// `->Name`, `map->Name` and the predicate `Name?`.
+pub fn defrecord(args: &[MalType], env: &mut Environment) -> Result<MalType> {
+    let (name, fields) = match args {
+        [MalType::Symbol(name, _), MalType::Vector(fields, _)] => (name, fields),
+        _ => anyhow::bail!(
+            "defrecord expects a name and a vector of fields, received {:?}",
+            args
+        ),
+    };
+
+    let mut keys = vec![];
+    for field in fields {
+        match field {
+            MalType::Symbol(field, _) if !keys.contains(&MalType::Keyword(*field)) => {
+                keys.push(MalType::Keyword(*field))
+            }
+            other => anyhow::bail!("defrecord {} received unexpected field {:b}", name, other),
+        }
+    }
+
+    let ty = Rc::new(RecordType {
+        name: name.to_string(),
+        qualified: format!("{}.{}", env.ns_name(), name),
+        fields: keys,
+    });
+    env.namespaces()
+        .borrow_mut()
+        .add_record(env.ns_name(), *name, Rc::clone(&ty));
+
+    let constructor = format!("->{}", name);
+    let positional = {
+        let ty = Rc::clone(&ty);
+        let constructor = constructor.clone();
+        move |args: &[MalType], _| {
+            if args.len() != ty.fields.len() {
+                anyhow::bail!(
+                    "Wrong number of args ({}) passed to {} expecting {}",
+                    args.len(),
+                    constructor,
+                    ty.fields.len()
+                )
+            }
+            let entries = ty.fields.iter().cloned().zip(args.iter().cloned());
+            Ok(MalType::Record(Record {
+                ty: Rc::clone(&ty),
+                entries: entries.flat_map(|(key, value)| [key, value]).collect(),
+            }))
+        }
+    };
+    let from_map = {
+        let ty = Rc::clone(&ty);
+        move |args: &[MalType], _| {
+            let map = match args {
+                [MalType::HashMap(map, _)] => map.as_slice(),
+                [MalType::Record(record)] => record.entries(),
+                _ => anyhow::bail!("map->{} received unexpected value {:?}", ty.name, args),
+            };
+            // Declared fields come first and default to nil
+            let mut entries = vec![];
+            for field in &ty.fields {
+                entries.push(field.clone());
+                entries.push(hash_map_get(map, field).cloned().unwrap_or(MalType::Nil));
+            }
+            for pair in map.chunks(2) {
+                hash_map_insert(&mut entries, pair[0].clone(), pair[1].clone());
+            }
+            Ok(MalType::Record(Record {
+                ty: Rc::clone(&ty),
+                entries,
+            }))
+        }
+    };
+    let predicate = {
+        let ty = Rc::clone(&ty);
+        move |args: &[MalType], _| match args {
+            [MalType::Record(record)] => Ok(MalType::Bool(Rc::ptr_eq(&record.ty, &ty))),
+            [_] => Ok(MalType::Bool(false)),
+            _ => anyhow::bail!("{}? received unexpected value {:?}", ty.name, args),
+        }
+    };
+
+    env.set(
+        MalType::Symbol(constructor.as_str().into(), None),
+        MalType::native(&constructor, positional),
+    );
+    let map_constructor = format!("map->{}", name);
+    env.set(
+        MalType::Symbol(map_constructor.as_str().into(), None),
+        MalType::native(&map_constructor, from_map),
+    );
+    let predicate_name = format!("{}?", name);
+    env.set(
+        MalType::Symbol(predicate_name.as_str().into(), None),
+        MalType::native(&predicate_name, predicate),
+    );
+
+    Ok(MalType::Symbol(*name, None))
+}
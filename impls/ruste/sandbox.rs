@@ -0,0 +1,354 @@
+use crate::types::MalType;
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+/// Core functions that print or touch the file system, left out of a
+/// sandbox without I/O.
+pub const IO_FUNCTIONS: &[&str] = &[
+    "println",
+    "prn",
+    "doc",
+    "source",
+    "trace",
+    "untrace",
+    "break",
+    "unbreak",
+    "profile",
+    "load-file",
+    "require",
+    "load-path",
+    "set-load-path!",
+];
+
+/// How deeply evaluation nests by default. Each level takes about 1.5 KiB
+/// of native stack in release builds, so this fits in the 2 MiB a spawned
+/// thread gets, and runaway recursion fails with an error instead of
+/// aborting. Debug builds take several times as much stack per level.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// Limits for running untrusted code. Each one is unlimited when `None`,
+/// which is the default for all but `max_depth`.
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    /// Forms evaluated plus items walked in sequences.
+    pub max_steps: Option<u64>,
+    /// How deeply evaluation may nest, which bounds recursion. Without a
+    /// limit, deep recursion overflows the native stack.
+    pub max_depth: Option<usize>,
+    /// Items in any one collection or realized sequence, and bytes in any
+    /// one string.
+    pub max_collection_size: Option<usize>,
+    pub timeout: Option<Duration>,
+    /// Whether `IO_FUNCTIONS` and loading namespaces from files stay available.
+    pub allow_io: bool,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self {
+            max_steps: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_collection_size: None,
+            timeout: None,
+            allow_io: false,
+        }
+    }
+}
+
+/// The limit that stopped evaluation. Reaches the host as an error.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LimitExceeded {
+    Steps(u64),
+    Depth(usize),
+    CollectionSize(usize),
+    Timeout(Duration),
+    Io(String),
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Steps(max) => write!(f, "Evaluation exceeded {} steps", max),
+            LimitExceeded::Depth(max) => write!(f, "Evaluation nested deeper than {}", max),
+            LimitExceeded::CollectionSize(max) => {
+                write!(f, "Collection or string grew beyond {} items", max)
+            }
+            LimitExceeded::Timeout(max) => write!(f, "Evaluation took longer than {:?}", max),
+            LimitExceeded::Io(what) => write!(f, "{} is not allowed without I/O", what),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+struct State {
+    sandbox: Sandbox,
+    steps: u64,
+    depth: usize,
+    deadline: Option<Instant>,
+}
+
+thread_local! {
+    /// The sandbox evaluation currently runs in, if any.
+    static ACTIVE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+fn with_state<T>(f: impl FnOnce(&mut State) -> T) -> Option<T> {
+    ACTIVE.with(|active| active.borrow_mut().as_mut().map(f))
+}
+
+/// Runs `f` inside `sandbox` with a fresh budget, putting back whatever
+/// sandbox was active before.
+pub fn run<T>(sandbox: &Sandbox, f: impl FnOnce() -> T) -> T {
+    let state = State {
+        sandbox: sandbox.clone(),
+        steps: 0,
+        depth: 0,
+        deadline: sandbox.timeout.map(|timeout| Instant::now() + timeout),
+    };
+    let _restore = Restore(ACTIVE.with(|active| active.borrow_mut().replace(state)));
+    f()
+}
+
+/// Puts back the sandbox that was active before `run` when dropped, so a
+/// panic in `f` doesn't leave its limits in place.
+struct Restore(Option<State>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        ACTIVE.with(|active| *active.borrow_mut() = previous);
+    }
+}
+
+/// Counts one step, failing once the steps or the time run out.
+pub fn tick() -> Result<(), LimitExceeded> {
+    with_state(|state| {
+        state.steps += 1;
+        match (state.sandbox.max_steps, state.deadline) {
+            (Some(max), _) if state.steps > max => Err(LimitExceeded::Steps(max)),
+            (_, Some(deadline)) if Instant::now() > deadline => Err(LimitExceeded::Timeout(
+                state.sandbox.timeout.unwrap_or_default(),
+            )),
+            _ => Ok(()),
+        }
+    })
+    .unwrap_or(Ok(()))
+}
+
+/// Leaves a level of nesting when dropped.
+pub struct Depth(bool);
+
+impl Drop for Depth {
+    fn drop(&mut self) {
+        if self.0 {
+            with_state(|state| state.depth -= 1);
+        }
+    }
+}
+
+/// Enters a level of nesting, for as long as the returned guard lives.
+pub fn enter() -> Result<Depth, LimitExceeded> {
+    with_state(|state| match state.sandbox.max_depth {
+        Some(max) if state.depth >= max => Err(LimitExceeded::Depth(max)),
+        _ => {
+            state.depth += 1;
+            Ok(Depth(true))
+        }
+    })
+    .unwrap_or(Ok(Depth(false)))
+}
+
+/// Fails if a collection of `size` items is over the limit.
+pub fn check_size(size: usize) -> Result<(), LimitExceeded> {
+    with_state(|state| match state.sandbox.max_collection_size {
+        Some(max) if size > max => Err(LimitExceeded::CollectionSize(max)),
+        _ => Ok(()),
+    })
+    .unwrap_or(Ok(()))
+}
+
+/// Fails if `value` is a collection or string over the limit, counting the
+/// bytes of a string.
+pub fn check_value(value: &MalType) -> Result<(), LimitExceeded> {
+    match value {
+        MalType::String(s) => check_size(s.len()),
+        MalType::List(items, _) | MalType::Vector(items, _) | MalType::Set(items, _) => {
+            check_size(items.len())
+        }
+        MalType::HashMap(entries, _) => check_size(entries.len() / 2),
+        _ => Ok(()),
+    }
+}
+
+/// Fails if the active sandbox doesn't allow I/O, naming what needed it.
+pub fn check_io(what: &str) -> Result<(), LimitExceeded> {
+    with_state(|state| match state.sandbox.allow_io {
+        true => Ok(()),
+        false => Err(LimitExceeded::Io(what.to_string())),
+    })
+    .unwrap_or(Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, Interpreter};
+
+    #[test]
+    fn limits() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        let limited =
+            |sandbox: Sandbox, input: &str| match Interpreter::sandboxed(sandbox).eval_str(input) {
+                Err(Error::Limit(limit)) => limit,
+                other => panic!("{} was not stopped: {:?}", input, other),
+            };
+
+        assert_eq!(
+            limited(
+                Sandbox {
+                    max_steps: Some(1000),
+                    ..Sandbox::default()
+                },
+                "(count (range))"
+            ),
+            LimitExceeded::Steps(1000)
+        );
+        assert_eq!(
+            limited(
+                Sandbox {
+                    max_depth: Some(50),
+                    ..Sandbox::default()
+                },
+                "(def! down (fn* [n] (if (= n 0) 0 (+ 1 (down (- n 1)))))) (down 1000)"
+            ),
+            LimitExceeded::Depth(50)
+        );
+        assert_eq!(
+            limited(
+                Sandbox {
+                    max_collection_size: Some(100),
+                    ..Sandbox::default()
+                },
+                "(reverse (range 1000))"
+            ),
+            LimitExceeded::CollectionSize(100)
+        );
+        assert_eq!(
+            limited(
+                Sandbox {
+                    max_collection_size: Some(1000),
+                    ..Sandbox::default()
+                },
+                r#"(loop* [s "x"] (recur (str s s)))"#
+            ),
+            LimitExceeded::CollectionSize(1000)
+        );
+        assert_eq!(
+            limited(
+                Sandbox {
+                    timeout: Some(Duration::from_millis(50)),
+                    ..Sandbox::default()
+                },
+                "(loop* [n 0] (recur (+ n 1)))"
+            ),
+            LimitExceeded::Timeout(Duration::from_millis(50))
+        );
+
+        let interpreter = Interpreter::sandboxed(Sandbox {
+            max_steps: Some(1000),
+            ..Sandbox::default()
+        });
+        assert!(interpreter.eval_str("(count (range))").is_err());
+        // The budget is per call, and the interpreter stays usable
+        assert_eq!(
+            interpreter.eval_str("(count (range 10))").unwrap(),
+            MalType::Number(10)
+        );
+        assert!(interpreter.eval_str(r#"(println "hi")"#).is_err());
+        assert!(interpreter.get("load-file").is_none());
+        assert_eq!(
+            interpreter
+                .eval_str("(ns app (:require geometry))")
+                .unwrap_err()
+                .to_string(),
+            "Loading geometry.mal is not allowed without I/O"
+        );
+
+        // Nothing is limited outside of a sandbox
+        assert!(Interpreter::new().get("println").is_some());
+        // Not even after a panic inside one
+        let panicked = std::panic::catch_unwind(|| {
+            run(&Sandbox::default(), || panic!("in the sandbox"));
+        });
+        assert!(panicked.is_err());
+        assert!(with_state(|_| ()).is_none());
+    }
+
+    #[test]
+    fn arithmetic_errors() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        let interpreter = Interpreter::sandboxed(Sandbox::default());
+        let error = |input: &str| interpreter.eval_str(input).unwrap_err().to_string();
+        assert_eq!(
+            error(r#"(+ 1 "a")"#),
+            r#"+ expects a number as argument 2, got "a""#
+        );
+        assert_eq!(
+            error("(- :a 1)"),
+            "- expects a number as argument 1, got :a"
+        );
+        assert_eq!(error("(/ 1 0)"), "Divide by zero");
+        assert_eq!(error("(* 9223372036854775807 2)"), "Integer overflow in *");
+        assert_eq!(
+            error(r#"(< 1 "a")"#),
+            r#"< expects a number as argument 2, got "a""#
+        );
+        assert_eq!(
+            interpreter
+                .eval_str("[(/ 1.0 0) (< 1 2.5) (>= 2 2)]")
+                .unwrap(),
+            interpreter.eval_str("[##Inf true true]").unwrap()
+        );
+    }
+
+    #[test]
+    fn default_depth() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Info)
+            .is_test(true)
+            .try_init();
+        // Tracing the environment at every level this deep takes minutes, and
+        // another test may have turned tracing on already
+        log::set_max_level(log::LevelFilter::Info);
+
+        // Debug builds need far more stack per level than release ones
+        let recursion = std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| {
+                Interpreter::new()
+                    .eval_str("(def! g (fn* [x] (if (> x 0) (g (- x 1)) :done))) (g 10000)")
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())
+            })
+            .unwrap();
+        assert_eq!(
+            recursion.join().unwrap(),
+            Err(format!(
+                "Evaluation nested deeper than {}",
+                DEFAULT_MAX_DEPTH
+            ))
+        );
+    }
+}
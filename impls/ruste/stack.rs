@@ -0,0 +1,202 @@
+use crate::{
+    reader::{Lexer, Parser},
+    types::{hash_map_get, MalType, Recur},
+};
+use std::fmt::Display;
+
+/// A call that was in progress when an error happened.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// The name of the fn called, or the symbol it was called through.
+    pub name: String,
+    /// The form the call was made by, printed: errors have to be `Send`,
+    /// which values aren't.
+    pub form: String,
+    pub file: Option<String>,
+    /// Where `form` starts, when it was read from source.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Frame {
+    fn new(f: &MalType, form: &MalType) -> Self {
+        let name = match (f, form) {
+            (MalType::Fn(f), _) if f.name.is_some() => f.name.clone().unwrap(),
+            (MalType::LibFn(f), _) => f.expr.symbol.clone(),
+            (MalType::BinOp(expr), _) => expr.symbol.clone(),
+            (MalType::Multi(multi), _) => multi.name(),
+            (_, MalType::List(items, _)) => match items.first() {
+                Some(MalType::Symbol(symbol, _)) => symbol.to_string(),
+                Some(MalType::Keyword(keyword)) => format!(":{}", keyword),
+                _ => "fn".to_string(),
+            },
+            _ => "fn".to_string(),
+        };
+        let position = |key: &str| match form.meta() {
+            Some(MalType::HashMap(meta, _)) => {
+                hash_map_get(meta, &MalType::Keyword(key.into())).cloned()
+            }
+            _ => None,
+        };
+        let number = |key: &str| match position(key) {
+            Some(MalType::Number(n)) => Some(n as usize),
+            _ => None,
+        };
+        Self {
+            name,
+            form: format!("{:b}", form),
+            file: match position("file") {
+                Some(MalType::String(file)) => Some(file),
+                _ => None,
+            },
+            line: number("line"),
+            column: number("column"),
+        }
+    }
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "in {}: {}", self.name, self.form)?;
+        match (&self.file, self.line, self.column) {
+            (Some(file), Some(line), Some(column)) => write!(f, " at {}:{}:{}", file, line, column),
+            (None, Some(line), Some(column)) => {
+                write!(f, " at line {}, column {}", line, column)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The same frame as a map, for embedders to hand to mal code. The form is
+/// read back in.
+impl From<Frame> for MalType {
+    fn from(frame: Frame) -> Self {
+        let form = Parser::new(Lexer::tokenize(&frame.form))
+            .parse()
+            .ok()
+            .and_then(|forms| forms.into_iter().next())
+            .unwrap_or(MalType::String(frame.form));
+        let mut map = vec![
+            MalType::Keyword("fn".into()),
+            MalType::String(frame.name),
+            MalType::Keyword("form".into()),
+            form,
+        ];
+        let position = [
+            ("file", frame.file.map(MalType::String)),
+            ("line", frame.line.map(|line| MalType::Number(line as i64))),
+            (
+                "column",
+                frame.column.map(|column| MalType::Number(column as i64)),
+            ),
+        ];
+        for (key, value) in position {
+            if let Some(value) = value {
+                map.push(MalType::Keyword(key.into()));
+                map.push(value);
+            }
+        }
+        MalType::HashMap(map, None)
+    }
+}
+
+/// An error together with the calls it unwound through, innermost first.
+/// Wraps the error it was raised with at the first call it leaves.
+#[derive(Debug)]
+pub struct Traced {
+    pub error: anyhow::Error,
+    pub stack: Vec<Frame>,
+}
+
+impl Display for Traced {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#}", self.error)
+    }
+}
+
+impl std::error::Error for Traced {}
+
+/// Records that `err` unwound through calling `f` from `form`.
+pub fn unwind(mut err: anyhow::Error, f: &MalType, form: &MalType) -> anyhow::Error {
+    // A recur is on its way to the fn it jumps back into, not failing
+    if err.is::<Recur>() {
+        return err;
+    }
+    let frame = Frame::new(f, form);
+    if let Some(traced) = err.downcast_mut::<Traced>() {
+        traced.stack.push(frame);
+        return err;
+    }
+    anyhow::Error::new(Traced {
+        error: err,
+        stack: vec![frame],
+    })
+}
+
+/// The calls `err` unwound through, innermost first.
+pub fn stack(err: &anyhow::Error) -> &[Frame] {
+    match err.downcast_ref::<Traced>() {
+        Some(traced) => &traced.stack,
+        None => &[],
+    }
+}
+
+/// The error that was raised, without the stack it was wrapped in.
+pub fn cause(err: &anyhow::Error) -> &anyhow::Error {
+    match err.downcast_ref::<Traced>() {
+        Some(traced) => &traced.error,
+        None => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Interpreter, MalType};
+
+    #[test]
+    fn stack_trace() {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Trace)
+            .is_test(true)
+            .try_init();
+
+        let interpreter = Interpreter::new();
+        interpreter
+            .eval_str(
+                "(defn inner [x]
+                   (+ x (nope)))
+                 (defn outer [x] (inner x))",
+            )
+            .unwrap();
+        // list is never called, as evaluating its argument fails
+        let err = interpreter.eval_str("(list (outer 1))").unwrap_err();
+        assert_eq!(err.to_string(), "nope not found");
+
+        let frames: Vec<String> = err.stack().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            frames,
+            vec![
+                "in inner: (inner x) at line 3, column 34",
+                "in outer: (outer 1) at line 1, column 7",
+            ]
+        );
+
+        let data = MalType::from(err.stack().to_vec());
+        interpreter.set("frames", data);
+        assert_eq!(
+            interpreter
+                .eval_str("(map (fn* [frame] [(:fn frame) (:line frame)]) frames)")
+                .unwrap(),
+            interpreter
+                .eval_str(r#"(list ["inner" 3] ["outer" 1])"#)
+                .unwrap()
+        );
+        assert_eq!(
+            interpreter
+                .eval_str("(first (:form (first frames)))")
+                .unwrap(),
+            MalType::Symbol("inner".into(), None)
+        );
+    }
+}
@@ -1,10 +1,4 @@
-mod console;
-mod core;
-mod environment;
-mod eval;
-mod expr;
-mod reader;
-mod types;
+use ruste::console;
 
 fn main() {
     while let Some(input) = console::Console::read_user_input() {
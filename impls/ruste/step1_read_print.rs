@@ -1,12 +1,8 @@
 use anyhow::Result;
-use reader::{Lexer, Parser};
-mod console;
-mod core;
-mod environment;
-mod eval;
-mod expr;
-mod reader;
-mod types;
+use ruste::{
+    console,
+    reader::{Lexer, Parser},
+};
 
 fn main() -> Result<()> {
     while let Some(input) = console::Console::read_user_input() {
@@ -1,14 +1,11 @@
 use anyhow::Result;
-use environment::Environment;
-use reader::{Lexer, Parser};
-use types::MalType;
-mod console;
-mod core;
-mod environment;
-mod eval;
-mod expr;
-mod reader;
-mod types;
+use ruste::{
+    console,
+    environment::Environment,
+    lazy,
+    reader::{Lexer, Parser},
+    types::MalType,
+};
 
 fn main() -> Result<()> {
     while let Some(input) = console::Console::read_user_input() {
@@ -37,7 +34,9 @@ fn main() -> Result<()> {
 fn rep(tokens: Vec<MalType>, env: &mut Environment) -> Result<()> {
     let token = tokens.first().unwrap();
 
-    match eval(token, env) {
+    let ret =
+        eval(token, env).and_then(|exp| lazy::realize_up_to(&exp, lazy::PRINT_LENGTH).map(|_| exp));
+    match ret {
         Ok(exp) => {
             println!("{}", exp);
         }
@@ -51,12 +50,12 @@ fn rep(tokens: Vec<MalType>, env: &mut Environment) -> Result<()> {
 
 fn eval(ast: &MalType, env: &mut Environment) -> Result<MalType> {
     match ast {
-        MalType::List(inner) => {
-            if inner.len() == 0 {
+        MalType::List(inner, _) => {
+            if inner.is_empty() {
                 return Ok(ast.clone());
             }
             match eval_ast(ast, env)? {
-                MalType::List(inner) => {
+                MalType::List(inner, _) => {
                     let func = inner[0].clone();
                     func.eval(&inner[1..], env)
                 }
@@ -69,29 +68,29 @@ fn eval(ast: &MalType, env: &mut Environment) -> Result<MalType> {
 
 fn eval_ast(ast: &MalType, env: &mut Environment) -> Result<MalType> {
     match ast {
-        MalType::List(inner) => {
+        MalType::List(inner, _) => {
             let mut list = vec![];
             for item in inner {
                 list.push(eval(item, env)?);
             }
-            Ok(MalType::List(list))
+            Ok(MalType::List(list, None))
         }
-        MalType::HashMap(map) => {
+        MalType::HashMap(map, _) => {
             let mut list = vec![];
-            for item in map.windows(2) {
+            for item in map.chunks(2) {
                 list.push(item[0].clone());
                 list.push(eval(&item[1], env)?);
             }
-            Ok(MalType::HashMap(list))
+            Ok(MalType::HashMap(list, None))
         }
-        MalType::Vector(inner) => {
+        MalType::Vector(inner, _) => {
             let mut list = vec![];
             for item in inner {
                 list.push(eval(item, env)?);
             }
-            Ok(MalType::Vector(list))
+            Ok(MalType::Vector(list, None))
         }
-        MalType::Symbol(sym) => env
+        MalType::Symbol(sym, _) => env
             .get(sym)
             .ok_or(anyhow::anyhow!("symbol not found: {}", sym)),
         _ => Ok(ast.clone()),
@@ -1,49 +1,31 @@
-use anyhow::Result;
-use environment::Environment;
-use eval::eval;
-use reader::{Lexer, Parser};
-use types::MalType;
-mod console;
-mod core;
-mod environment;
-mod eval;
-mod expr;
-mod reader;
-mod types;
+use ruste::{console, lazy, Interpreter};
 
-fn main() -> Result<()> {
-    let mut env = Environment::new();
+fn main() {
+    let interpreter = Interpreter::new();
+    // Ctrl-C stops the form being evaluated rather than the whole REPL
+    let interrupt = interpreter.interrupt_handle();
+    ctrlc::set_handler(move || interrupt.trigger()).expect("Could not handle Ctrl-C");
     while let Some(input) = console::Console::read_user_input() {
-        let lexer = Lexer::tokenize(&input);
-        let mut parser = Parser::new(lexer);
-        let tokens = parser.parse();
-
-        match tokens {
-            Ok(tokens) => {
-                rep(tokens, &mut env)?;
-            }
-            Err(err) => {
-                println!("Error: {:?}", err);
-            }
-        }
-
-        // println!("{}", input);
+        rep(&input, &interpreter);
     }
-
-    Ok(())
 }
 
-fn rep(tokens: Vec<MalType>, env: &mut Environment) -> Result<()> {
-    let token = tokens.first().unwrap();
-
-    match eval(token, env) {
+fn rep(input: &str, interpreter: &Interpreter) {
+    // Each form runs in whichever namespace the previous one left us in
+    let ret = interpreter.eval_str(input).and_then(|exp| {
+        lazy::realize_up_to(&exp, lazy::PRINT_LENGTH)
+            .map(|_| exp)
+            .map_err(ruste::Error::Eval)
+    });
+    match ret {
         Ok(exp) => {
             println!("{}", exp);
         }
         Err(err) => {
-            println!("Error: {:?}", err);
+            println!("Error: {}", err);
+            for frame in err.stack() {
+                println!("  {}", frame);
+            }
         }
     }
-
-    Ok(())
 }
@@ -1,39 +1,39 @@
-use anyhow::Result;
-use environment::Environment;
-use eval::eval;
-use reader::{Lexer, Parser};
-use types::MalType;
-mod console;
-mod core;
-mod environment;
-mod eval;
-mod expr;
-mod reader;
-mod types;
+use ruste::{console, lazy, profile, Interpreter};
 
-fn main() -> Result<()> {
-    let mut env = Environment::new();
-    mal_define_fn(&mut env)?;
+#[global_allocator]
+static ALLOCATOR: profile::CountingAllocator = profile::CountingAllocator;
+
+fn main() {
     setup();
+    // `--profile` reports the calls made over the whole session once it
+    // ends, and `--folded FILE` writes their stacks for flamegraph tools
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let folded = args
+        .iter()
+        .position(|arg| arg == "--folded")
+        .and_then(|i| args.get(i + 1));
+    let profiling = folded.is_some() || args.iter().any(|arg| arg == "--profile");
+    if profiling {
+        profile::start();
+    }
 
+    let interpreter = Interpreter::new();
+    // Ctrl-C stops the form being evaluated rather than the whole REPL
+    let interrupt = interpreter.interrupt_handle();
+    ctrlc::set_handler(move || interrupt.trigger()).expect("Could not handle Ctrl-C");
     while let Some(input) = console::Console::read_user_input() {
-        let lexer = Lexer::tokenize(&input);
-        let mut parser = Parser::new(lexer);
-        let tokens = parser.parse();
+        rep(&input, &interpreter);
+    }
 
-        match tokens {
-            Ok(tokens) => {
-                rep(tokens, &mut env)?;
-            }
-            Err(err) => {
-                println!("Error: {:?}", err);
+    if profiling {
+        let profile = profile::stop();
+        println!("{}", profile.report());
+        if let Some(file) = folded {
+            if let Err(err) = std::fs::write(file, profile.folded() + "\n") {
+                println!("Could not write {}: {}", file, err);
             }
         }
-
-        // println!("{}", input);
     }
-
-    Ok(())
 }
 
 #[cfg(debug_assertions)]
@@ -46,28 +46,22 @@ fn setup() {
 #[cfg(not(debug_assertions))]
 fn setup() {}
 
-fn mal_define_fn(env: &mut Environment) -> Result<()> {
-    let input = String::from("(def! not (fn* (a) (if a false true)))");
-
-    let lexer = Lexer::tokenize(&input);
-    let mut parser = Parser::new(lexer);
-    let tokens = parser.parse()?;
-
-    eval(tokens.first().unwrap(), env)?;
-    Ok(())
-}
-
-fn rep(tokens: Vec<MalType>, env: &mut Environment) -> Result<()> {
-    let token = tokens.first().unwrap();
-
-    match eval(token, env) {
+fn rep(input: &str, interpreter: &Interpreter) {
+    // Each form runs in whichever namespace the previous one left us in
+    let ret = interpreter.eval_str(input).and_then(|exp| {
+        lazy::realize_up_to(&exp, lazy::PRINT_LENGTH)
+            .map(|_| exp)
+            .map_err(ruste::Error::Eval)
+    });
+    match ret {
         Ok(exp) => {
             println!("{}", exp);
         }
         Err(err) => {
-            println!("Error: {:?}", err);
+            println!("Error: {}", err);
+            for frame in err.stack() {
+                println!("  {}", frame);
+            }
         }
     }
-
-    Ok(())
 }
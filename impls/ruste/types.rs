@@ -1,58 +1,354 @@
-use crate::environment::Environment;
+use crate::{
+    atom::Atom,
+    environment::Environment,
+    intern::{Sym, AMP},
+    lazy::{LazySeq, SeqIter},
+    multimethod::MultiFn,
+    protocol::Protocol,
+    record::Record,
+};
 use anyhow::Result;
 use log::debug;
 use std::{
+    cell::{OnceCell, RefCell},
+    cmp::Ordering,
     fmt::{Binary, Debug, Display},
-    ops::{Add, Div, Mul, Sub},
+    ops::{Add, Deref, Div, Mul, Sub},
     rc::Rc,
 };
 
+/// Metadata attached to a collection, symbol or function. It never takes part
+/// in equality.
+pub type Meta = Option<Rc<MalType>>;
+
 #[derive(Debug, Clone)]
 pub enum MalType {
-    List(Vec<MalType>),
-    HashMap(Vec<MalType>),
-    Vector(Vec<MalType>),
+    List(Vec<MalType>, Meta),
+    HashMap(Vec<MalType>, Meta),
+    Vector(Vec<MalType>, Meta),
+    Set(Vec<MalType>, Meta),
+    Lazy(LazySeq),
     String(String),
-    Symbol(String),
+    Symbol(Sym, Meta),
+    Keyword(Sym),
     Number(i64),
+    Float(f64),
     Bool(bool),
     Nil,
+    Regex(MalRegex),
+    Tagged(String, Box<MalType>),
     Bind(MalExpr),
     BinOp(MalExpr),
     Fn(MalFn),
     LibFn(MalLibFn),
+    Protocol(Protocol),
+    Record(Record),
+    Multi(MultiFn),
+    Atom(Atom),
 }
 
 impl MalType {
+    /// Everything except nil and false counts as true.
+    pub fn truthy(&self) -> bool {
+        !matches!(self, MalType::Nil | MalType::Bool(false))
+    }
+
+    /// Whether this is the symbol `sym`, compared by id.
+    pub fn is_symbol(&self, sym: Sym) -> bool {
+        matches!(self, MalType::Symbol(name, _) if *name == sym)
+    }
+
     pub fn eval(self, val: &[MalType], env: &Environment) -> Result<MalType> {
+        let _profiled = crate::profile::enter(&self);
+        match crate::debug::enabled() {
+            true => crate::debug::call(self, val, env),
+            false => self.invoke(val, env),
+        }
+    }
+
+    /// Calls this value with `val`, without tracing or stopping in the debugger.
+    pub fn invoke(self, val: &[MalType], env: &Environment) -> Result<MalType> {
         match self {
             MalType::Fn(expr) => expr.eval(val, env),
             MalType::LibFn(expr) => expr.eval(val, env),
             MalType::BinOp(expr) => expr.eval(val, env),
-            MalType::Symbol(symbol) => env
-                .get(&symbol)
+            MalType::Multi(multi) => multi.eval(val, env),
+            // Keywords look themselves up, as in `(:name person)`
+            MalType::Keyword(_) => match val {
+                [coll] => Ok(lookup(coll, &self).unwrap_or(MalType::Nil)),
+                [coll, default] => Ok(lookup(coll, &self).unwrap_or_else(|| default.clone())),
+                _ => anyhow::bail!("{} expects a map and an optional default", self),
+            },
+            MalType::Symbol(symbol, _) => env
+                .get(symbol)
                 .ok_or(anyhow::anyhow!("MalType::eval: Expected to find symbol")),
             other => Ok(other),
         }
     }
+
+    /// The metadata attached to a collection, symbol or function.
+    pub fn meta(&self) -> Option<&MalType> {
+        match self {
+            MalType::List(_, meta)
+            | MalType::HashMap(_, meta)
+            | MalType::Vector(_, meta)
+            | MalType::Set(_, meta)
+            | MalType::Symbol(_, meta) => meta.as_deref(),
+            MalType::Fn(f) => f.meta.as_deref(),
+            MalType::LibFn(f) => f.expr.meta.as_deref(),
+            MalType::Bind(expr) | MalType::BinOp(expr) => expr.meta.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The same value with its metadata replaced by `meta`.
+    pub fn with_meta(self, meta: Meta) -> Result<MalType> {
+        let value = match self {
+            MalType::List(items, _) => MalType::List(items, meta),
+            MalType::HashMap(items, _) => MalType::HashMap(items, meta),
+            MalType::Vector(items, _) => MalType::Vector(items, meta),
+            MalType::Set(items, _) => MalType::Set(items, meta),
+            MalType::Symbol(symbol, _) => MalType::Symbol(symbol, meta),
+            MalType::Fn(mut f) => {
+                f.meta = meta;
+                MalType::Fn(f)
+            }
+            MalType::LibFn(mut f) => {
+                f.expr.meta = meta;
+                MalType::LibFn(f)
+            }
+            MalType::Bind(mut expr) => {
+                expr.meta = meta;
+                MalType::Bind(expr)
+            }
+            MalType::BinOp(mut expr) => {
+                expr.meta = meta;
+                MalType::BinOp(expr)
+            }
+            other => anyhow::bail!("Cannot attach metadata to {:b}", other),
+        };
+        Ok(value)
+    }
+
+    /// A function implemented in Rust, as opposed to one made with `fn*`.
+    pub fn native(
+        symbol: &str,
+        f: impl Fn(&[MalType], Environment) -> Result<MalType> + 'static,
+    ) -> MalType {
+        MalType::LibFn(MalLibFn {
+            expr: Box::new(MalExpr {
+                symbol: symbol.to_string(),
+                arguments: 0,
+                inner: Rc::new(f),
+                meta: None,
+            }),
+            captured_env: Box::new(MalType::List(vec![], None)),
+        })
+    }
+}
+
+/// What `get` finds for `key` in a map, record, set or vector.
+pub fn lookup(coll: &MalType, key: &MalType) -> Option<MalType> {
+    match (coll, key) {
+        (MalType::HashMap(map, _), key) => hash_map_get(map, key).cloned(),
+        (MalType::Record(record), key) => record.get(key).cloned(),
+        (MalType::Set(items, _), key) => items.contains(key).then(|| key.clone()),
+        (MalType::Vector(items, _), MalType::Number(i)) if *i >= 0 => {
+            items.get(*i as usize).cloned()
+        }
+        _ => None,
+    }
+}
+
+/// Looks up `key` in the flat key/value list backing `MalType::HashMap`.
+pub fn hash_map_get<'a>(map: &'a [MalType], key: &MalType) -> Option<&'a MalType> {
+    map.chunks(2)
+        .find(|pair| &pair[0] == key)
+        .and_then(|pair| pair.get(1))
+}
+
+/// Sets `key` in the flat key/value list, replacing an existing entry in place.
+pub fn hash_map_insert(map: &mut Vec<MalType>, key: MalType, value: MalType) {
+    match map.chunks(2).position(|pair| pair[0] == key) {
+        Some(i) => map[i * 2 + 1] = value,
+        None => {
+            map.push(key);
+            map.push(value);
+        }
+    }
 }
 
+thread_local! {
+    /// Arguments of the pending `recur`, picked up by the `loop*` or fn it targets.
+    static RECUR_ARGS: RefCell<Vec<MalType>> = const { RefCell::new(vec![]) };
+}
+
+/// Raised by `recur` and caught by the enclosing `loop*` or fn, which rebinds
+/// and runs its body again instead of growing the stack. One that escapes
+/// surfaces as a regular error.
+#[derive(Debug)]
+pub struct Recur;
+
+impl Display for Recur {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Can only recur from tail position of a fn* or loop*")
+    }
+}
+
+impl std::error::Error for Recur {}
+
+impl Recur {
+    pub fn raise(args: Vec<MalType>) -> anyhow::Error {
+        RECUR_ARGS.with(|recur| *recur.borrow_mut() = args);
+        anyhow::Error::new(Recur)
+    }
+}
+
+/// The outcome of a body that may end in `recur`.
+pub enum Tail {
+    Return(MalType),
+    Recur(Vec<MalType>),
+}
+
+impl Tail {
+    pub fn from(ret: Result<MalType>) -> Result<Tail> {
+        match ret {
+            Err(err) if err.is::<Recur>() => Ok(Tail::Recur(RECUR_ARGS.with(|recur| recur.take()))),
+            ret => ret.map(Tail::Return),
+        }
+    }
+}
+
+/// A user function: one or more arities sharing the environment the `fn*`
+/// was evaluated in. A named fn can refer to itself through its name.
 #[derive(Clone, Debug)]
 pub struct MalFn {
-    pub expr: Box<MalType>,
-    pub captured_args: Box<MalType>,
+    pub name: Option<String>,
+    pub arities: Rc<Vec<MalArity>>,
     pub captured_env: Environment,
+    pub meta: Meta,
+    /// The form that defined the fn, as `source` prints it.
+    pub source: Option<Rc<MalType>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MalArity {
+    pub params: MalType,
+    pub body: Vec<MalType>,
+}
+
+impl MalArity {
+    /// Number of fixed parameters, and whether `& rest` accepts any more.
+    pub fn arity(&self) -> (usize, bool) {
+        match &self.params {
+            MalType::List(params, _) | MalType::Vector(params, _) => {
+                match params.iter().position(|param| param.is_symbol(*AMP)) {
+                    Some(required) => (required, true),
+                    None => (params.len(), false),
+                }
+            }
+            _ => (0, true),
+        }
+    }
+
+    /// `recur` passes the rest param as a single sequence, which is spread
+    /// back out so the params bind the same way as for a regular call.
+    fn recur_values(&self, mut args: Vec<MalType>) -> Result<Vec<MalType>> {
+        let (required, variadic) = self.arity();
+        let expected = required + variadic as usize;
+        if args.len() != expected {
+            anyhow::bail!(
+                "Mismatched argument count to recur, expected {} args, got {}",
+                expected,
+                args.len()
+            )
+        }
+        if variadic {
+            let rest = args.pop().unwrap();
+            for item in SeqIter::new(&rest)? {
+                args.push(item?);
+            }
+        }
+        Ok(args)
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        match self.arity() {
+            (required, true) => count >= required,
+            (required, false) => count == required,
+        }
+    }
 }
 
 impl MalFn {
+    /// Copies of a fn share its arities, and metadata doesn't change it.
+    pub fn ptr_eq(&self, other: &MalFn) -> bool {
+        Rc::ptr_eq(&self.arities, &other.arities)
+    }
+
     pub fn eval(&self, val: &[MalType], _: &Environment) -> Result<MalType> {
         debug!("MalFn::eval: self: {:?} -- values {:?}", self, val);
-        let mut env = Environment::from(
-            self.captured_env.clone(),
-            self.captured_args.as_ref().clone(),
-            val,
-        );
-        crate::eval::eval(&self.expr, &mut env)
+        let arity = self.select(val.len())?;
+
+        let mut outer = self.captured_env.clone();
+        if let Some(name) = &self.name {
+            outer.enter();
+            outer.set(
+                MalType::Symbol(name.as_str().into(), None),
+                MalType::Fn(self.clone()),
+            );
+        }
+
+        let mut values = val.to_vec();
+        loop {
+            let mut env = Environment::from(outer.clone(), arity.params.clone(), &values)?;
+            let ret = arity
+                .body
+                .iter()
+                .try_fold(MalType::Nil, |_, form| crate::eval::eval(form, &mut env));
+            match Tail::from(ret)? {
+                Tail::Return(ret) => return Ok(ret),
+                Tail::Recur(args) => values = arity.recur_values(args)?,
+            }
+        }
+    }
+
+    /// Picks the arity for `count` arguments, preferring an exact match over
+    /// the variadic one.
+    fn select(&self, count: usize) -> Result<&MalArity> {
+        let fixed = self
+            .arities
+            .iter()
+            .find(|arity| arity.accepts(count) && !arity.arity().1);
+        let variadic = || self.arities.iter().find(|arity| arity.accepts(count));
+        if let Some(arity) = fixed.or_else(variadic) {
+            return Ok(arity);
+        }
+
+        let expected: Vec<String> = self
+            .arities
+            .iter()
+            .map(|arity| match arity.arity() {
+                (required, true) => format!("at least {}", required),
+                (required, false) => required.to_string(),
+            })
+            .collect();
+        anyhow::bail!(
+            "Wrong number of args ({}) passed to {} expecting {}",
+            count,
+            self.name.as_deref().unwrap_or("fn"),
+            expected.join(" or ")
+        )
+    }
+}
+
+impl Display for MalArity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}", self.params)?;
+        for form in &self.body {
+            write!(f, " {:b}", form)?;
+        }
+        write!(f, ")")
     }
 }
 
@@ -65,16 +361,66 @@ pub struct MalLibFn {
 impl MalLibFn {
     pub fn eval(&self, val: &[MalType], env: &Environment) -> Result<MalType> {
         debug!("MalLibFn::eval: self: {:?} -- values {:?}", self, val);
-        let env = Environment::from(env.clone(), self.captured_env.as_ref().clone(), val);
+        let env = Environment::from(env.clone(), self.captured_env.as_ref().clone(), val)?;
         self.expr.eval(val, &env)
     }
 }
 
+/// A compiled regex. The anchored form `re-matches` needs is compiled the
+/// first time it's asked for, and shared by every copy of the value.
+#[derive(Clone)]
+pub struct MalRegex {
+    regex: regex::Regex,
+    anchored: Rc<OnceCell<regex::Regex>>,
+}
+
+impl MalRegex {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        regex::Regex::new(pattern).map(Self::from)
+    }
+
+    /// The pattern, matching only the whole of the input, so alternations
+    /// can't settle for a prefix.
+    pub fn anchored(&self) -> Result<&regex::Regex, regex::Error> {
+        if let Some(anchored) = self.anchored.get() {
+            return Ok(anchored);
+        }
+        let anchored = regex::Regex::new(&format!(r"\A(?:{})\z", self.regex.as_str()))?;
+        Ok(self.anchored.get_or_init(|| anchored))
+    }
+}
+
+impl From<regex::Regex> for MalRegex {
+    fn from(regex: regex::Regex) -> Self {
+        Self {
+            regex,
+            anchored: Rc::default(),
+        }
+    }
+}
+
+impl Debug for MalRegex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.regex, f)
+    }
+}
+
+impl Deref for MalRegex {
+    type Target = regex::Regex;
+
+    fn deref(&self) -> &regex::Regex {
+        &self.regex
+    }
+}
+
+pub type NativeFn = Rc<dyn Fn(&[MalType], Environment) -> Result<MalType> + 'static>;
+
 #[derive(Clone)]
 pub struct MalExpr {
     pub symbol: String,
     pub arguments: usize,
-    pub inner: Rc<dyn Fn(&[MalType], Environment) -> Result<MalType> + 'static>,
+    pub inner: NativeFn,
+    pub meta: Meta,
 }
 
 impl MalExpr {
@@ -82,6 +428,10 @@ impl MalExpr {
         debug!("MalExpr::eval: self: {:?} -- values {:?}", self, val);
         (self.inner)(val, env.clone())
     }
+
+    pub fn ptr_eq(&self, other: &MalExpr) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
 }
 
 impl Debug for MalExpr {
@@ -96,23 +446,54 @@ impl Debug for MalExpr {
 impl Display for MalType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            MalType::List(inner) => print_collection(MalCollection::List, inner, f),
-            MalType::HashMap(inner) => print_collection(MalCollection::HashMap, inner, f),
-            MalType::Vector(inner) => print_collection(MalCollection::Vector, inner, f),
+            MalType::List(inner, _) => print_collection(MalCollection::List, inner, f),
+            MalType::HashMap(inner, _) => print_collection(MalCollection::HashMap, inner, f),
+            MalType::Vector(inner, _) => print_collection(MalCollection::Vector, inner, f),
+            MalType::Set(inner, _) => print_collection(MalCollection::Set, inner, f),
+            MalType::Lazy(seq) => {
+                let (mut inner, complete) = seq.cached();
+                if !complete {
+                    inner.push(MalType::Symbol("...".into(), None));
+                }
+                print_collection(MalCollection::List, &inner, f)
+            }
             MalType::String(str) => write!(f, "{}", str),
-            MalType::Symbol(symbol) => write!(f, "{}", symbol),
+            MalType::Symbol(symbol, _) => write!(f, "{}", symbol),
+            MalType::Keyword(keyword) => write!(f, ":{}", keyword),
             MalType::Number(nr) => write!(f, "{}", nr),
+            MalType::Float(nr) if nr.is_nan() => write!(f, "NaN"),
+            MalType::Float(nr) if nr.is_infinite() && *nr > 0.0 => write!(f, "Infinity"),
+            MalType::Float(nr) if nr.is_infinite() => write!(f, "-Infinity"),
+            MalType::Float(nr) => write!(f, "{:?}", nr),
             MalType::Bool(b) => match b {
                 true => write!(f, "true"),
                 false => write!(f, "false"),
             },
             MalType::Nil => write!(f, "nil"),
+            MalType::Regex(re) => write!(f, "#\"{}\"", re.as_str()),
+            MalType::Tagged(tag, value) => write!(f, "#{} {:b}", tag, value.as_ref()),
             MalType::LibFn(expr) => {
                 write!(f, "LibFn: {} [{}]", expr.expr.symbol, expr.captured_env)
             }
-            MalType::Fn(expr) => write!(f, "Fn: {} [{}]", expr.expr, expr.captured_args),
+            MalType::Fn(expr) => {
+                write!(f, "Fn:")?;
+                if let Some(name) = &expr.name {
+                    write!(f, " {}", name)?;
+                }
+                for arity in expr.arities.iter() {
+                    write!(f, " {}", arity)?;
+                }
+                Ok(())
+            }
             MalType::Bind(expr) => write!(f, "Bind: {} [{}]", expr.symbol, expr.arguments),
             MalType::BinOp(expr) => write!(f, "BinOp: {} [{}]", expr.symbol, expr.arguments),
+            MalType::Protocol(protocol) => write!(f, "Protocol: {}", protocol.name()),
+            MalType::Multi(multi) => write!(f, "MultiFn: {}", multi.name()),
+            MalType::Atom(atom) => write!(f, "(atom {})", atom.deref()),
+            MalType::Record(record) => {
+                write!(f, "#{}", record.name())?;
+                print_collection(MalCollection::HashMap, record.entries(), f)
+            }
         }
     }
 }
@@ -120,10 +501,27 @@ impl Display for MalType {
 impl Binary for MalType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            MalType::List(inner) => print_collection_b(MalCollection::List, inner, f),
-            MalType::HashMap(inner) => print_collection_b(MalCollection::HashMap, inner, f),
-            MalType::Vector(inner) => print_collection_b(MalCollection::Vector, inner, f),
+            MalType::List(inner, _) => print_collection_b(MalCollection::List, inner, f),
+            MalType::HashMap(inner, _) => print_collection_b(MalCollection::HashMap, inner, f),
+            MalType::Vector(inner, _) => print_collection_b(MalCollection::Vector, inner, f),
+            MalType::Set(inner, _) => print_collection_b(MalCollection::Set, inner, f),
+            MalType::Lazy(seq) => {
+                let (mut inner, complete) = seq.cached();
+                if !complete {
+                    inner.push(MalType::Symbol("...".into(), None));
+                }
+                print_collection_b(MalCollection::List, &inner, f)
+            }
             MalType::String(str) => write!(f, "\"{}\"", escape_str(str)),
+            // As EDN spells them, so the reader reads them back
+            MalType::Float(nr) if nr.is_nan() => write!(f, "##NaN"),
+            MalType::Float(nr) if nr.is_infinite() && *nr > 0.0 => write!(f, "##Inf"),
+            MalType::Float(nr) if nr.is_infinite() => write!(f, "##-Inf"),
+            MalType::Record(record) => {
+                write!(f, "#{}", record.name())?;
+                print_collection_b(MalCollection::HashMap, record.entries(), f)
+            }
+            MalType::Atom(atom) => write!(f, "(atom {:b})", atom.deref()),
             other => write!(f, "{}", other),
         }
     }
@@ -147,12 +545,12 @@ fn print_collection(
     f: &mut std::fmt::Formatter<'_>,
 ) -> std::fmt::Result {
     write!(f, "{}", collection_type.start())?;
-    for i in 0..inner.len() {
+    for (i, item) in inner.iter().enumerate() {
         if i > 0 {
             write!(f, " ")?;
         }
 
-        write!(f, "{}", inner[i])?;
+        write!(f, "{}", item)?;
     }
     write!(f, "{}", collection_type.end())
 }
@@ -163,12 +561,12 @@ fn print_collection_b(
     f: &mut std::fmt::Formatter<'_>,
 ) -> std::fmt::Result {
     write!(f, "{}", collection_type.start())?;
-    for i in 0..inner.len() {
+    for (i, item) in inner.iter().enumerate() {
         if i > 0 {
             write!(f, " ")?;
         }
 
-        write!(f, "{:b}", inner[i])?;
+        write!(f, "{:b}", item)?;
     }
     write!(f, "{}", collection_type.end())
 }
@@ -177,6 +575,7 @@ pub enum MalCollection {
     HashMap,
     List,
     Vector,
+    Set,
 }
 
 impl MalCollection {
@@ -185,6 +584,7 @@ impl MalCollection {
             "{" => MalCollection::HashMap,
             "(" => MalCollection::List,
             "[" => MalCollection::Vector,
+            "#{" => MalCollection::Set,
             _ => unreachable!(),
         }
     }
@@ -194,6 +594,7 @@ impl MalCollection {
             MalCollection::HashMap => "{",
             MalCollection::List => "(",
             MalCollection::Vector => "[",
+            MalCollection::Set => "#{",
         }
     }
 
@@ -202,92 +603,204 @@ impl MalCollection {
             MalCollection::HashMap => "}",
             MalCollection::List => ")",
             MalCollection::Vector => "]",
+            MalCollection::Set => "}",
         }
     }
 
     pub fn into(self, data: Vec<MalType>) -> MalType {
         match self {
-            MalCollection::HashMap => MalType::HashMap(data),
-            MalCollection::List => MalType::List(data),
-            MalCollection::Vector => MalType::Vector(data),
+            MalCollection::HashMap => MalType::HashMap(data, None),
+            MalCollection::List => MalType::List(data, None),
+            MalCollection::Vector => MalType::Vector(data, None),
+            MalCollection::Set => MalType::Set(data, None),
         }
     }
 }
 
 impl Add for &MalType {
-    type Output = MalType;
+    type Output = Result<MalType>;
 
     fn add(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (MalType::Number(n1), MalType::Number(n2)) => MalType::Number(n1 + n2),
-            _ => panic!("ADD: {:?} ---- {:?}", self, rhs),
+            (MalType::Number(n1), MalType::Number(n2)) => match n1.checked_add(*n2) {
+                Some(n) => Ok(MalType::Number(n)),
+                None => anyhow::bail!("Integer overflow in +"),
+            },
+            (MalType::Float(n1), MalType::Float(n2)) => Ok(MalType::Float(n1 + n2)),
+            (MalType::Number(n1), MalType::Float(n2)) => Ok(MalType::Float(*n1 as f64 + n2)),
+            (MalType::Float(n1), MalType::Number(n2)) => Ok(MalType::Float(n1 + *n2 as f64)),
+            _ => Err(not_numbers("+", self, rhs)),
         }
     }
 }
 
 impl Sub for &MalType {
-    type Output = MalType;
+    type Output = Result<MalType>;
 
     fn sub(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (MalType::Number(n1), MalType::Number(n2)) => MalType::Number(n1 - n2),
-            _ => panic!("SUB: {:?} ---- {:?}", self, rhs),
+            (MalType::Number(n1), MalType::Number(n2)) => match n1.checked_sub(*n2) {
+                Some(n) => Ok(MalType::Number(n)),
+                None => anyhow::bail!("Integer overflow in -"),
+            },
+            (MalType::Float(n1), MalType::Float(n2)) => Ok(MalType::Float(n1 - n2)),
+            (MalType::Number(n1), MalType::Float(n2)) => Ok(MalType::Float(*n1 as f64 - n2)),
+            (MalType::Float(n1), MalType::Number(n2)) => Ok(MalType::Float(n1 - *n2 as f64)),
+            _ => Err(not_numbers("-", self, rhs)),
         }
     }
 }
 
 impl Mul for &MalType {
-    type Output = MalType;
+    type Output = Result<MalType>;
 
     fn mul(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (MalType::Number(n1), MalType::Number(n2)) => MalType::Number(n1 * n2),
-            _ => panic!("MUL: {:?} ---- {:?}", self, rhs),
+            (MalType::Number(n1), MalType::Number(n2)) => match n1.checked_mul(*n2) {
+                Some(n) => Ok(MalType::Number(n)),
+                None => anyhow::bail!("Integer overflow in *"),
+            },
+            (MalType::Float(n1), MalType::Float(n2)) => Ok(MalType::Float(n1 * n2)),
+            (MalType::Number(n1), MalType::Float(n2)) => Ok(MalType::Float(*n1 as f64 * n2)),
+            (MalType::Float(n1), MalType::Number(n2)) => Ok(MalType::Float(n1 * *n2 as f64)),
+            _ => Err(not_numbers("*", self, rhs)),
         }
     }
 }
 
 impl Div for &MalType {
-    type Output = MalType;
+    type Output = Result<MalType>;
 
     fn div(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (MalType::Number(n1), MalType::Number(n2)) => MalType::Number(n1 / n2),
-            _ => panic!("DIV: {:?} ---- {:?}", self, rhs),
+            (MalType::Number(_), MalType::Number(0)) => anyhow::bail!("Divide by zero"),
+            (MalType::Number(n1), MalType::Number(n2)) => match n1.checked_div(*n2) {
+                Some(n) => Ok(MalType::Number(n)),
+                None => anyhow::bail!("Integer overflow in /"),
+            },
+            (MalType::Float(n1), MalType::Float(n2)) => Ok(MalType::Float(n1 / n2)),
+            (MalType::Number(n1), MalType::Float(n2)) => Ok(MalType::Float(*n1 as f64 / n2)),
+            (MalType::Float(n1), MalType::Number(n2)) => Ok(MalType::Float(n1 / *n2 as f64)),
+            _ => Err(not_numbers("/", self, rhs)),
         }
     }
 }
 
+/// The error for `op` given something other than two numbers, naming the
+/// first argument that isn't one.
+fn not_numbers(op: &str, lhs: &MalType, rhs: &MalType) -> anyhow::Error {
+    let (position, arg) = match lhs {
+        MalType::Number(_) | MalType::Float(_) => (2, rhs),
+        _ => (1, lhs),
+    };
+    crate::native::arg_error(op, position, crate::native::mismatch("a number", arg))
+}
+
 impl PartialEq for MalType {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (Self::List(l0), Self::List(r0)) => l0 == r0,
-            (Self::HashMap(l0), Self::HashMap(r0)) => l0 == r0,
-            (Self::Vector(l0), Self::Vector(r0)) => l0 == r0,
+            // Lists, vectors and lazy sequences with the same items are equal
+            (Self::List(l0, _) | Self::Vector(l0, _), Self::List(r0, _) | Self::Vector(r0, _)) => {
+                l0 == r0
+            }
+            (Self::HashMap(l0, _), Self::HashMap(r0, _)) => l0 == r0,
+            (Self::Set(l0, _), Self::Set(r0, _)) => {
+                l0.len() == r0.len() && l0.iter().all(|item| r0.contains(item))
+            }
             (Self::String(l0), Self::String(r0)) => l0 == r0,
-            (Self::Symbol(l0), Self::Symbol(r0)) => l0 == r0,
+            (Self::Symbol(l0, _), Self::Symbol(r0, _)) => l0 == r0,
+            (Self::Keyword(l0), Self::Keyword(r0)) => l0 == r0,
             (Self::Number(l0), Self::Number(r0)) => l0 == r0,
+            (Self::Float(l0), Self::Float(r0)) => l0 == r0,
             (Self::Bool(l0), Self::Bool(r0)) => l0 == r0,
-            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
+            (Self::Regex(l0), Self::Regex(r0)) => l0.as_str() == r0.as_str(),
+            (Self::Tagged(l0, l1), Self::Tagged(r0, r1)) => l0 == r0 && l1 == r1,
+            (Self::Protocol(l0), Self::Protocol(r0)) => l0.ptr_eq(r0),
+            (Self::Record(l0), Self::Record(r0)) => l0 == r0,
+            (Self::Multi(l0), Self::Multi(r0)) => l0.ptr_eq(r0),
+            (Self::Atom(l0), Self::Atom(r0)) => l0.ptr_eq(r0),
+            // Functions are only equal to themselves
+            (Self::Fn(l0), Self::Fn(r0)) => l0.ptr_eq(r0),
+            (Self::LibFn(l0), Self::LibFn(r0)) => l0.expr.ptr_eq(&r0.expr),
+            (Self::Bind(l0), Self::Bind(r0)) | (Self::BinOp(l0), Self::BinOp(r0)) => l0.ptr_eq(r0),
+            (Self::Lazy(_), Self::Lazy(_) | Self::List(..) | Self::Vector(..))
+            | (Self::List(..) | Self::Vector(..), Self::Lazy(_)) => seq_eq(self, other),
+            (Self::Nil, Self::Nil) => true,
+            _ => false,
+        }
+    }
+}
+
+impl MalType {
+    /// Equality as `=` sees it. It is `==`, except that a lazy sequence
+    /// failing to realize is reported instead of comparing unequal.
+    pub fn equals(&self, other: &MalType) -> Result<bool> {
+        match (self, other) {
+            (Self::List(l0, _) | Self::Vector(l0, _), Self::List(r0, _) | Self::Vector(r0, _))
+            | (Self::HashMap(l0, _), Self::HashMap(r0, _)) => {
+                if l0.len() != r0.len() {
+                    return Ok(false);
+                }
+                for (l, r) in l0.iter().zip(r0) {
+                    if !l.equals(r)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            (Self::Lazy(_), Self::Lazy(_) | Self::List(..) | Self::Vector(..))
+            | (Self::List(..) | Self::Vector(..), Self::Lazy(_)) => {
+                let mut right = SeqIter::new(other)?;
+                for l in SeqIter::new(self)? {
+                    let l = l?;
+                    match right.next().transpose()? {
+                        Some(r) if l.equals(&r)? => (),
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(right.next().transpose()?.is_none())
+            }
+            (Self::Tagged(l0, l1), Self::Tagged(r0, r1)) => Ok(l0 == r0 && l1.equals(r1)?),
+            _ => Ok(self == other),
+        }
+    }
+}
+
+/// Compares two sequences item by item. A lazy sequence that fails to
+/// realize equals nothing here, `MalType::equals` reports it instead.
+fn seq_eq(left: &MalType, right: &MalType) -> bool {
+    let (Ok(left), Ok(right)) = (SeqIter::new(left), SeqIter::new(right)) else {
+        return false;
+    };
+    let mut right = right;
+    for l in left {
+        match (l, right.next()) {
+            (Ok(l), Some(Ok(r))) if l == r => (),
+            _ => return false,
         }
     }
+    right.next().is_none()
 }
 
 impl PartialEq<&str> for MalType {
     fn eq(&self, other: &&str) -> bool {
         match self {
             Self::String(l0) => l0 == other,
-            Self::Symbol(l0) => l0 == other,
+            Self::Symbol(l0, _) => l0 == other,
             _ => false,
         }
     }
 }
 
-impl PartialOrd for &MalType {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+impl MalType {
+    /// Orders two numbers for `op`, with `None` when either is NaN.
+    pub fn num_cmp(&self, other: &MalType, op: &str) -> Result<Option<Ordering>> {
         match (self, other) {
-            (MalType::Number(n1), MalType::Number(n2)) => n1.partial_cmp(n2),
-            _ => panic!("ORD: {:?} ---- {:?}", self, other),
+            (MalType::Number(n1), MalType::Number(n2)) => Ok(n1.partial_cmp(n2)),
+            (MalType::Float(n1), MalType::Float(n2)) => Ok(n1.partial_cmp(n2)),
+            (MalType::Number(n1), MalType::Float(n2)) => Ok((*n1 as f64).partial_cmp(n2)),
+            (MalType::Float(n1), MalType::Number(n2)) => Ok(n1.partial_cmp(&(*n2 as f64))),
+            _ => Err(not_numbers(op, self, other)),
         }
     }
 }